@@ -1,6 +1,12 @@
 // TODO: remove this when you're done with your implementation.
 #![allow(unused_variables, dead_code)]
 
+pub mod batch;
+mod cli;
+pub mod generator;
+pub mod network;
+pub mod rng;
+
 pub fn luhn(cc_number: &str) -> bool {
     let nums = cc_number
         .split_whitespace()
@@ -24,7 +30,9 @@ pub fn luhn(cc_number: &str) -> bool {
         );
 
     match nums {
-        Some(nums) if nums.len() > 0 => {
+        // A single digit is never a card number, even one whose digit sum
+        // is a multiple of ten, like "0".
+        Some(nums) if nums.len() > 1 => {
             nums.into_iter()
                 .enumerate()
                 .map(|(i, num)| {
@@ -48,6 +56,33 @@ pub fn luhn(cc_number: &str) -> bool {
     }
 }
 
+/// Compute the digit that makes `partial` pass [`luhn`] once appended to it.
+///
+/// Whitespace is ignored, like in [`luhn`]. Returns `None` if `partial`
+/// contains no digits or anything other than digits and whitespace.
+pub fn check_digit(partial: &str) -> Option<u32> {
+    let mut sum = 0;
+    let mut count = 0;
+    for c in partial.chars().rev().filter(|c| !c.is_whitespace()) {
+        let num = c.to_digit(10)?;
+        // The check digit takes the rightmost position, so doubling starts
+        // at the last digit of `partial`.
+        sum += if count % 2 == 0 {
+            let doubled = num * 2;
+            doubled % 10 + doubled / 10
+        } else {
+            num
+        };
+        count += 1;
+    }
+
+    if count == 0 {
+        return None;
+    }
+
+    Some((10 - sum % 10) % 10)
+}
+
 #[test]
 fn test_non_digit_cc_number() {
     assert!(!luhn("foo"));
@@ -86,5 +121,36 @@ fn test_invalid_cc_number() {
     assert!(!luhn("8273 1232 7352 0569"));
 }
 
+#[test]
+fn test_check_digit() {
+    assert_eq!(check_digit("4263 9826 4026 929"), Some(9));
+    assert_eq!(check_digit("7992 7398 71"), Some(3));
+    assert_eq!(check_digit("0"), Some(0));
+    assert_eq!(check_digit(""), None);
+    assert_eq!(check_digit("12a4"), None);
+}
+
+#[test]
+fn test_check_digit_passes_luhn() {
+    for partial in [
+        "4539 3195 0343 646",
+        "5",
+        "37828224631000",
+        "601111111111111",
+    ] {
+        let digit = check_digit(partial).unwrap();
+        assert!(luhn(&format!("{partial}{digit}")), "{partial}{digit}");
+    }
+}
+
 #[allow(dead_code)]
-pub fn main() {}
+pub fn main() {
+    let stdin = std::io::stdin();
+    let code = cli::run(
+        std::env::args().skip(1),
+        &mut stdin.lock(),
+        &mut std::io::stdout(),
+        &mut std::io::stderr(),
+    );
+    std::process::exit(code);
+}
@@ -0,0 +1,187 @@
+/// Number of card numbers validated side by side. The inner loops work on
+/// arrays of this size, which the compiler turns into vector instructions.
+const LANES: usize = 8;
+
+const PAN_LEN: usize = 16;
+
+/// Build a table mapping an ASCII byte to its Luhn contribution.
+///
+/// Digits map to their value, or to the digit sum of twice their value
+/// when `doubled`. Any other byte maps to `INVALID`, which is large enough
+/// that a single one pushes the lane total past `MAX_VALID_SUM`.
+const fn lut(doubled: bool) -> [u16; 256] {
+    let mut table = [INVALID; 256];
+    let mut d = 0;
+    while d < 10 {
+        let value = if doubled { d * 2 } else { d };
+        table[b'0' as usize + d as usize] = value % 10 + value / 10;
+        d += 1;
+    }
+    table
+}
+
+const INVALID: u16 = 0x100;
+const MAX_VALID_SUM: u16 = 9 * PAN_LEN as u16;
+const PLAIN: [u16; 256] = lut(false);
+const DOUBLED: [u16; 256] = lut(true);
+
+/// Sum the Luhn contributions of `LANES` card numbers at once.
+fn lane_sums(pans: &[[u8; PAN_LEN]; LANES]) -> [u16; LANES] {
+    let mut sums = [0u16; LANES];
+    for pos in 0..PAN_LEN {
+        // Counting from the right, every second digit is doubled, which for
+        // a 16-digit number means every even position from the left.
+        let table = if pos % 2 == 0 { &DOUBLED } else { &PLAIN };
+        for lane in 0..LANES {
+            sums[lane] += table[pans[lane][pos] as usize];
+        }
+    }
+    sums
+}
+
+/// Validate fixed-length 16-digit card numbers given as ASCII bytes.
+///
+/// Bit `i % 64` of word `i / 64` in the result is set when `pans[i]`
+/// passes [`luhn`](super::luhn). The few entries that are not 16 bare
+/// digits, such as ones with spaces, which `luhn` skips, are checked by
+/// `luhn` itself, so the results are always the same.
+pub fn validate_batch(pans: &[[u8; PAN_LEN]]) -> Vec<u64> {
+    let mut bitmap = vec![0u64; pans.len().div_ceil(64)];
+    let mut set = |i: usize, sum: u16| {
+        let valid = if sum <= MAX_VALID_SUM {
+            sum.is_multiple_of(10)
+        } else {
+            std::str::from_utf8(&pans[i]).is_ok_and(super::luhn)
+        };
+        if valid {
+            bitmap[i / 64] |= 1 << (i % 64);
+        }
+    };
+
+    let mut chunks = pans.chunks_exact(LANES);
+    for (chunk_idx, chunk) in chunks.by_ref().enumerate() {
+        let chunk: &[[u8; PAN_LEN]; LANES] = chunk.try_into().unwrap();
+        for (lane, sum) in lane_sums(chunk).into_iter().enumerate() {
+            set(chunk_idx * LANES + lane, sum);
+        }
+    }
+
+    let done = pans.len() - chunks.remainder().len();
+    let mut tail = [[b'0'; PAN_LEN]; LANES];
+    tail[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    for (lane, sum) in lane_sums(&tail)
+        .into_iter()
+        .take(pans.len() - done)
+        .enumerate()
+    {
+        set(done + lane, sum);
+    }
+
+    bitmap
+}
+
+/// Whether bit `i` of a bitmap returned by [`validate_batch`] is set.
+pub fn is_valid(bitmap: &[u64], i: usize) -> bool {
+    bitmap[i / 64] & (1 << (i % 64)) != 0
+}
+
+#[cfg(test)]
+fn random_pans(count: usize, seed: u64) -> Vec<[u8; PAN_LEN]> {
+    use super::generator::Generator;
+    use super::network::Network;
+    use super::rng::Rng;
+
+    let mut rng = Rng::new(seed);
+    let mut valid = Generator::new(Network::Visa, seed);
+    (0..count)
+        .map(|_| {
+            let mut pan = [0; PAN_LEN];
+            match rng.below(4) {
+                // Valid numbers, which random digits rarely are.
+                0 => pan.copy_from_slice(valid.next().unwrap().as_bytes()),
+                // A valid number with a single byte replaced.
+                1 => {
+                    pan.copy_from_slice(valid.next().unwrap().as_bytes());
+                    pan[rng.below(PAN_LEN as u64) as usize] =
+                        b"0123456789x/: "[rng.below(14) as usize];
+                }
+                _ => pan.fill_with(|| rng.digit() as u8),
+            }
+            pan
+        })
+        .collect()
+}
+
+#[test]
+fn test_validate_batch_matches_luhn() {
+    // Odd size so the tail path is exercised too.
+    let pans = random_pans(10_007, 5);
+    let bitmap = validate_batch(&pans);
+    assert_eq!(bitmap.len(), 157);
+    let mut valid = 0;
+    for (i, pan) in pans.iter().enumerate() {
+        let expected = super::luhn(std::str::from_utf8(pan).unwrap());
+        assert_eq!(
+            is_valid(&bitmap, i),
+            expected,
+            "{:?}",
+            std::str::from_utf8(pan)
+        );
+        valid += usize::from(expected);
+    }
+    // `luhn` skips spaces, and so does the batch.
+    assert!(is_valid(&validate_batch(&[*b"37828224631000 5"]), 0));
+    assert!(!is_valid(&validate_batch(&[*b"37828224631000 6"]), 0));
+    assert!(
+        valid > 2000,
+        "only {valid} valid inputs, the test is too weak"
+    );
+}
+
+#[test]
+fn test_validate_batch_edge_cases() {
+    assert!(validate_batch(&[]).is_empty());
+    assert_eq!(
+        validate_batch(&[
+            *b"4263982640269299",
+            *b"4223982640269299",
+            *b"0000000000000000"
+        ]),
+        vec![0b101]
+    );
+    // A byte outside the digits must not be able to cancel out to a
+    // multiple of ten.
+    let mut odd = *b"0000000000000000";
+    odd[0] = 0xff;
+    assert_eq!(validate_batch(&[odd]), vec![0]);
+}
+
+/// Throughput comparison, run with
+/// `cargo test --release -- --ignored bench_validate_batch --nocapture`.
+#[test]
+#[ignore]
+fn bench_validate_batch() {
+    use std::time::Instant;
+
+    let pans = random_pans(1 << 20, 11);
+
+    let start = Instant::now();
+    let scalar = pans
+        .iter()
+        .filter(|pan| super::luhn(std::str::from_utf8(&pan[..]).unwrap()))
+        .count();
+    let scalar_time = start.elapsed();
+
+    let start = Instant::now();
+    let bitmap = validate_batch(&pans);
+    let batch_time = start.elapsed();
+    let batch: u32 = bitmap.iter().map(|word| word.count_ones()).sum();
+
+    assert_eq!(scalar, batch as usize);
+    let rate = |secs: f64| pans.len() as f64 / secs / 1e6;
+    println!(
+        "luhn: {:.1} M/s, validate_batch: {:.1} M/s",
+        rate(scalar_time.as_secs_f64()),
+        rate(batch_time.as_secs_f64())
+    );
+}
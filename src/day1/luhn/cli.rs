@@ -0,0 +1,436 @@
+// Command-line front end for the Luhn helpers.
+
+use std::io::{BufRead, BufReader, Write};
+
+use super::generator::Generator;
+use super::network::Network;
+use super::rng::Rng;
+use super::{check_digit, luhn};
+use crate::json::Value;
+
+/// Every input was valid (or, for `scan`, at least one number was found).
+pub const EXIT_OK: i32 = 0;
+/// At least one input failed validation (or `scan` found nothing).
+pub const EXIT_INVALID: i32 = 1;
+/// Bad usage or an I/O error.
+pub const EXIT_ERROR: i32 = 2;
+
+const USAGE: &str = "\
+usage: luhn [--json] <command> [args]
+
+commands:
+  check [NUMBER...]          validate numbers, read from stdin if none given
+  generate PARTIAL...        append the check digit to each partial number
+  random --network NAME [--count N] [--length N] [--seed N]
+                             print valid test numbers for a card network
+  scan FILE                  find valid card numbers in a text file
+";
+
+/// Shortest and longest card numbers looked for by `scan`.
+const SCAN_MIN_DIGITS: usize = 13;
+const SCAN_MAX_DIGITS: usize = 19;
+
+struct Output<'a> {
+    out: &'a mut dyn Write,
+    json: bool,
+}
+
+impl Output<'_> {
+    fn emit(&mut self, plain: String, json: Value) -> std::io::Result<()> {
+        if self.json {
+            writeln!(self.out, "{json}")
+        } else {
+            writeln!(self.out, "{plain}")
+        }
+    }
+}
+
+/// Run the tool with `args` (without the program name) and return the
+/// process exit code.
+pub fn run<I>(args: I, stdin: &mut dyn BufRead, out: &mut dyn Write, err: &mut dyn Write) -> i32
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut json = false;
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                let _ = write!(out, "{USAGE}");
+                return EXIT_OK;
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    let mut output = Output { out, json };
+    let result = match rest.split_first() {
+        Some((cmd, args)) if cmd == "check" => check(args, stdin, &mut output),
+        Some((cmd, args)) if cmd == "generate" => generate(args, &mut output),
+        Some((cmd, args)) if cmd == "random" => random(args, &mut output),
+        Some((cmd, args)) if cmd == "scan" => scan(args, &mut output),
+        Some((cmd, _)) => Err(format!("unknown command {cmd:?}\n\n{USAGE}")),
+        None => Err(USAGE.to_owned()),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(msg) => {
+            let _ = writeln!(err, "luhn: {}", msg.trim_end());
+            EXIT_ERROR
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> String {
+    format!("i/o error: {err}")
+}
+
+fn check(args: &[String], stdin: &mut dyn BufRead, output: &mut Output) -> Result<i32, String> {
+    let numbers = if args.is_empty() {
+        let mut lines = Vec::new();
+        for line in stdin.lines() {
+            let line = line.map_err(io_error)?;
+            if !line.trim().is_empty() {
+                lines.push(line.trim().to_owned());
+            }
+        }
+        lines
+    } else {
+        args.to_vec()
+    };
+
+    let mut code = EXIT_OK;
+    for number in numbers {
+        let valid = luhn(&number);
+        if !valid {
+            code = EXIT_INVALID;
+        }
+        output
+            .emit(
+                format!("{number}: {}", if valid { "valid" } else { "invalid" }),
+                Value::object([("number", number.as_str().into()), ("valid", valid.into())]),
+            )
+            .map_err(io_error)?;
+    }
+
+    Ok(code)
+}
+
+fn generate(args: &[String], output: &mut Output) -> Result<i32, String> {
+    if args.is_empty() {
+        return Err("generate: missing partial number".to_owned());
+    }
+
+    let mut code = EXIT_OK;
+    for partial in args {
+        match check_digit(partial) {
+            Some(digit) => output
+                .emit(
+                    format!("{partial}{digit}"),
+                    Value::object([
+                        ("partial", partial.as_str().into()),
+                        ("check_digit", digit.into()),
+                        ("number", format!("{partial}{digit}").into()),
+                    ]),
+                )
+                .map_err(io_error)?,
+            None => {
+                code = EXIT_INVALID;
+                output
+                    .emit(
+                        format!("{partial}: not a number"),
+                        Value::object([
+                            ("partial", partial.as_str().into()),
+                            ("error", "not a number".into()),
+                        ]),
+                    )
+                    .map_err(io_error)?
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+fn random(args: &[String], output: &mut Output) -> Result<i32, String> {
+    let mut network = None;
+    let mut count = 1;
+    let mut length = None;
+    let mut seed = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("random: {arg} requires a value"))
+        };
+        match arg.as_str() {
+            "--network" => network = Some(value()?.parse::<Network>()?),
+            "--count" => {
+                count = value()?
+                    .parse::<usize>()
+                    .map_err(|err| format!("random: invalid count: {err}"))?
+            }
+            "--length" => {
+                length = Some(
+                    value()?
+                        .parse::<usize>()
+                        .map_err(|err| format!("random: invalid length: {err}"))?,
+                )
+            }
+            "--seed" => {
+                seed = Some(
+                    value()?
+                        .parse::<u64>()
+                        .map_err(|err| format!("random: invalid seed: {err}"))?,
+                )
+            }
+            _ => return Err(format!("random: unexpected argument {arg:?}")),
+        }
+    }
+    let network = network.ok_or("random: --network is required")?;
+
+    let seed = seed.unwrap_or_else(|| Rng::from_time().next_u64());
+    let mut generator = Generator::new(network, seed);
+    if let Some(length) = length {
+        generator = generator.with_length(length)?;
+    }
+
+    for number in generator.take(count) {
+        output
+            .emit(
+                number.clone(),
+                Value::object([
+                    ("network", network.name().into()),
+                    ("number", number.into()),
+                ]),
+            )
+            .map_err(io_error)?;
+    }
+
+    Ok(EXIT_OK)
+}
+
+/// Find card numbers in runs of digit groups separated by single spaces or
+/// dashes. A number may be only part of a run, as long as it starts and ends
+/// on group boundaries, so one next to other numbers is still found. From
+/// each group on, the longest such number is taken and the search goes on
+/// after it.
+fn find_card_numbers(line: &str) -> Vec<&str> {
+    let bytes = line.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+
+        // Byte ranges of the groups in this run.
+        let mut groups = Vec::new();
+        let mut start = i;
+        while i < bytes.len() {
+            if bytes[i].is_ascii_digit() {
+                i += 1;
+            } else if matches!(bytes[i], b' ' | b'-')
+                && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+            {
+                groups.push(start..i);
+                i += 1;
+                start = i;
+            } else {
+                break;
+            }
+        }
+        groups.push(start..i);
+
+        let mut first = 0;
+        while first < groups.len() {
+            let number = (first..groups.len()).rev().find_map(|last| {
+                let digits: usize = groups[first..=last].iter().map(|g| g.len()).sum();
+                let candidate = &line[groups[first].start..groups[last].end];
+                ((SCAN_MIN_DIGITS..=SCAN_MAX_DIGITS).contains(&digits)
+                    && luhn(&candidate.replace('-', " ")))
+                .then_some((last, candidate))
+            });
+            match number {
+                Some((last, candidate)) => {
+                    found.push(candidate);
+                    first = last + 1;
+                }
+                None => first += 1,
+            }
+        }
+    }
+
+    found
+}
+
+fn scan(args: &[String], output: &mut Output) -> Result<i32, String> {
+    let [path] = args else {
+        return Err("scan: expected exactly one FILE".to_owned());
+    };
+    let file = std::fs::File::open(path).map_err(|err| format!("can't open {path}: {err}"))?;
+
+    let mut code = EXIT_INVALID;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        for number in find_card_numbers(&line) {
+            code = EXIT_OK;
+            output
+                .emit(
+                    format!("{path}:{}: {number}", i + 1),
+                    Value::object([("line", (i + 1).into()), ("number", number.into())]),
+                )
+                .map_err(io_error)?;
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+fn run_with(args: &[&str], stdin: &str) -> (i32, String, String) {
+    let mut out = Vec::new();
+    let mut err = Vec::new();
+    let code = run(
+        args.iter().map(|s| s.to_string()),
+        &mut stdin.as_bytes(),
+        &mut out,
+        &mut err,
+    );
+    (
+        code,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_check_args() {
+    let (code, out, _) = run_with(&["check", "4263 9826 4026 9299"], "");
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(out, "4263 9826 4026 9299: valid\n");
+
+    let (code, out, _) = run_with(&["check", "4263 9826 4026 9299", "1234"], "");
+    assert_eq!(code, EXIT_INVALID);
+    assert_eq!(out, "4263 9826 4026 9299: valid\n1234: invalid\n");
+}
+
+#[test]
+fn test_check_stdin_json() {
+    let (code, out, _) = run_with(&["--json", "check"], "7992 7398 713\n\n0\n");
+    assert_eq!(code, EXIT_INVALID);
+    assert_eq!(
+        out,
+        "{\"number\":\"7992 7398 713\",\"valid\":true}\n{\"number\":\"0\",\"valid\":false}\n"
+    );
+}
+
+#[test]
+fn test_generate() {
+    let (code, out, _) = run_with(&["generate", "7992739871"], "");
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(out, "79927398713\n");
+
+    let (code, out, _) = run_with(&["generate", "12x"], "");
+    assert_eq!(code, EXIT_INVALID);
+    assert_eq!(out, "12x: not a number\n");
+}
+
+#[test]
+fn test_random() {
+    let (code, out, _) = run_with(&["random", "--network", "amex", "--count", "5"], "");
+    assert_eq!(code, EXIT_OK);
+    let numbers: Vec<_> = out.lines().collect();
+    assert_eq!(numbers.len(), 5);
+    for number in numbers {
+        assert_eq!(number.len(), 15);
+        assert!(number.starts_with("34") || number.starts_with("37"));
+        assert!(luhn(number), "{number}");
+    }
+}
+
+#[test]
+fn test_random_with_seed_is_reproducible() {
+    let args = [
+        "random",
+        "--network",
+        "visa",
+        "--count",
+        "3",
+        "--seed",
+        "17",
+    ];
+    let (code, first, _) = run_with(&args, "");
+    assert_eq!(code, EXIT_OK);
+    let (_, second, _) = run_with(&args, "");
+    assert_eq!(first, second);
+
+    let (code, _, err) = run_with(&["random", "--network", "visa", "--length", "15"], "");
+    assert_eq!(code, EXIT_ERROR);
+    assert!(err.contains("15-digit"));
+}
+
+#[test]
+fn test_random_requires_network() {
+    let (code, out, err) = run_with(&["random"], "");
+    assert_eq!(code, EXIT_ERROR);
+    assert!(out.is_empty());
+    assert!(err.contains("--network"));
+}
+
+#[test]
+fn test_find_card_numbers() {
+    assert_eq!(
+        find_card_numbers("paid with 4539-3195-0343-6467, ref 12345, alt 4263982640269299."),
+        vec!["4539-3195-0343-6467", "4263982640269299"]
+    );
+    assert!(find_card_numbers("order 4223 9826 4026 9299").is_empty());
+    // Numbers next to other numbers in the same run.
+    assert_eq!(
+        find_card_numbers("card 4263 9826 4026 9299 12 2025"),
+        vec!["4263 9826 4026 9299"]
+    );
+    assert_eq!(
+        find_card_numbers("ref 1 4263982640269299"),
+        vec!["4263982640269299"]
+    );
+    assert!(find_card_numbers("").is_empty());
+}
+
+#[test]
+fn test_scan() -> Result<(), std::io::Error> {
+    let dir = tempfile::TempDir::new()?;
+    let path = dir.path().join("log.txt");
+    std::fs::write(&path, "nothing here\ncard 4263 9826 4026 9299 ok\n")?;
+    let path = path.to_str().unwrap();
+
+    let (code, out, _) = run_with(&["--json", "scan", path], "");
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(out, "{\"line\":2,\"number\":\"4263 9826 4026 9299\"}\n");
+
+    std::fs::write(
+        dir.path().join("log.txt"),
+        "card 4263 9826 4026 9299 12 2025\nref 1 4263982640269299\n",
+    )?;
+    let (code, out, _) = run_with(&["scan", path], "");
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(
+        out,
+        format!("{path}:1: 4263 9826 4026 9299\n{path}:2: 4263982640269299\n")
+    );
+
+    let (code, _, err) = run_with(&["scan", "no-such-file"], "");
+    assert_eq!(code, EXIT_ERROR);
+    assert!(err.contains("can't open"));
+    Ok(())
+}
+
+#[test]
+fn test_unknown_command() {
+    let (code, _, err) = run_with(&["frobnicate"], "");
+    assert_eq!(code, EXIT_ERROR);
+    assert!(err.contains("unknown command"));
+}
@@ -0,0 +1,134 @@
+use std::ops::RangeInclusive;
+
+use super::check_digit;
+use super::network::Network;
+use super::rng::Rng;
+
+/// Number of leading digits that make up the bank identification number.
+pub const BIN_DIGITS: usize = 6;
+
+/// How many draws to attempt before deciding the deny-list leaves no room.
+const MAX_ATTEMPTS: usize = 10_000;
+
+/// Reproducible source of Luhn-valid card numbers for test fixtures.
+///
+/// The same seed, network, length and deny-list always yield the same
+/// sequence. Numbers are produced lazily, so use [`Iterator::take`] to
+/// bound it. Iteration ends early only if no allowed BIN could be found.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rng: Rng,
+    network: Network,
+    length: usize,
+    denied_bins: Vec<RangeInclusive<u32>>,
+}
+
+impl Generator {
+    /// Generator of numbers of the network's most common length.
+    pub fn new(network: Network, seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            network,
+            length: network.lengths()[0],
+            denied_bins: Vec::new(),
+        }
+    }
+
+    /// Generate numbers of `length` digits, which must be one the network
+    /// issues.
+    pub fn with_length(mut self, length: usize) -> Result<Self, String> {
+        if !self.network.lengths().contains(&length) {
+            return Err(format!(
+                "{} does not issue {length}-digit numbers",
+                self.network
+            ));
+        }
+        self.length = length;
+        Ok(self)
+    }
+
+    /// Never produce numbers whose first six digits fall in `bins`.
+    pub fn deny(mut self, bins: RangeInclusive<u32>) -> Self {
+        self.denied_bins.push(bins);
+        self
+    }
+
+    pub fn is_denied(&self, bin: u32) -> bool {
+        self.denied_bins.iter().any(|range| range.contains(&bin))
+    }
+
+    fn draw_bin(&mut self) -> Option<String> {
+        let prefixes = self.network.prefixes();
+        for _ in 0..MAX_ATTEMPTS {
+            let mut bin = prefixes[self.rng.below(prefixes.len() as u64) as usize].to_owned();
+            while bin.len() < BIN_DIGITS {
+                bin.push(self.rng.digit());
+            }
+            let value = bin.parse().expect("BIN is made of digits");
+            if !self.is_denied(value) {
+                return Some(bin);
+            }
+        }
+
+        None
+    }
+}
+
+impl Iterator for Generator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut number = self.draw_bin()?;
+        while number.len() < self.length - 1 {
+            number.push(self.rng.digit());
+        }
+        let digit = check_digit(&number).expect("number is made of digits");
+        number.push(char::from(b'0' + digit as u8));
+        Some(number)
+    }
+}
+
+#[test]
+fn test_generator_is_reproducible() {
+    let a: Vec<_> = Generator::new(Network::Visa, 42).take(20).collect();
+    let b: Vec<_> = Generator::new(Network::Visa, 42).take(20).collect();
+    let c: Vec<_> = Generator::new(Network::Visa, 43).take(20).collect();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn test_generator_numbers_are_valid() {
+    for network in Network::ALL {
+        for &length in network.lengths() {
+            let generator = Generator::new(network, 1).with_length(length).unwrap();
+            for number in generator.take(200) {
+                assert_eq!(number.len(), length);
+                assert!(network.prefixes().iter().any(|p| number.starts_with(p)));
+                assert!(super::luhn(&number), "{number}");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_generator_rejects_unissued_length() {
+    assert!(Generator::new(Network::Amex, 1).with_length(16).is_err());
+}
+
+#[test]
+fn test_generator_skips_denied_bins() {
+    let generator = Generator::new(Network::Mastercard, 9)
+        .deny(510000..=539999)
+        .deny(550000..=550999);
+    for number in generator.take(1000) {
+        let bin: u32 = number[..BIN_DIGITS].parse().unwrap();
+        assert!((540000..=549999).contains(&bin) || (551000..=559999).contains(&bin));
+    }
+}
+
+#[test]
+fn test_generator_stops_when_everything_is_denied() {
+    let mut generator = Generator::new(Network::Visa, 3).deny(400000..=499999);
+    assert_eq!(generator.next(), None);
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Card network, identified by the leading digits of a card number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+}
+
+impl Network {
+    pub const ALL: [Network; 4] = [
+        Network::Visa,
+        Network::Mastercard,
+        Network::Amex,
+        Network::Discover,
+    ];
+
+    /// Issuer prefixes assigned to the network.
+    pub fn prefixes(&self) -> &'static [&'static str] {
+        match self {
+            Network::Visa => &["4"],
+            Network::Mastercard => &["51", "52", "53", "54", "55"],
+            Network::Amex => &["34", "37"],
+            Network::Discover => &["6011", "65"],
+        }
+    }
+
+    /// Card number lengths issued by the network, most common first.
+    pub fn lengths(&self) -> &'static [usize] {
+        match self {
+            Network::Visa => &[16, 13, 19],
+            Network::Mastercard => &[16],
+            Network::Amex => &[15],
+            Network::Discover => &[16, 19],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Network::Visa => "visa",
+            Network::Mastercard => "mastercard",
+            Network::Amex => "amex",
+            Network::Discover => "discover",
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown network {s:?}"))
+    }
+}
+
+#[test]
+fn test_parse_network() {
+    assert_eq!("visa".parse(), Ok(Network::Visa));
+    assert_eq!("AMEX".parse(), Ok(Network::Amex));
+    assert!("diners".parse::<Network>().is_err());
+}
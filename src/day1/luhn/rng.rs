@@ -0,0 +1,64 @@
+/// SplitMix64 pseudo-random generator.
+///
+/// Not suitable for anything secret, but small, fast and fully determined
+/// by its seed, which is what test data needs.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seed from the system clock.
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..bound`. `bound` must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Reject the tail that would bias the modulo.
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+
+    pub fn digit(&mut self) -> char {
+        char::from(b'0' + self.below(10) as u8)
+    }
+}
+
+#[test]
+fn test_same_seed_same_sequence() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+}
+
+#[test]
+fn test_below_stays_in_bounds() {
+    let mut rng = Rng::new(1);
+    for _ in 0..1000 {
+        assert!(rng.below(10) < 10);
+    }
+}
@@ -1,3 +1,3 @@
-mod bookstore;
+pub mod bookstore;
 mod health;
 mod router;
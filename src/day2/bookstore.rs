@@ -1,9 +1,44 @@
 // day2-morning-ep1
-#[allow(dead_code)]
 
+mod index;
+// These modules are only reached from their tests so far, not from the
+// server, so the binary build sees them as unused. Test builds still report
+// anything in them that nothing uses.
+#[cfg_attr(not(test), allow(dead_code))]
+mod interchange;
+#[cfg_attr(not(test), allow(dead_code))]
+mod inventory;
+#[cfg_attr(not(test), allow(dead_code))]
+mod lending;
+#[cfg_attr(not(test), allow(dead_code))]
+mod render;
+#[cfg_attr(not(test), allow(dead_code))]
+mod search;
+pub mod server;
+mod shared;
+#[cfg_attr(not(test), allow(dead_code))]
+mod storage;
+#[cfg_attr(not(test), allow(dead_code))]
+mod works;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::RangeBounds;
+
+use index::Indexes;
+use render::{BookFormat, IoAdapter, Plain};
+
+#[derive(Debug, Clone, PartialEq)]
 struct Book {
     title: String,
     year: u16,
+    authors: Vec<String>,
+    isbn: Option<String>,
+    publisher: Option<String>,
+    tags: Vec<String>,
+    copies: u32,
+    description: Option<String>,
 }
 
 impl Book {
@@ -12,54 +47,256 @@ impl Book {
         Book {
             title: String::from(title),
             year,
+            authors: Vec::new(),
+            isbn: None,
+            publisher: None,
+            tags: Vec::new(),
+            copies: 1,
+            description: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn with_author(mut self, author: &str) -> Book {
+        self.authors.push(String::from(author));
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_isbn(mut self, isbn: &str) -> Book {
+        self.isbn = Some(String::from(isbn));
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_publisher(mut self, publisher: &str) -> Book {
+        self.publisher = Some(String::from(publisher));
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_tag(mut self, tag: &str) -> Book {
+        self.tags.push(String::from(tag));
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_copies(mut self, copies: u32) -> Book {
+        self.copies = copies;
+        self
+    }
+
+    #[allow(dead_code)]
+    fn with_description(mut self, description: &str) -> Book {
+        self.description = Some(String::from(description));
+        self
+    }
+
+    fn has_author(&self, author: &str) -> bool {
+        self.authors.iter().any(|a| a.eq_ignore_ascii_case(author))
+    }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Stable handle to a book in a `Library`, valid until the book is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct BookId(u64);
+
+#[derive(Debug, PartialEq, Eq)]
+enum LibraryError {
+    DuplicateIsbn(String),
+    DuplicateId(BookId),
+    NotFound(BookId),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LibraryError::DuplicateIsbn(isbn) => {
+                write!(f, "a book with ISBN {isbn} already exists")
+            }
+            LibraryError::DuplicateId(BookId(id)) => {
+                write!(f, "a book with id {id} already exists")
+            }
+            LibraryError::NotFound(BookId(id)) => write!(f, "no book with id {id}"),
         }
     }
 }
 
+impl std::error::Error for LibraryError {}
+
+#[derive(Clone)]
 struct Library {
-    books: Vec<Book>,
+    // Keyed by ids handed out in increasing order, so iteration follows the
+    // order books were added in.
+    books: BTreeMap<BookId, Book>,
+    next_id: u64,
+    indexes: Indexes,
 }
 impl Library {
     fn new() -> Library {
-        Library { books: Vec::new() }
+        Library {
+            books: BTreeMap::new(),
+            next_id: 0,
+            indexes: Indexes::default(),
+        }
     }
 
     fn len(&self) -> usize {
         self.books.len()
     }
 
+    #[allow(dead_code)]
     fn is_empty(&self) -> bool {
         self.books.len() == 0
     }
 
-    fn add_book(&mut self, book: Book) {
-        self.books.push(book)
+    // Panics if another book already has the same ISBN, see `try_add_book`.
+    #[allow(dead_code)]
+    fn add_book(&mut self, book: Book) -> BookId {
+        self.try_add_book(book).unwrap()
+    }
+
+    fn try_add_book(&mut self, book: Book) -> Result<BookId, LibraryError> {
+        let id = BookId(self.next_id);
+        self.restore_book(id, book)?;
+        Ok(id)
     }
 
+    // Insert `book` under a known id, as when loading a saved library.
+    fn restore_book(&mut self, id: BookId, book: Book) -> Result<(), LibraryError> {
+        if self.books.contains_key(&id) {
+            return Err(LibraryError::DuplicateId(id));
+        }
+        self.check_isbn(id, &book)?;
+
+        self.next_id = self.next_id.max(id.0 + 1);
+        self.indexes.insert(id, &book);
+        self.books.insert(id, book);
+        Ok(())
+    }
+
+    // Whether `book` can be stored under `id` without sharing its ISBN with
+    // another book.
+    fn check_isbn(&self, id: BookId, book: &Book) -> Result<(), LibraryError> {
+        match &book.isbn {
+            Some(isbn) if self.indexes.isbn(isbn).is_some_and(|other| other != id) => {
+                Err(LibraryError::DuplicateIsbn(isbn.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn remove_book(&mut self, id: BookId) -> Result<Book, LibraryError> {
+        let book = self.books.remove(&id).ok_or(LibraryError::NotFound(id))?;
+        self.indexes.remove(id, &book);
+        Ok(book)
+    }
+
+    // Apply `change` to a copy of the book and store it only if the result
+    // does not clash with another book, so a failed update changes nothing.
+    fn update_book(
+        &mut self,
+        id: BookId,
+        change: impl FnOnce(&mut Book),
+    ) -> Result<(), LibraryError> {
+        let mut updated = self.get(id).ok_or(LibraryError::NotFound(id))?.clone();
+        change(&mut updated);
+        self.check_isbn(id, &updated)?;
+
+        let book = self.books.get_mut(&id).expect("checked above");
+        self.indexes.remove(id, book);
+        self.indexes.insert(id, &updated);
+        *book = updated;
+        Ok(())
+    }
+
+    fn get(&self, id: BookId) -> Option<&Book> {
+        self.books.get(&id)
+    }
+
+    #[allow(dead_code)]
     fn print_books(&self) {
-        // Iterate over `self.books` and print each book's title and year
-        for (i, book) in self.books.iter().enumerate() {
-            let Book { title, year } = book;
-            println!("#{i} {title} ({year})");
+        // Print each book's title and year
+        let mut out = String::new();
+        self.render_books(&Plain, &mut out)
+            .expect("writing to a String can't fail");
+        print!("{out}");
+    }
+
+    #[allow(dead_code)]
+    fn render_books(&self, format: &dyn BookFormat, out: &mut dyn fmt::Write) -> fmt::Result {
+        let books: Vec<&Book> = self.books().collect();
+        format.render(&books, out)
+    }
+
+    #[allow(dead_code)]
+    fn write_books(&self, format: &dyn BookFormat, out: &mut dyn io::Write) -> io::Result<()> {
+        let mut adapter = IoAdapter {
+            inner: out,
+            error: None,
+        };
+        match self.render_books(format, &mut adapter) {
+            Ok(()) => Ok(()),
+            Err(fmt::Error) => Err(adapter
+                .error
+                .unwrap_or_else(|| io::Error::other("formatting failed"))),
         }
     }
 
+    #[allow(dead_code)]
     fn oldest_book(&self) -> Option<&Book> {
         // Return a reference to the oldest book (if any)
-        let mut oldest: Option<&Book> = None;
-        for book in self.books.iter() {
-            match (oldest, book) {
-                (None, _) => {
-                    oldest = Some(book);
-                }
-                (Some(old), book) if book.year < old.year => {
-                    oldest = Some(book);
-                }
-                _ => {}
-            }
-        }
+        self.published_in(..).next()
+    }
+
+    #[allow(dead_code)]
+    fn by_isbn(&self, isbn: &str) -> Option<&Book> {
+        self.indexes.isbn(isbn).and_then(|id| self.get(id))
+    }
 
-        oldest
+    #[allow(dead_code)]
+    fn with_title_word(&self, word: &str) -> impl Iterator<Item = &Book> {
+        self.indexes.title_word(word).map(|id| &self.books[&id])
+    }
+
+    fn books(&self) -> impl Iterator<Item = &Book> {
+        self.books.values()
+    }
+
+    #[allow(dead_code)]
+    fn by_author<'a>(&'a self, author: &'a str) -> impl Iterator<Item = &'a Book> {
+        self.books().filter(move |book| book.has_author(author))
+    }
+
+    // Oldest first, books from the same year in the order they were added.
+    #[allow(dead_code)]
+    fn published_in(&self, years: impl RangeBounds<u16>) -> impl Iterator<Item = &Book> {
+        self.indexes.years(years).map(|id| &self.books[&id])
+    }
+
+    #[allow(dead_code)]
+    fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Book> {
+        self.books().filter(move |book| book.has_tag(tag))
+    }
+
+    #[allow(dead_code)]
+    fn title_contains(&self, needle: &str) -> impl Iterator<Item = &Book> {
+        let needle = needle.to_lowercase();
+        self.books()
+            .filter(move |book| book.title.to_lowercase().contains(&needle))
+    }
+
+    // Books ordered by `key`. The sort is stable, so books with equal keys
+    // keep the order they were added in.
+    #[allow(dead_code)]
+    fn sorted_by_key<K: Ord>(&self, key: impl FnMut(&&Book) -> K) -> impl Iterator<Item = &Book> {
+        let mut books: Vec<&Book> = self.books().collect();
+        books.sort_by_key(key);
+        books.into_iter()
     }
 }
 
@@ -117,9 +354,14 @@ fn test_library_print_books() {
     let mut library = Library::new();
     library.add_book(Book::new("Lord of the Rings", 1954));
     library.add_book(Book::new("Alice's Adventures in Wonderland", 1865));
-    // We could try and capture stdout, but let us just call the
-    // method to start with.
     library.print_books();
+
+    let mut out = String::new();
+    library.render_books(&Plain, &mut out).unwrap();
+    assert_eq!(
+        out,
+        "#0 Lord of the Rings (1954)\n#1 Alice's Adventures in Wonderland (1865)\n"
+    );
 }
 
 #[test]
@@ -139,3 +381,272 @@ fn test_library_oldest_book() {
         Some("Alice's Adventures in Wonderland")
     );
 }
+
+#[cfg(test)]
+fn sample_library() -> Library {
+    let mut library = Library::new();
+    library.add_book(
+        Book::new("The Fellowship of the Ring", 1954)
+            .with_author("J. R. R. Tolkien")
+            .with_isbn("978-0-261-10235-4")
+            .with_publisher("Allen & Unwin")
+            .with_tag("fantasy")
+            .with_copies(3),
+    );
+    library.add_book(
+        Book::new("Alice's Adventures in Wonderland", 1865)
+            .with_author("Lewis Carroll")
+            .with_isbn("978-0-14-143976-1")
+            .with_publisher("Macmillan")
+            .with_tag("fantasy")
+            .with_tag("children"),
+    );
+    library.add_book(
+        Book::new("Good Omens", 1990)
+            .with_author("Terry Pratchett")
+            .with_author("Neil Gaiman")
+            .with_tag("fantasy")
+            .with_tag("comedy"),
+    );
+    library.add_book(Book::new("The Hobbit", 1937).with_author("J. R. R. Tolkien"));
+    library
+}
+
+#[cfg(test)]
+fn titles<'a>(books: impl Iterator<Item = &'a Book>) -> Vec<&'a str> {
+    books.map(|book| book.title.as_str()).collect()
+}
+
+#[test]
+fn test_book_defaults() {
+    let book = Book::new("Lord of the Rings", 1954);
+    assert!(book.authors.is_empty());
+    assert_eq!(book.isbn, None);
+    assert_eq!(book.publisher, None);
+    assert!(book.tags.is_empty());
+    assert_eq!(book.copies, 1);
+    assert_eq!(book.description, None);
+}
+
+#[test]
+fn test_library_by_author() {
+    let library = sample_library();
+    assert_eq!(
+        titles(library.by_author("j. r. r. tolkien")),
+        ["The Fellowship of the Ring", "The Hobbit"]
+    );
+    assert_eq!(titles(library.by_author("Neil Gaiman")), ["Good Omens"]);
+    assert!(library.by_author("Nobody").next().is_none());
+}
+
+#[test]
+fn test_library_published_in() {
+    let library = sample_library();
+    assert_eq!(
+        titles(library.published_in(1900..1960)),
+        ["The Hobbit", "The Fellowship of the Ring"]
+    );
+    assert_eq!(titles(library.published_in(1990..)), ["Good Omens"]);
+    assert_eq!(library.published_in(..).count(), 4);
+}
+
+#[test]
+fn test_library_tagged() {
+    let library = sample_library();
+    assert_eq!(library.tagged("Fantasy").count(), 3);
+    assert_eq!(
+        titles(library.tagged("children")),
+        ["Alice's Adventures in Wonderland"]
+    );
+}
+
+#[test]
+fn test_library_title_contains() {
+    let library = sample_library();
+    assert_eq!(
+        titles(library.title_contains("THE")),
+        ["The Fellowship of the Ring", "The Hobbit"]
+    );
+    assert!(library.title_contains("dune").next().is_none());
+}
+
+#[test]
+fn test_library_sorted_by_key() {
+    let library = sample_library();
+    assert_eq!(
+        titles(library.sorted_by_key(|book| book.year)),
+        [
+            "Alice's Adventures in Wonderland",
+            "The Hobbit",
+            "The Fellowship of the Ring",
+            "Good Omens"
+        ]
+    );
+    assert_eq!(
+        titles(library.sorted_by_key(|book| std::cmp::Reverse(book.copies))),
+        [
+            "The Fellowship of the Ring",
+            "Alice's Adventures in Wonderland",
+            "Good Omens",
+            "The Hobbit"
+        ]
+    );
+}
+
+#[test]
+fn test_library_queries_compose() {
+    let library = sample_library();
+    let found = library
+        .by_author("J. R. R. Tolkien")
+        .filter(|book| book.copies > 1);
+    assert_eq!(titles(found), ["The Fellowship of the Ring"]);
+}
+
+#[cfg(test)]
+impl Library {
+    // Rebuild the indexes from scratch and compare with the maintained ones.
+    fn assert_indexes_consistent(&self) {
+        let mut rebuilt = Indexes::default();
+        for (&id, book) in &self.books {
+            rebuilt.insert(id, book);
+        }
+        assert_eq!(self.indexes, rebuilt);
+    }
+}
+
+#[test]
+fn test_library_by_isbn() {
+    let library = sample_library();
+    assert_eq!(
+        library
+            .by_isbn("978-0-14-143976-1")
+            .map(|b| b.title.as_str()),
+        Some("Alice's Adventures in Wonderland")
+    );
+    assert!(library.by_isbn("978-0-00-000000-0").is_none());
+}
+
+#[test]
+fn test_library_with_title_word() {
+    let library = sample_library();
+    assert_eq!(
+        titles(library.with_title_word("the")),
+        ["The Fellowship of the Ring", "The Hobbit"]
+    );
+    assert_eq!(titles(library.with_title_word("OMENS")), ["Good Omens"]);
+    assert!(library.with_title_word("dune").next().is_none());
+}
+
+#[test]
+fn test_library_rejects_duplicate_isbn() {
+    let mut library = sample_library();
+    let result = library.try_add_book(Book::new("Copy", 2000).with_isbn("978-0-261-10235-4"));
+    assert_eq!(
+        result,
+        Err(LibraryError::DuplicateIsbn(String::from(
+            "978-0-261-10235-4"
+        )))
+    );
+    assert_eq!(library.len(), 4);
+    library.assert_indexes_consistent();
+}
+
+#[test]
+fn test_library_remove_book() {
+    let mut library = sample_library();
+    let id = library.add_book(Book::new("The Silmarillion", 1977).with_isbn("978-0-04-823139-0"));
+    library.assert_indexes_consistent();
+
+    let book = library.remove_book(id).unwrap();
+    assert_eq!(book.title, "The Silmarillion");
+    assert_eq!(library.remove_book(id), Err(LibraryError::NotFound(id)));
+    assert!(library.by_isbn("978-0-04-823139-0").is_none());
+    assert!(library.with_title_word("silmarillion").next().is_none());
+    assert_eq!(library.published_in(1977..=1977).count(), 0);
+    library.assert_indexes_consistent();
+
+    // The ISBN is free again once the book is gone.
+    library.add_book(Book::new("The Silmarillion", 1977).with_isbn("978-0-04-823139-0"));
+    library.assert_indexes_consistent();
+}
+
+#[test]
+fn test_library_update_book() {
+    let mut library = sample_library();
+    let id = library.add_book(Book::new("The Hobbit", 1938));
+    library
+        .update_book(id, |book| {
+            book.title = String::from("The Hobbit, or There and Back Again");
+            book.year = 1951;
+            book.isbn = Some(String::from("978-0-04-823070-6"));
+        })
+        .unwrap();
+    library.assert_indexes_consistent();
+    assert_eq!(library.by_isbn("978-0-04-823070-6"), library.get(id));
+    assert_eq!(titles(library.published_in(1951..1952)).len(), 1);
+    assert_eq!(library.with_title_word("again").count(), 1);
+
+    // A clashing update is rejected and leaves the book untouched.
+    let before = library.get(id).cloned();
+    let result = library.update_book(id, |book| {
+        book.year = 2000;
+        book.isbn = Some(String::from("978-0-14-143976-1"));
+    });
+    assert!(matches!(result, Err(LibraryError::DuplicateIsbn(_))));
+    assert_eq!(library.get(id).cloned(), before);
+    library.assert_indexes_consistent();
+}
+
+// Lookup timings on a large catalog, run with
+// `cargo test --release -- --ignored bench_library_lookups --nocapture`.
+#[test]
+#[ignore]
+fn bench_library_lookups() {
+    use std::time::Instant;
+
+    const BOOKS: u32 = 1_000_000;
+    let mut library = Library::new();
+    for i in 0..BOOKS {
+        library.add_book(
+            Book::new(
+                &format!("Volume {i} of the archive"),
+                1500 + (i % 500) as u16,
+            )
+            .with_isbn(&format!("isbn-{i}")),
+        );
+    }
+
+    let time = |name: &str, f: &dyn Fn() -> usize| {
+        let start = Instant::now();
+        let found = f();
+        println!("{name}: {found} found in {:?}", start.elapsed());
+    };
+
+    time("1000 ISBN lookups", &|| {
+        (0..1000)
+            .filter(|i| library.by_isbn(&format!("isbn-{}", i * 997)).is_some())
+            .count()
+    });
+    // Linear scans for comparison, far fewer since each one is slow.
+    time("10 ISBN scans", &|| {
+        (0..10)
+            .filter(|i| {
+                let isbn = format!("isbn-{}", i * 997);
+                library
+                    .books()
+                    .any(|b| b.isbn.as_deref() == Some(isbn.as_str()))
+            })
+            .count()
+    });
+    time("year range", &|| library.published_in(1700..1701).count());
+    time("year scan", &|| {
+        library
+            .books()
+            .filter(|b| (1700..1701).contains(&b.year))
+            .count()
+    });
+    time("title word", &|| library.with_title_word("12345").count());
+    time("oldest book", &|| {
+        usize::from(library.oldest_book().is_some())
+    });
+}
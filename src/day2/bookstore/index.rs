@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::RangeBounds;
+
+use super::{Book, BookId};
+
+/// Split a title into the lowercase words used as title index keys.
+pub(super) fn title_words(title: &str) -> impl Iterator<Item = String> + '_ {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Secondary indexes over the books of a `Library`.
+///
+/// The caller keeps them in sync by calling `insert` after storing a book
+/// and `remove` with the same book before dropping or changing it.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Indexes {
+    by_year: BTreeMap<u16, BTreeSet<BookId>>,
+    by_isbn: HashMap<String, BookId>,
+    by_title_word: HashMap<String, BTreeSet<BookId>>,
+}
+
+impl Indexes {
+    /// Index `book`. Its ISBN, if any, must not be indexed yet.
+    pub(super) fn insert(&mut self, id: BookId, book: &Book) {
+        self.by_year.entry(book.year).or_default().insert(id);
+        if let Some(isbn) = &book.isbn {
+            let previous = self.by_isbn.insert(isbn.clone(), id);
+            debug_assert!(previous.is_none(), "ISBN {isbn} indexed twice");
+        }
+        for word in title_words(&book.title) {
+            self.by_title_word.entry(word).or_default().insert(id);
+        }
+    }
+
+    pub(super) fn remove(&mut self, id: BookId, book: &Book) {
+        if let Some(ids) = self.by_year.get_mut(&book.year) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_year.remove(&book.year);
+            }
+        }
+        if let Some(isbn) = &book.isbn {
+            self.by_isbn.remove(isbn);
+        }
+        for word in title_words(&book.title) {
+            if let Some(ids) = self.by_title_word.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.by_title_word.remove(&word);
+                }
+            }
+        }
+    }
+
+    pub(super) fn isbn(&self, isbn: &str) -> Option<BookId> {
+        self.by_isbn.get(isbn).copied()
+    }
+
+    /// Books published in `years`, oldest first, then in insertion order.
+    pub(super) fn years(&self, years: impl RangeBounds<u16>) -> impl Iterator<Item = BookId> + '_ {
+        self.by_year
+            .range(years)
+            .flat_map(|(_, ids)| ids.iter().copied())
+    }
+
+    /// Books with `word` in their title, in insertion order.
+    #[allow(dead_code)]
+    pub(super) fn title_word(&self, word: &str) -> impl Iterator<Item = BookId> + '_ {
+        self.by_title_word
+            .get(&word.to_lowercase())
+            .into_iter()
+            .flat_map(|ids| ids.iter().copied())
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for Indexes {
+    fn eq(&self, other: &Self) -> bool {
+        self.by_year == other.by_year
+            && self.by_isbn == other.by_isbn
+            && self.by_title_word == other.by_title_word
+    }
+}
+
+#[test]
+fn test_title_words() {
+    assert_eq!(
+        title_words("Alice's Adventures in Wonderland").collect::<Vec<_>>(),
+        ["alice", "s", "adventures", "in", "wonderland"]
+    );
+    assert_eq!(title_words(" -- ").count(), 0);
+}
@@ -0,0 +1,777 @@
+// Import and export of `Library` contents as CSV, JSON Lines and BibTeX.
+//
+// Imports never stop at a bad record: every record that can be added is,
+// and the others are listed in the returned `ImportReport`.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use super::{Book, BookId, Library, LibraryError};
+use crate::json::{self, Value};
+
+/// Book fields as they appear in exchanged records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Field {
+    Title,
+    Year,
+    Authors,
+    Isbn,
+    Publisher,
+    Tags,
+    Copies,
+    Description,
+}
+
+impl Field {
+    const ALL: [Field; 8] = [
+        Field::Title,
+        Field::Year,
+        Field::Authors,
+        Field::Isbn,
+        Field::Publisher,
+        Field::Tags,
+        Field::Copies,
+        Field::Description,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Year => "year",
+            Field::Authors => "authors",
+            Field::Isbn => "isbn",
+            Field::Publisher => "publisher",
+            Field::Tags => "tags",
+            Field::Copies => "copies",
+            Field::Description => "description",
+        }
+    }
+}
+
+/// Separator for the authors and tags lists in CSV cells.
+const LIST_SEPARATOR: &str = ";";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum RecordError {
+    MissingTitle,
+    BadYear(String),
+    BadCopies(String),
+    DuplicateIsbn(String),
+    Malformed(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::MissingTitle => write!(f, "missing title"),
+            RecordError::BadYear(year) => write!(f, "invalid year {year:?}"),
+            RecordError::BadCopies(copies) => write!(f, "invalid copy count {copies:?}"),
+            RecordError::DuplicateIsbn(isbn) => write!(f, "duplicate ISBN {isbn}"),
+            RecordError::Malformed(msg) => write!(f, "malformed record: {msg}"),
+        }
+    }
+}
+
+/// A record that was skipped, with the input line it started on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Rejected {
+    pub(super) line: usize,
+    pub(super) error: RecordError,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct ImportReport {
+    pub(super) imported: Vec<BookId>,
+    pub(super) rejected: Vec<Rejected>,
+}
+
+// Field values of one record, as text, before validation.
+#[derive(Debug, Default)]
+struct RawRecord {
+    title: Option<String>,
+    year: Option<String>,
+    authors: Vec<String>,
+    isbn: Option<String>,
+    publisher: Option<String>,
+    tags: Vec<String>,
+    copies: Option<String>,
+    description: Option<String>,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+fn split_list(value: &str, separator: &str) -> Vec<String> {
+    value.split(separator).filter_map(non_empty).collect()
+}
+
+impl RawRecord {
+    fn set(&mut self, field: Field, value: &str) {
+        match field {
+            Field::Title => self.title = non_empty(value),
+            Field::Year => self.year = non_empty(value),
+            Field::Authors => self.authors = split_list(value, LIST_SEPARATOR),
+            Field::Isbn => self.isbn = non_empty(value),
+            Field::Publisher => self.publisher = non_empty(value),
+            Field::Tags => self.tags = split_list(value, LIST_SEPARATOR),
+            Field::Copies => self.copies = non_empty(value),
+            Field::Description => self.description = non_empty(value),
+        }
+    }
+
+    fn into_book(self) -> Result<Book, RecordError> {
+        let title = self.title.ok_or(RecordError::MissingTitle)?;
+        let year = self.year.unwrap_or_default();
+        let year = year.parse().map_err(|_| RecordError::BadYear(year))?;
+        let mut book = Book::new(&title, year);
+        book.authors = self.authors;
+        book.isbn = self.isbn;
+        book.publisher = self.publisher;
+        book.tags = self.tags;
+        book.description = self.description;
+        if let Some(copies) = self.copies {
+            book.copies = copies.parse().map_err(|_| RecordError::BadCopies(copies))?;
+        }
+        Ok(book)
+    }
+}
+
+impl ImportReport {
+    fn add(&mut self, library: &mut Library, line: usize, record: Result<RawRecord, RecordError>) {
+        let result = record.and_then(RawRecord::into_book).and_then(|book| {
+            library.try_add_book(book).map_err(|err| match err {
+                LibraryError::DuplicateIsbn(isbn) => RecordError::DuplicateIsbn(isbn),
+                err => RecordError::Malformed(err.to_string()),
+            })
+        });
+        match result {
+            Ok(id) => self.imported.push(id),
+            Err(error) => self.rejected.push(Rejected { line, error }),
+        }
+    }
+}
+
+/// Which CSV column holds each field. Columns not mapped are ignored.
+#[derive(Debug, Clone)]
+pub(super) struct CsvMapping {
+    columns: Vec<(Field, String)>,
+}
+
+impl Default for CsvMapping {
+    /// Columns named like the fields, as written by `export_csv`.
+    fn default() -> Self {
+        CsvMapping {
+            columns: Field::ALL
+                .iter()
+                .map(|&field| (field, String::from(field.name())))
+                .collect(),
+        }
+    }
+}
+
+impl CsvMapping {
+    /// Read `field` from the column with header `column` instead.
+    pub(super) fn column(mut self, field: Field, column: &str) -> Self {
+        self.columns.retain(|(f, _)| *f != field);
+        self.columns.push((field, String::from(column)));
+        self
+    }
+}
+
+/// Split CSV text into records of fields, each with its starting line.
+///
+/// Follows RFC 4180: fields may be quoted, quotes inside quoted fields are
+/// doubled and quoted fields may span lines.
+fn csv_records(input: &str) -> Result<Vec<(usize, Vec<String>)>, Rejected> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut in_quotes = false;
+    let mut at_field_start = true;
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                c => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if at_field_start => {
+                in_quotes = true;
+                at_field_start = false;
+            }
+            ',' => {
+                record.push(std::mem::take(&mut field));
+                at_field_start = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((start_line, std::mem::take(&mut record)));
+                line += 1;
+                start_line = line;
+                at_field_start = true;
+            }
+            c => {
+                field.push(c);
+                at_field_start = false;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(Rejected {
+            line: start_line,
+            error: RecordError::Malformed(String::from("unterminated quoted field")),
+        });
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start_line, record));
+    }
+    // Blank lines are not records.
+    records.retain(|(_, fields)| fields.len() > 1 || !fields[0].is_empty());
+    Ok(records)
+}
+
+pub(super) fn import_csv(library: &mut Library, input: &str, mapping: &CsvMapping) -> ImportReport {
+    let mut report = ImportReport::default();
+    let records = match csv_records(input) {
+        Ok(records) => records,
+        Err(rejected) => {
+            report.rejected.push(rejected);
+            return report;
+        }
+    };
+    let Some(((_, header), rows)) = records.split_first() else {
+        return report;
+    };
+
+    let columns: Vec<Option<Field>> = header
+        .iter()
+        .map(|name| {
+            mapping
+                .columns
+                .iter()
+                .find(|(_, column)| column.trim().eq_ignore_ascii_case(name.trim()))
+                .map(|(field, _)| *field)
+        })
+        .collect();
+
+    for (line, row) in rows {
+        let record = if row.len() != header.len() {
+            Err(RecordError::Malformed(format!(
+                "expected {} fields, found {}",
+                header.len(),
+                row.len()
+            )))
+        } else {
+            let mut record = RawRecord::default();
+            for (field, value) in columns.iter().zip(row) {
+                if let Some(field) = field {
+                    record.set(*field, value);
+                }
+            }
+            Ok(record)
+        };
+        report.add(library, *line, record);
+    }
+    report
+}
+
+pub(super) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+pub(super) fn export_csv(library: &Library, out: &mut dyn Write) -> io::Result<()> {
+    let header: Vec<_> = Field::ALL.iter().map(|field| field.name()).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for book in library.books() {
+        let fields = [
+            book.title.clone(),
+            book.year.to_string(),
+            book.authors.join(LIST_SEPARATOR),
+            book.isbn.clone().unwrap_or_default(),
+            book.publisher.clone().unwrap_or_default(),
+            book.tags.join(LIST_SEPARATOR),
+            book.copies.to_string(),
+            book.description.clone().unwrap_or_default(),
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+fn json_record(value: &Value) -> Result<RawRecord, RecordError> {
+    if !matches!(value, Value::Object(_)) {
+        return Err(RecordError::Malformed(String::from("expected an object")));
+    }
+    // Numbers are accepted as text, so they go through the same checks as
+    // the other formats.
+    let text = |field: Field| match value.get(field.name()) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(non_empty(s)),
+        Some(Value::Number(n)) => Ok(Some(n.to_string())),
+        Some(_) => Err(RecordError::Malformed(format!(
+            "{} must be a string",
+            field.name()
+        ))),
+    };
+    let list = |field: Field| match value.get(field.name()) {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                RecordError::Malformed(format!("{} must be a list of strings", field.name()))
+            }),
+        Some(_) => Err(RecordError::Malformed(format!(
+            "{} must be a list of strings",
+            field.name()
+        ))),
+    };
+
+    Ok(RawRecord {
+        title: text(Field::Title)?,
+        year: text(Field::Year)?,
+        authors: list(Field::Authors)?,
+        isbn: text(Field::Isbn)?,
+        publisher: text(Field::Publisher)?,
+        tags: list(Field::Tags)?,
+        copies: text(Field::Copies)?,
+        description: text(Field::Description)?,
+    })
+}
+
+pub(super) fn import_json_lines(library: &mut Library, input: &str) -> ImportReport {
+    let mut report = ImportReport::default();
+    for (i, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = json::parse(line)
+            .map_err(|err| RecordError::Malformed(err.to_string()))
+            .and_then(|value| json_record(&value));
+        report.add(library, i + 1, record);
+    }
+    report
+}
+
+pub(super) fn export_json_lines(library: &Library, out: &mut dyn Write) -> io::Result<()> {
+    for book in library.books() {
+        writeln!(out, "{}", book.to_json())?;
+    }
+    Ok(())
+}
+
+// Lowercased field names and their values, in entry order.
+type BibtexFields = Result<Vec<(String, String)>, RecordError>;
+
+/// Parse `@book{key, name = {value}, ...}` entries. Values may be braced,
+/// quoted or bare; anything between entries is ignored, as in BibTeX.
+fn bibtex_entries(input: &str) -> Vec<(usize, BibtexFields)> {
+    let mut entries = Vec::new();
+    let mut rest = input;
+    while let Some(at) = rest.find('@') {
+        let line = 1 + input[..input.len() - rest.len() + at].matches('\n').count();
+        let (entry, remaining) = bibtex_entry(&rest[at + 1..]);
+        entries.push((line, entry));
+        rest = remaining;
+    }
+    entries
+}
+
+fn bibtex_entry(input: &str) -> (BibtexFields, &str) {
+    let malformed = |msg: &str| Err(RecordError::Malformed(String::from(msg)));
+    let Some(open) = input.find('{') else {
+        return (malformed("missing '{'"), "");
+    };
+    // Find the matching closing brace so one bad entry does not swallow
+    // the following ones.
+    let mut depth = 0;
+    let mut end = None;
+    for (i, c) in input[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(open + i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(end) = end else {
+        return (malformed("unbalanced braces"), "");
+    };
+    let rest = &input[end + 1..];
+
+    if !input[..open].trim().eq_ignore_ascii_case("book") {
+        return (malformed("only @book entries are supported"), rest);
+    }
+    let body = &input[open + 1..end];
+    let Some(comma) = body.find(',') else {
+        return (Ok(Vec::new()), rest);
+    };
+
+    let mut fields = Vec::new();
+    let mut body = body[comma + 1..].trim_start();
+    while !body.is_empty() {
+        let Some(eq) = body.find('=') else {
+            return (malformed("expected 'name = value'"), rest);
+        };
+        let name = body[..eq].trim().to_lowercase();
+        let value = body[eq + 1..].trim_start();
+        let (value, after) = match value.chars().next() {
+            Some('{') => {
+                let mut depth = 0;
+                let close = value.char_indices().find_map(|(i, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    (depth == 0).then_some(i)
+                });
+                match close {
+                    Some(close) => (&value[1..close], &value[close + 1..]),
+                    None => return (malformed("unbalanced braces"), rest),
+                }
+            }
+            Some('"') => match value[1..].find('"') {
+                Some(close) => (&value[1..close + 1], &value[close + 2..]),
+                None => return (malformed("unterminated string"), rest),
+            },
+            _ => {
+                let end = value.find(',').unwrap_or(value.len());
+                (value[..end].trim(), &value[end..])
+            }
+        };
+        fields.push((name, bibtex_unescape(value)));
+        body = after.trim_start();
+        body = body.strip_prefix(',').unwrap_or(body).trim_start();
+    }
+    (Ok(fields), rest)
+}
+
+// Braces and backslashes written as LaTeX commands in their own group, so
+// the value stays balanced for BibTeX and reads back unchanged.
+const BIBTEX_ESCAPES: [(char, &str); 3] = [
+    ('{', "{\\textbraceleft}"),
+    ('}', "{\\textbraceright}"),
+    ('\\', "{\\textbackslash}"),
+];
+
+fn bibtex_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match BIBTEX_ESCAPES.iter().find(|&&(special, _)| special == c) {
+            Some((_, escape)) => escaped.push_str(escape),
+            None => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Undo `bibtex_escape`. Other braces only protect capitalization in BibTeX
+// and are dropped.
+fn bibtex_unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        if let Some(&(special, escape)) = BIBTEX_ESCAPES
+            .iter()
+            .find(|(_, escape)| rest.starts_with(escape))
+        {
+            unescaped.push(special);
+            rest = &rest[escape.len()..];
+            continue;
+        }
+        if c != '{' && c != '}' {
+            unescaped.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    unescaped
+}
+
+pub(super) fn import_bibtex(library: &mut Library, input: &str) -> ImportReport {
+    let mut report = ImportReport::default();
+    for (line, entry) in bibtex_entries(input) {
+        let record = entry.map(|fields| {
+            let mut record = RawRecord::default();
+            for (name, value) in fields {
+                match name.as_str() {
+                    "title" => record.set(Field::Title, &value),
+                    "year" => record.set(Field::Year, &value),
+                    "author" => record.authors = split_list(&value, " and "),
+                    "isbn" => record.set(Field::Isbn, &value),
+                    "publisher" => record.set(Field::Publisher, &value),
+                    "keywords" => record.tags = split_list(&value, ","),
+                    "copies" => record.set(Field::Copies, &value),
+                    "abstract" => record.set(Field::Description, &value),
+                    _ => {}
+                }
+            }
+            record
+        });
+        report.add(library, line, record);
+    }
+    report
+}
+
+fn bibtex_field(out: &mut dyn Write, name: &str, value: &str) -> io::Result<()> {
+    writeln!(out, "  {name} = {{{}}},", bibtex_escape(value))
+}
+
+pub(super) fn export_bibtex(library: &Library, out: &mut dyn Write) -> io::Result<()> {
+    for (i, book) in library.books().enumerate() {
+        writeln!(out, "@book{{book{i},")?;
+        bibtex_field(out, "title", &book.title)?;
+        if !book.authors.is_empty() {
+            bibtex_field(out, "author", &book.authors.join(" and "))?;
+        }
+        writeln!(out, "  year = {},", book.year)?;
+        if let Some(publisher) = &book.publisher {
+            bibtex_field(out, "publisher", publisher)?;
+        }
+        if let Some(isbn) = &book.isbn {
+            bibtex_field(out, "isbn", isbn)?;
+        }
+        if !book.tags.is_empty() {
+            bibtex_field(out, "keywords", &book.tags.join(", "))?;
+        }
+        if let Some(description) = &book.description {
+            bibtex_field(out, "abstract", description)?;
+        }
+        writeln!(out, "  copies = {}", book.copies)?;
+        writeln!(out, "}}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn export_to_string(
+    library: &Library,
+    export: fn(&Library, &mut dyn Write) -> io::Result<()>,
+) -> String {
+    let mut out = Vec::new();
+    export(library, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_csv_round_trip() {
+    let library = super::sample_library();
+    let csv = export_to_string(&library, export_csv);
+    assert!(csv.starts_with("title,year,authors,isbn,publisher,tags,copies,description\n"));
+    assert!(csv.contains("Good Omens,1990,Terry Pratchett;Neil Gaiman,,,fantasy;comedy,1,\n"));
+
+    let mut imported = Library::new();
+    let report = import_csv(&mut imported, &csv, &CsvMapping::default());
+    assert!(report.rejected.is_empty(), "{:?}", report.rejected);
+    assert_eq!(report.imported.len(), 4);
+    assert!(imported.books().eq(library.books()));
+}
+
+#[test]
+fn test_csv_quoting() {
+    let mut library = Library::new();
+    library.add_book(Book::new("Eats, Shoots & \"Leaves\"\nA Guide", 2003));
+    let csv = export_to_string(&library, export_csv);
+    assert!(csv.contains("\"Eats, Shoots & \"\"Leaves\"\"\nA Guide\""));
+
+    let mut imported = Library::new();
+    let report = import_csv(&mut imported, &csv, &CsvMapping::default());
+    assert_eq!(report.imported.len(), 1);
+    assert!(imported.books().eq(library.books()));
+}
+
+#[test]
+fn test_csv_header_mapping() {
+    let input = "\
+Name,Published,Writer,Shelf
+Dune,1965,Frank Herbert,B2
+\"Emma\",1815,Jane Austen,A1
+";
+    let mapping = CsvMapping::default()
+        .column(Field::Title, "Name")
+        .column(Field::Year, "Published")
+        .column(Field::Authors, "Writer");
+    let mut library = Library::new();
+    let report = import_csv(&mut library, input, &mapping);
+    assert!(report.rejected.is_empty());
+    assert_eq!(library.by_author("Jane Austen").count(), 1);
+    assert_eq!(
+        library.oldest_book().map(|b| b.title.as_str()),
+        Some("Emma")
+    );
+}
+
+#[test]
+fn test_csv_error_report() {
+    let input = "\
+title,year,isbn
+Dune,1965,1
+,1999,2
+Emma,18xx,3
+Ulysses,1922,1
+Middlemarch,1871
+Persuasion,1817,4
+";
+    let mut library = Library::new();
+    let report = import_csv(&mut library, input, &CsvMapping::default());
+    assert_eq!(report.imported.len(), 2);
+    assert_eq!(
+        report.rejected,
+        [
+            Rejected {
+                line: 3,
+                error: RecordError::MissingTitle
+            },
+            Rejected {
+                line: 4,
+                error: RecordError::BadYear(String::from("18xx"))
+            },
+            Rejected {
+                line: 5,
+                error: RecordError::DuplicateIsbn(String::from("1"))
+            },
+            Rejected {
+                line: 6,
+                error: RecordError::Malformed(String::from("expected 3 fields, found 2"))
+            },
+        ]
+    );
+    assert_eq!(
+        report.rejected[1].error.to_string(),
+        "invalid year \"18xx\""
+    );
+}
+
+#[test]
+fn test_json_lines_round_trip() {
+    let library = super::sample_library();
+    let jsonl = export_to_string(&library, export_json_lines);
+    assert_eq!(jsonl.lines().count(), 4);
+
+    let mut imported = Library::new();
+    let report = import_json_lines(&mut imported, &jsonl);
+    assert!(report.rejected.is_empty());
+    assert!(imported.books().eq(library.books()));
+}
+
+#[test]
+fn test_json_lines_error_report() {
+    let input = r#"{"title":"Dune","year":1965}
+{"title":"Emma","year":"1815"}
+
+{"title":"Ulysses","year":-5}
+{"year":1999}
+not json
+{"title":"Middlemarch","year":1871,"authors":"George Eliot"}
+"#;
+    let mut library = Library::new();
+    let report = import_json_lines(&mut library, input);
+    assert_eq!(report.imported.len(), 2);
+    let errors: Vec<_> = report.rejected.iter().map(|r| (r.line, &r.error)).collect();
+    assert_eq!(errors[0], (4, &RecordError::BadYear(String::from("-5"))));
+    assert_eq!(errors[1], (5, &RecordError::MissingTitle));
+    assert!(matches!(errors[2], (6, RecordError::Malformed(_))));
+    assert!(matches!(errors[3], (7, RecordError::Malformed(_))));
+}
+
+#[test]
+fn test_bibtex_round_trip() {
+    let library = super::sample_library();
+    let bib = export_to_string(&library, export_bibtex);
+    assert!(bib.contains("  author = {Terry Pratchett and Neil Gaiman},\n"));
+
+    let mut imported = Library::new();
+    let report = import_bibtex(&mut imported, &bib);
+    assert!(report.rejected.is_empty(), "{:?}", report.rejected);
+    assert!(imported.books().eq(library.books()));
+}
+
+#[test]
+fn test_bibtex_escaping() {
+    let mut library = Library::new();
+    library.add_book(
+        Book::new("Sets {a, b} and }unbalanced{ \\ braces", 2001)
+            .with_author("A. {Bracket")
+            .with_tag("math")
+            .with_tag("set theory")
+            .with_description("Ends in a backslash \\"),
+    );
+    let bib = export_to_string(&library, export_bibtex);
+    assert!(bib.contains(
+        "  title = {Sets {\\textbraceleft}a, b{\\textbraceright} and \
+         {\\textbraceright}unbalanced{\\textbraceleft} {\\textbackslash} braces},\n"
+    ));
+    assert!(bib.contains("  keywords = {math, set theory},\n"));
+
+    let mut imported = Library::new();
+    let report = import_bibtex(&mut imported, &bib);
+    assert!(report.rejected.is_empty(), "{:?}", report.rejected);
+    assert!(imported.books().eq(library.books()));
+    // Whitespace after the keyword separator is not kept.
+    assert_eq!(
+        imported.books().next().unwrap().tags,
+        ["math", "set theory"]
+    );
+}
+
+#[test]
+fn test_bibtex_import() {
+    let input = r#"
+Comments outside entries are ignored.
+
+@Book{herbert65,
+  Title = {{D}une},
+  Author = "Frank Herbert",
+  Year = 1965,
+  keywords = {science fiction, classic}
+}
+
+@article{x, title = {Not a book}, year = 2000}
+
+@book{missing,
+  year = {1999}
+}
+"#;
+    let mut library = Library::new();
+    let report = import_bibtex(&mut library, input);
+    assert_eq!(report.imported.len(), 1);
+    let book = library.get(report.imported[0]).unwrap();
+    assert_eq!(book.title, "Dune");
+    assert_eq!(book.authors, ["Frank Herbert"]);
+    assert_eq!(book.tags, ["science fiction", "classic"]);
+
+    let errors: Vec<_> = report.rejected.iter().map(|r| (r.line, &r.error)).collect();
+    assert!(matches!(errors[0], (11, RecordError::Malformed(_))));
+    assert_eq!(errors[1], (13, &RecordError::MissingTitle));
+}
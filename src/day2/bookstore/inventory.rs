@@ -0,0 +1,659 @@
+// Prices, stock and sales for the books of a `Library`.
+//
+// Every change in stock is an entry in an append-only ledger. Stock levels
+// and reports are computed from the ledger rather than kept in counters, so
+// they always agree with the recorded history.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, RangeBounds};
+
+use super::lending::Day;
+use super::{Book, BookId, Library};
+
+/// An amount in minor currency units, such as cents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct Money(pub(super) u64);
+
+impl Money {
+    /// `None` if the amount doesn't fit.
+    pub(super) fn checked_times(self, quantity: u32) -> Option<Money> {
+        self.0.checked_mul(u64::from(quantity)).map(Money)
+    }
+
+    pub(super) fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    // Rounded down, so a discount never exceeds the advertised percentage.
+    // Whole hundreds and the rest are scaled apart so that large amounts
+    // don't overflow.
+    pub(super) fn percent(self, percent: u32) -> Money {
+        let percent = u64::from(percent.min(100));
+        Money(self.0 / 100 * percent + self.0 % 100 * percent / 100)
+    }
+}
+
+/// Totals saturate at the largest amount instead of overflowing. Each sale
+/// is checked to fit when it is recorded; see `Inventory::sell`.
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        self.checked_add(other).unwrap_or(Money(u64::MAX))
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money(0), Add::add)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Transaction {
+    Restock {
+        quantity: u32,
+        unit_cost: Money,
+    },
+    /// The price and discount are recorded as charged, so later price
+    /// changes don't rewrite past revenue.
+    Sale {
+        quantity: u32,
+        unit_price: Money,
+        discount: Money,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Entry {
+    pub(super) day: Day,
+    pub(super) book: BookId,
+    pub(super) transaction: Transaction,
+}
+
+impl Entry {
+    /// What the customer paid, zero for restocks.
+    pub(super) fn revenue(&self) -> Money {
+        match self.transaction {
+            Transaction::Sale {
+                quantity,
+                unit_price,
+                discount,
+            } => {
+                let gross = unit_price
+                    .checked_times(quantity)
+                    .expect("sales are checked to fit when recorded");
+                Money(gross.0.saturating_sub(discount.0))
+            }
+            Transaction::Restock { .. } => Money(0),
+        }
+    }
+
+    fn units_sold(&self) -> u32 {
+        match self.transaction {
+            Transaction::Sale { quantity, .. } => quantity,
+            Transaction::Restock { .. } => 0,
+        }
+    }
+}
+
+/// A sale being priced, as seen by discount rules.
+pub(super) struct SaleLine<'a> {
+    pub(super) book: &'a Book,
+    pub(super) quantity: u32,
+    pub(super) unit_price: Money,
+    pub(super) day: Day,
+}
+
+impl SaleLine<'_> {
+    pub(super) fn gross(&self) -> Money {
+        self.unit_price
+            .checked_times(self.quantity)
+            .expect("sales are checked to fit before they are priced")
+    }
+}
+
+/// Computes the discount on a sale, as a total for the whole line.
+pub(super) trait DiscountRule {
+    fn discount(&self, line: &SaleLine) -> Money;
+}
+
+/// A percentage off books with a tag.
+pub(super) struct TagDiscount {
+    pub(super) tag: String,
+    pub(super) percent: u32,
+}
+
+impl DiscountRule for TagDiscount {
+    fn discount(&self, line: &SaleLine) -> Money {
+        if line.book.has_tag(&self.tag) {
+            line.gross().percent(self.percent)
+        } else {
+            Money(0)
+        }
+    }
+}
+
+/// A percentage off when buying at least `min_quantity` copies at once.
+pub(super) struct BulkDiscount {
+    pub(super) min_quantity: u32,
+    pub(super) percent: u32,
+}
+
+impl DiscountRule for BulkDiscount {
+    fn discount(&self, line: &SaleLine) -> Money {
+        if line.quantity >= self.min_quantity {
+            line.gross().percent(self.percent)
+        } else {
+            Money(0)
+        }
+    }
+}
+
+/// A percentage off everything between two days, inclusive.
+pub(super) struct Promotion {
+    pub(super) first: Day,
+    pub(super) last: Day,
+    pub(super) percent: u32,
+}
+
+impl DiscountRule for Promotion {
+    fn discount(&self, line: &SaleLine) -> Money {
+        if (self.first..=self.last).contains(&line.day) {
+            line.gross().percent(self.percent)
+        } else {
+            Money(0)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum SalesError {
+    UnknownBook(BookId),
+    NotForSale(BookId),
+    ZeroQuantity,
+    OutOfStock {
+        book: BookId,
+        requested: u32,
+        available: u32,
+    },
+    /// Entries must be recorded in day order.
+    OutOfOrder {
+        last: Day,
+        day: Day,
+    },
+    /// The stock of the book would go past `u32::MAX` copies.
+    StockOverflow(BookId),
+    /// The price of the sale doesn't fit in `Money`.
+    AmountOverflow(BookId),
+}
+
+impl fmt::Display for SalesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SalesError::UnknownBook(BookId(id)) => write!(f, "no book with id {id}"),
+            SalesError::NotForSale(BookId(id)) => write!(f, "book {id} has no price"),
+            SalesError::ZeroQuantity => write!(f, "quantity must be at least 1"),
+            SalesError::OutOfStock {
+                book: BookId(book),
+                requested,
+                available,
+            } => write!(
+                f,
+                "only {available} of book {book} in stock, {requested} requested"
+            ),
+            SalesError::OutOfOrder {
+                last: Day(last),
+                day: Day(day),
+            } => write!(f, "day {day} is before the last entry, on day {last}"),
+            SalesError::StockOverflow(BookId(id)) => {
+                write!(f, "too many copies of book {id} in stock")
+            }
+            SalesError::AmountOverflow(BookId(id)) => {
+                write!(f, "the price of the sale of book {id} is too large")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SalesError {}
+
+/// Prices, discount rules and the stock ledger for the books of a
+/// `Library`.
+///
+/// Stock here counts copies for sale, separate from the copies the library
+/// lends out.
+#[derive(Default)]
+pub(super) struct Inventory {
+    prices: HashMap<BookId, Money>,
+    discounts: Vec<Box<dyn DiscountRule>>,
+    // In day order, see `SalesError::OutOfOrder`.
+    ledger: Vec<Entry>,
+}
+
+impl Inventory {
+    pub(super) fn new() -> Self {
+        Inventory::default()
+    }
+
+    pub(super) fn set_price(
+        &mut self,
+        library: &Library,
+        book: BookId,
+        price: Money,
+    ) -> Result<(), SalesError> {
+        library.get(book).ok_or(SalesError::UnknownBook(book))?;
+        self.prices.insert(book, price);
+        Ok(())
+    }
+
+    pub(super) fn price(&self, book: BookId) -> Option<Money> {
+        self.prices.get(&book).copied()
+    }
+
+    /// Add a discount rule. When several apply to a sale only the largest
+    /// discount is given; discounts don't stack.
+    pub(super) fn add_discount(&mut self, rule: impl DiscountRule + 'static) {
+        self.discounts.push(Box::new(rule));
+    }
+
+    pub(super) fn ledger(&self) -> &[Entry] {
+        &self.ledger
+    }
+
+    fn record(&mut self, entry: Entry) -> Result<&Entry, SalesError> {
+        if let Some(last) = self.ledger.last() {
+            if entry.day < last.day {
+                return Err(SalesError::OutOfOrder {
+                    last: last.day,
+                    day: entry.day,
+                });
+            }
+        }
+        self.ledger.push(entry);
+        Ok(&self.ledger[self.ledger.len() - 1])
+    }
+
+    pub(super) fn restock(
+        &mut self,
+        library: &Library,
+        book: BookId,
+        quantity: u32,
+        unit_cost: Money,
+        day: Day,
+    ) -> Result<(), SalesError> {
+        library.get(book).ok_or(SalesError::UnknownBook(book))?;
+        if quantity == 0 {
+            return Err(SalesError::ZeroQuantity);
+        }
+        if self.stock(book).checked_add(quantity).is_none() {
+            return Err(SalesError::StockOverflow(book));
+        }
+        self.record(Entry {
+            day,
+            book,
+            transaction: Transaction::Restock {
+                quantity,
+                unit_cost,
+            },
+        })?;
+        Ok(())
+    }
+
+    /// Sell `quantity` copies at the current price less the best applicable
+    /// discount, returning the recorded entry.
+    pub(super) fn sell(
+        &mut self,
+        library: &Library,
+        book: BookId,
+        quantity: u32,
+        day: Day,
+    ) -> Result<&Entry, SalesError> {
+        let found = library.get(book).ok_or(SalesError::UnknownBook(book))?;
+        let unit_price = self.price(book).ok_or(SalesError::NotForSale(book))?;
+        if quantity == 0 {
+            return Err(SalesError::ZeroQuantity);
+        }
+        let available = self.stock(book);
+        if quantity > available {
+            return Err(SalesError::OutOfStock {
+                book,
+                requested: quantity,
+                available,
+            });
+        }
+        if unit_price.checked_times(quantity).is_none() {
+            return Err(SalesError::AmountOverflow(book));
+        }
+
+        let line = SaleLine {
+            book: found,
+            quantity,
+            unit_price,
+            day,
+        };
+        let discount = self
+            .discounts
+            .iter()
+            .map(|rule| rule.discount(&line))
+            .max()
+            .unwrap_or_default()
+            .min(line.gross());
+        self.record(Entry {
+            day,
+            book,
+            transaction: Transaction::Sale {
+                quantity,
+                unit_price,
+                discount,
+            },
+        })
+    }
+
+    // Restocks that would take the total past `u32::MAX` are refused and
+    // sales never exceed the stock, so this can't overflow.
+    pub(super) fn stock(&self, book: BookId) -> u32 {
+        self.ledger
+            .iter()
+            .filter(|entry| entry.book == book)
+            .fold(0, |stock, entry| match entry.transaction {
+                Transaction::Restock { quantity, .. } => stock + quantity,
+                Transaction::Sale { quantity, .. } => stock - quantity,
+            })
+    }
+
+    // Entries recorded on `days`. The ledger is in day order, so this is a
+    // contiguous slice.
+    fn entries_in(&self, days: impl RangeBounds<Day>) -> impl Iterator<Item = &Entry> {
+        let start = self
+            .ledger
+            .partition_point(|entry| entry_before(&days, entry));
+        self.ledger[start..]
+            .iter()
+            .take_while(move |entry| days.contains(&entry.day))
+    }
+
+    pub(super) fn revenue(&self, days: impl RangeBounds<Day>) -> Money {
+        self.entries_in(days).map(Entry::revenue).sum()
+    }
+
+    /// Revenue for each period of `period_days` days from `start` up to,
+    /// not including, `end`, keyed by the first day of the period. Periods
+    /// without sales are included with zero revenue.
+    pub(super) fn revenue_by_period(
+        &self,
+        start: Day,
+        end: Day,
+        period_days: u32,
+    ) -> Vec<(Day, Money)> {
+        assert!(period_days > 0, "periods must be at least one day long");
+        let periods = start.days_until(end).div_ceil(period_days);
+        let mut report: Vec<_> = (0..periods)
+            .map(|i| (start.plus(i * period_days), Money(0)))
+            .collect();
+        for entry in self.entries_in(start..end) {
+            let period = start.days_until(entry.day) / period_days;
+            let revenue = &mut report[period as usize].1;
+            *revenue = *revenue + entry.revenue();
+        }
+        report
+    }
+
+    /// The `limit` books with the most copies sold on `days`, most first
+    /// and ties in id order.
+    pub(super) fn best_sellers(
+        &self,
+        days: impl RangeBounds<Day>,
+        limit: usize,
+    ) -> Vec<(BookId, u32)> {
+        let mut sold: HashMap<BookId, u32> = HashMap::new();
+        for entry in self.entries_in(days) {
+            if entry.units_sold() > 0 {
+                *sold.entry(entry.book).or_default() += entry.units_sold();
+            }
+        }
+        let mut sold: Vec<_> = sold.into_iter().collect();
+        sold.sort_by(|(a, a_sold), (b, b_sold)| b_sold.cmp(a_sold).then(a.cmp(b)));
+        sold.truncate(limit);
+        sold
+    }
+
+    /// Books for sale with at most `threshold` copies in stock, in id order.
+    pub(super) fn low_stock(&self, threshold: u32) -> Vec<(BookId, u32)> {
+        let mut low: Vec<_> = self
+            .prices
+            .keys()
+            .map(|&book| (book, self.stock(book)))
+            .filter(|&(_, stock)| stock <= threshold)
+            .collect();
+        low.sort();
+        low
+    }
+}
+
+// Whether `entry` comes before every day in `days`.
+fn entry_before(days: &impl RangeBounds<Day>, entry: &Entry) -> bool {
+    use std::ops::Bound;
+    match days.start_bound() {
+        Bound::Included(start) => entry.day < *start,
+        Bound::Excluded(start) => entry.day <= *start,
+        Bound::Unbounded => false,
+    }
+}
+
+#[cfg(test)]
+fn setup() -> (Library, Inventory, BookId, BookId) {
+    let mut library = Library::new();
+    let dune = library.add_book(Book::new("Dune", 1965).with_tag("classic"));
+    let hobbit = library.add_book(Book::new("The Hobbit", 1937));
+    let mut inventory = Inventory::new();
+    inventory.set_price(&library, dune, Money(1299)).unwrap();
+    inventory.set_price(&library, hobbit, Money(899)).unwrap();
+    inventory
+        .restock(&library, dune, 10, Money(600), Day(0))
+        .unwrap();
+    inventory
+        .restock(&library, hobbit, 5, Money(400), Day(0))
+        .unwrap();
+    (library, inventory, dune, hobbit)
+}
+
+#[test]
+fn test_money_display() {
+    assert_eq!(Money(1299).to_string(), "12.99");
+    assert_eq!(Money(5).to_string(), "0.05");
+    assert_eq!(Money(1299).percent(10), Money(129));
+}
+
+#[test]
+fn test_money_overflow() {
+    assert_eq!(Money(u64::MAX).checked_times(2), None);
+    assert_eq!(
+        Money(u64::MAX / 2).checked_times(2),
+        Some(Money(u64::MAX - 1))
+    );
+    assert_eq!(Money(u64::MAX).checked_add(Money(1)), None);
+    assert_eq!(Money(u64::MAX) + Money(1), Money(u64::MAX));
+    assert_eq!(Money(u64::MAX).percent(100), Money(u64::MAX));
+    assert_eq!(Money(u64::MAX).percent(50), Money(u64::MAX / 2));
+}
+
+#[test]
+fn test_overflowing_stock_and_sales() {
+    let (library, mut inventory, dune, hobbit) = setup();
+    assert_eq!(
+        inventory.restock(&library, dune, u32::MAX, Money(600), Day(1)),
+        Err(SalesError::StockOverflow(dune))
+    );
+    assert_eq!(inventory.stock(dune), 10);
+    inventory
+        .restock(&library, dune, u32::MAX - 10, Money(600), Day(1))
+        .unwrap();
+    assert_eq!(inventory.stock(dune), u32::MAX);
+    assert_eq!(
+        inventory.restock(&library, dune, 1, Money(600), Day(1)),
+        Err(SalesError::StockOverflow(dune))
+    );
+
+    inventory
+        .set_price(&library, hobbit, Money(u64::MAX / 2))
+        .unwrap();
+    assert_eq!(
+        inventory.sell(&library, hobbit, 3, Day(2)),
+        Err(SalesError::AmountOverflow(hobbit))
+    );
+    let sale = inventory.sell(&library, hobbit, 2, Day(2)).unwrap();
+    assert_eq!(sale.revenue(), Money(u64::MAX - 1));
+    assert_eq!(inventory.stock(hobbit), 3);
+}
+
+#[test]
+fn test_stock_follows_ledger() {
+    let (library, mut inventory, dune, hobbit) = setup();
+    inventory.sell(&library, dune, 3, Day(1)).unwrap();
+    inventory
+        .restock(&library, dune, 2, Money(600), Day(2))
+        .unwrap();
+    inventory.sell(&library, dune, 4, Day(3)).unwrap();
+    assert_eq!(inventory.stock(dune), 5);
+    assert_eq!(inventory.stock(hobbit), 5);
+    assert_eq!(inventory.ledger().len(), 5);
+
+    assert_eq!(
+        inventory.sell(&library, hobbit, 6, Day(3)),
+        Err(SalesError::OutOfStock {
+            book: hobbit,
+            requested: 6,
+            available: 5
+        })
+    );
+    assert_eq!(
+        inventory.sell(&library, hobbit, 1, Day(2)),
+        Err(SalesError::OutOfOrder {
+            last: Day(3),
+            day: Day(2)
+        })
+    );
+    assert_eq!(
+        inventory.sell(&library, hobbit, 0, Day(3)),
+        Err(SalesError::ZeroQuantity)
+    );
+    // Failed sales leave no trace.
+    assert_eq!(inventory.ledger().len(), 5);
+}
+
+#[test]
+fn test_unknown_and_unpriced_books() {
+    let (mut library, mut inventory, _, _) = setup();
+    assert_eq!(
+        inventory.sell(&library, BookId(99), 1, Day(0)),
+        Err(SalesError::UnknownBook(BookId(99)))
+    );
+    let unpriced = library.add_book(Book::new("Emma", 1815));
+    inventory
+        .restock(&library, unpriced, 1, Money(300), Day(0))
+        .unwrap();
+    assert_eq!(
+        inventory.sell(&library, unpriced, 1, Day(0)),
+        Err(SalesError::NotForSale(unpriced))
+    );
+}
+
+#[test]
+fn test_best_discount_applies() {
+    let (library, mut inventory, dune, hobbit) = setup();
+    inventory.add_discount(TagDiscount {
+        tag: String::from("classic"),
+        percent: 10,
+    });
+    inventory.add_discount(BulkDiscount {
+        min_quantity: 3,
+        percent: 20,
+    });
+    inventory.add_discount(Promotion {
+        first: Day(10),
+        last: Day(12),
+        percent: 50,
+    });
+
+    // Only the tag discount applies.
+    assert_eq!(
+        inventory.sell(&library, dune, 1, Day(1)).unwrap().revenue(),
+        Money(1170)
+    );
+    // Tag and bulk both apply, and the larger wins.
+    assert_eq!(
+        inventory.sell(&library, dune, 3, Day(2)).unwrap().revenue(),
+        Money(3118)
+    );
+    assert_eq!(
+        inventory
+            .sell(&library, hobbit, 1, Day(3))
+            .unwrap()
+            .revenue(),
+        Money(899)
+    );
+    assert_eq!(
+        inventory
+            .sell(&library, hobbit, 1, Day(11))
+            .unwrap()
+            .revenue(),
+        Money(450)
+    );
+}
+
+#[test]
+fn test_price_changes_keep_history() {
+    let (library, mut inventory, dune, _) = setup();
+    inventory.sell(&library, dune, 1, Day(1)).unwrap();
+    inventory.set_price(&library, dune, Money(1499)).unwrap();
+    inventory.sell(&library, dune, 1, Day(2)).unwrap();
+    assert_eq!(inventory.revenue(..Day(2)), Money(1299));
+    assert_eq!(inventory.revenue(..), Money(1299 + 1499));
+}
+
+#[test]
+fn test_reports() {
+    let (library, mut inventory, dune, hobbit) = setup();
+    for (book, quantity, day) in [
+        (dune, 2, 1),
+        (hobbit, 1, 2),
+        (hobbit, 3, 8),
+        (dune, 1, 9),
+        (dune, 1, 15),
+    ] {
+        inventory.sell(&library, book, quantity, Day(day)).unwrap();
+    }
+
+    assert_eq!(
+        inventory.revenue_by_period(Day(0), Day(21), 7),
+        [
+            (Day(0), Money(2 * 1299 + 899)),
+            (Day(7), Money(3 * 899 + 1299)),
+            (Day(14), Money(1299)),
+        ]
+    );
+    assert_eq!(
+        inventory.revenue_by_period(Day(0), Day(5), 2),
+        [
+            (Day(0), Money(2 * 1299)),
+            (Day(2), Money(899)),
+            (Day(4), Money(0)),
+        ]
+    );
+
+    assert_eq!(inventory.best_sellers(.., 10), [(dune, 4), (hobbit, 4)]);
+    assert_eq!(inventory.best_sellers(Day(2)..Day(9), 1), [(hobbit, 4)]);
+    assert_eq!(inventory.best_sellers(Day(16).., 1), []);
+
+    assert_eq!(inventory.low_stock(1), [(hobbit, 1)]);
+    assert_eq!(inventory.low_stock(6), [(dune, 6), (hobbit, 1)]);
+}
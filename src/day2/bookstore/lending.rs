@@ -0,0 +1,616 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+use super::{BookId, Library};
+
+/// Calendar day, counted from an arbitrary epoch chosen by the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct Day(pub(super) u32);
+
+impl Day {
+    pub(super) fn plus(self, days: u32) -> Day {
+        Day(self.0 + days)
+    }
+
+    /// Whole days from `self` until `later`, zero if `later` is not later.
+    pub(super) fn days_until(self, later: Day) -> u32 {
+        later.0.saturating_sub(self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct MemberId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct LoanId(u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Member {
+    pub(super) name: String,
+    pub(super) suspended: bool,
+    /// Unpaid fines, in cents.
+    pub(super) fines: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Loan {
+    pub(super) book: BookId,
+    pub(super) member: MemberId,
+    pub(super) since: Day,
+    pub(super) due: Day,
+    pub(super) renewals: u32,
+}
+
+/// Computes the fine, in cents, owed for returning `loan` on `returned`.
+pub(super) trait FinePolicy {
+    fn fine(&self, loan: &Loan, returned: Day) -> u32;
+}
+
+/// A fixed amount per day overdue, after a grace period, up to a cap.
+#[derive(Debug, Clone)]
+pub(super) struct DailyFine {
+    pub(super) per_day: u32,
+    pub(super) grace_days: u32,
+    pub(super) cap: u32,
+}
+
+impl FinePolicy for DailyFine {
+    fn fine(&self, loan: &Loan, returned: Day) -> u32 {
+        let late = loan.due.days_until(returned);
+        if late <= self.grace_days {
+            return 0;
+        }
+        late.saturating_mul(self.per_day).min(self.cap)
+    }
+}
+
+/// Limits applied to every member.
+#[derive(Debug, Clone)]
+pub(super) struct Rules {
+    pub(super) loan_days: u32,
+    pub(super) max_renewals: u32,
+    pub(super) max_loans: usize,
+    pub(super) max_holds: usize,
+    /// Members owing this much, in cents, can't borrow until they pay.
+    pub(super) max_fines: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            loan_days: 21,
+            max_renewals: 2,
+            max_loans: 5,
+            max_holds: 3,
+            max_fines: 1000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum LendingError {
+    UnknownBook(BookId),
+    UnknownMember(MemberId),
+    UnknownLoan(LoanId),
+    MemberSuspended(MemberId),
+    FinesOutstanding { member: MemberId, fines: u32 },
+    AlreadyCheckedOut { book: BookId, member: MemberId },
+    NoCopyAvailable(BookId),
+    LoanLimitReached(MemberId),
+    AlreadyOnHold { book: BookId, member: MemberId },
+    HoldLimitReached(MemberId),
+    RenewalLimitReached(LoanId),
+    OnHoldForOthers(BookId),
+    Overdue(LoanId),
+}
+
+impl fmt::Display for LendingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LendingError::UnknownBook(BookId(id)) => write!(f, "no book with id {id}"),
+            LendingError::UnknownMember(MemberId(id)) => write!(f, "no member with id {id}"),
+            LendingError::UnknownLoan(LoanId(id)) => write!(f, "no active loan with id {id}"),
+            LendingError::MemberSuspended(MemberId(id)) => write!(f, "member {id} is suspended"),
+            LendingError::FinesOutstanding {
+                member: MemberId(id),
+                fines,
+            } => write!(f, "member {id} owes {fines} cents in fines"),
+            LendingError::AlreadyCheckedOut {
+                book: BookId(book),
+                member: MemberId(member),
+            } => write!(f, "member {member} already has book {book}"),
+            LendingError::NoCopyAvailable(BookId(id)) => {
+                write!(f, "no copy of book {id} is available")
+            }
+            LendingError::LoanLimitReached(MemberId(id)) => {
+                write!(f, "member {id} has reached the loan limit")
+            }
+            LendingError::AlreadyOnHold {
+                book: BookId(book),
+                member: MemberId(member),
+            } => write!(f, "member {member} already has a hold on book {book}"),
+            LendingError::HoldLimitReached(MemberId(id)) => {
+                write!(f, "member {id} has reached the hold limit")
+            }
+            LendingError::RenewalLimitReached(LoanId(id)) => {
+                write!(f, "loan {id} can't be renewed again")
+            }
+            LendingError::OnHoldForOthers(BookId(id)) => {
+                write!(f, "book {id} is on hold for other members")
+            }
+            LendingError::Overdue(LoanId(id)) => {
+                write!(f, "loan {id} is overdue and must be returned")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LendingError {}
+
+/// Outcome of returning a book.
+#[derive(Debug, PartialEq)]
+pub(super) struct Returned {
+    pub(super) loan: Loan,
+    /// Fine added to the member's balance, in cents.
+    pub(super) fine: u32,
+    /// First member waiting for the book, who can now check it out.
+    pub(super) next_hold: Option<MemberId>,
+}
+
+/// Members, loans and holds for the books of a `Library`.
+///
+/// The library itself only knows how many copies of each book it owns;
+/// this tracks who has them.
+pub(super) struct Circulation<P: FinePolicy = DailyFine> {
+    rules: Rules,
+    fines: P,
+    members: BTreeMap<MemberId, Member>,
+    next_member: u32,
+    loans: BTreeMap<LoanId, Loan>,
+    next_loan: u64,
+    holds: HashMap<BookId, VecDeque<MemberId>>,
+}
+
+impl<P: FinePolicy> Circulation<P> {
+    pub(super) fn new(rules: Rules, fines: P) -> Self {
+        Circulation {
+            rules,
+            fines,
+            members: BTreeMap::new(),
+            next_member: 0,
+            loans: BTreeMap::new(),
+            next_loan: 0,
+            holds: HashMap::new(),
+        }
+    }
+
+    pub(super) fn add_member(&mut self, name: &str) -> MemberId {
+        let id = MemberId(self.next_member);
+        self.next_member += 1;
+        self.members.insert(
+            id,
+            Member {
+                name: String::from(name),
+                suspended: false,
+                fines: 0,
+            },
+        );
+        id
+    }
+
+    pub(super) fn member(&self, id: MemberId) -> Option<&Member> {
+        self.members.get(&id)
+    }
+
+    fn member_mut(&mut self, id: MemberId) -> Result<&mut Member, LendingError> {
+        self.members
+            .get_mut(&id)
+            .ok_or(LendingError::UnknownMember(id))
+    }
+
+    pub(super) fn set_suspended(
+        &mut self,
+        id: MemberId,
+        suspended: bool,
+    ) -> Result<(), LendingError> {
+        self.member_mut(id)?.suspended = suspended;
+        Ok(())
+    }
+
+    /// Pay off up to `amount` cents of fines, returning what remains owed.
+    pub(super) fn pay(&mut self, id: MemberId, amount: u32) -> Result<u32, LendingError> {
+        let member = self.member_mut(id)?;
+        member.fines = member.fines.saturating_sub(amount);
+        Ok(member.fines)
+    }
+
+    pub(super) fn loan(&self, id: LoanId) -> Option<&Loan> {
+        self.loans.get(&id)
+    }
+
+    pub(super) fn loans_of(&self, member: MemberId) -> impl Iterator<Item = (LoanId, &Loan)> {
+        self.loans
+            .iter()
+            .filter(move |(_, loan)| loan.member == member)
+            .map(|(&id, loan)| (id, loan))
+    }
+
+    pub(super) fn overdue(&self, today: Day) -> impl Iterator<Item = (LoanId, &Loan)> {
+        self.loans
+            .iter()
+            .filter(move |(_, loan)| loan.due < today)
+            .map(|(&id, loan)| (id, loan))
+    }
+
+    /// Copies of `book` that are on the shelf, whether or not they are
+    /// reserved for members with holds.
+    pub(super) fn available(&self, library: &Library, book: BookId) -> Result<u32, LendingError> {
+        let copies = library
+            .get(book)
+            .ok_or(LendingError::UnknownBook(book))?
+            .copies;
+        let lent = self.loans.values().filter(|loan| loan.book == book).count() as u32;
+        Ok(copies.saturating_sub(lent))
+    }
+
+    pub(super) fn holds(&self, book: BookId) -> impl Iterator<Item = MemberId> + '_ {
+        self.holds.get(&book).into_iter().flatten().copied()
+    }
+
+    // Members in the hold queue ahead of `member`, or the whole queue if
+    // `member` isn't in it.
+    fn holds_ahead_of(&self, book: BookId, member: MemberId) -> usize {
+        let queue = self.holds.get(&book);
+        let mut holds = queue.into_iter().flatten();
+        holds
+            .position(|&m| m == member)
+            .unwrap_or_else(|| queue.map_or(0, VecDeque::len))
+    }
+
+    fn check_in_good_standing(&self, id: MemberId) -> Result<&Member, LendingError> {
+        let member = self
+            .members
+            .get(&id)
+            .ok_or(LendingError::UnknownMember(id))?;
+        if member.suspended {
+            return Err(LendingError::MemberSuspended(id));
+        }
+        if member.fines >= self.rules.max_fines {
+            return Err(LendingError::FinesOutstanding {
+                member: id,
+                fines: member.fines,
+            });
+        }
+        Ok(member)
+    }
+
+    pub(super) fn checkout(
+        &mut self,
+        library: &Library,
+        book: BookId,
+        member: MemberId,
+        today: Day,
+    ) -> Result<LoanId, LendingError> {
+        self.check_in_good_standing(member)?;
+        if self.loans_of(member).any(|(_, loan)| loan.book == book) {
+            return Err(LendingError::AlreadyCheckedOut { book, member });
+        }
+        if self.loans_of(member).count() >= self.rules.max_loans {
+            return Err(LendingError::LoanLimitReached(member));
+        }
+        // Copies on the shelf go to members with holds first, in order.
+        if self.available(library, book)? as usize <= self.holds_ahead_of(book, member) {
+            return Err(LendingError::NoCopyAvailable(book));
+        }
+
+        if let Some(queue) = self.holds.get_mut(&book) {
+            queue.retain(|&m| m != member);
+        }
+        let id = LoanId(self.next_loan);
+        self.next_loan += 1;
+        self.loans.insert(
+            id,
+            Loan {
+                book,
+                member,
+                since: today,
+                due: today.plus(self.rules.loan_days),
+                renewals: 0,
+            },
+        );
+        Ok(id)
+    }
+
+    pub(super) fn return_book(&mut self, id: LoanId, today: Day) -> Result<Returned, LendingError> {
+        let loan = self
+            .loans
+            .remove(&id)
+            .ok_or(LendingError::UnknownLoan(id))?;
+        let fine = self.fines.fine(&loan, today);
+        let member = self.member_mut(loan.member)?;
+        member.fines = member.fines.saturating_add(fine);
+        let next_hold = self.holds(loan.book).next();
+        Ok(Returned {
+            loan,
+            fine,
+            next_hold,
+        })
+    }
+
+    /// Extend the loan by another loan period from `today`. Overdue loans
+    /// can't be renewed, since that would move the date their fine is
+    /// counted from.
+    pub(super) fn renew(
+        &mut self,
+        library: &Library,
+        id: LoanId,
+        today: Day,
+    ) -> Result<Day, LendingError> {
+        let loan = self.loans.get(&id).ok_or(LendingError::UnknownLoan(id))?;
+        let (book, member) = (loan.book, loan.member);
+        if loan.due < today {
+            return Err(LendingError::Overdue(id));
+        }
+        if loan.renewals >= self.rules.max_renewals {
+            return Err(LendingError::RenewalLimitReached(id));
+        }
+        self.check_in_good_standing(member)?;
+        // Renewing is fine as long as everyone waiting can get another copy.
+        if self.available(library, book)? < self.holds(book).count() as u32 {
+            return Err(LendingError::OnHoldForOthers(book));
+        }
+
+        let loan = self.loans.get_mut(&id).expect("loan was found above");
+        loan.renewals += 1;
+        loan.due = today.plus(self.rules.loan_days);
+        Ok(loan.due)
+    }
+
+    /// Join the queue for `book`, returning the position in it.
+    pub(super) fn place_hold(
+        &mut self,
+        library: &Library,
+        book: BookId,
+        member: MemberId,
+    ) -> Result<usize, LendingError> {
+        library.get(book).ok_or(LendingError::UnknownBook(book))?;
+        self.check_in_good_standing(member)?;
+        if self.holds(book).any(|m| m == member) {
+            return Err(LendingError::AlreadyOnHold { book, member });
+        }
+        let held = self.holds.values().flatten().filter(|&&m| m == member);
+        if held.count() >= self.rules.max_holds {
+            return Err(LendingError::HoldLimitReached(member));
+        }
+
+        let queue = self.holds.entry(book).or_default();
+        queue.push_back(member);
+        Ok(queue.len() - 1)
+    }
+
+    pub(super) fn cancel_hold(&mut self, book: BookId, member: MemberId) -> bool {
+        let Some(queue) = self.holds.get_mut(&book) else {
+            return false;
+        };
+        let before = queue.len();
+        queue.retain(|&m| m != member);
+        let removed = queue.len() != before;
+        if queue.is_empty() {
+            self.holds.remove(&book);
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+fn setup() -> (Library, Circulation, BookId, MemberId, MemberId) {
+    use super::Book;
+
+    let mut library = Library::new();
+    let book = library.add_book(Book::new("Dune", 1965).with_copies(1));
+    let mut circulation = Circulation::new(
+        Rules::default(),
+        DailyFine {
+            per_day: 25,
+            grace_days: 2,
+            cap: 500,
+        },
+    );
+    let alice = circulation.add_member("Alice");
+    let bob = circulation.add_member("Bob");
+    (library, circulation, book, alice, bob)
+}
+
+#[test]
+fn test_checkout_and_return() {
+    let (library, mut circulation, book, alice, bob) = setup();
+    let loan = circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    assert_eq!(circulation.loan(loan).unwrap().due, Day(21));
+    assert_eq!(circulation.available(&library, book), Ok(0));
+    assert_eq!(
+        circulation.checkout(&library, book, alice, Day(1)),
+        Err(LendingError::AlreadyCheckedOut {
+            book,
+            member: alice
+        })
+    );
+    assert_eq!(
+        circulation.checkout(&library, book, bob, Day(1)),
+        Err(LendingError::NoCopyAvailable(book))
+    );
+
+    let returned = circulation.return_book(loan, Day(10)).unwrap();
+    assert_eq!(returned.fine, 0);
+    assert_eq!(returned.next_hold, None);
+    assert_eq!(circulation.available(&library, book), Ok(1));
+    assert_eq!(
+        circulation.return_book(loan, Day(10)),
+        Err(LendingError::UnknownLoan(loan))
+    );
+}
+
+#[test]
+fn test_unknown_ids() {
+    let (library, mut circulation, book, alice, _) = setup();
+    assert_eq!(
+        circulation.checkout(&library, BookId(99), alice, Day(0)),
+        Err(LendingError::UnknownBook(BookId(99)))
+    );
+    assert_eq!(
+        circulation.checkout(&library, book, MemberId(99), Day(0)),
+        Err(LendingError::UnknownMember(MemberId(99)))
+    );
+}
+
+#[test]
+fn test_overdue_fines() {
+    let (library, mut circulation, book, alice, _) = setup();
+    let loan = circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    assert_eq!(circulation.overdue(Day(21)).count(), 0);
+    assert_eq!(circulation.overdue(Day(22)).count(), 1);
+
+    // Two days late is within the grace period.
+    let returned = circulation.return_book(loan, Day(23)).unwrap();
+    assert_eq!(returned.fine, 0);
+
+    let loan = circulation
+        .checkout(&library, book, alice, Day(30))
+        .unwrap();
+    let returned = circulation.return_book(loan, Day(61)).unwrap();
+    assert_eq!(returned.fine, 250);
+    assert_eq!(circulation.member(alice).unwrap().fines, 250);
+
+    // The cap limits a single fine, and enough fines block borrowing.
+    for start in [100, 200] {
+        let loan = circulation
+            .checkout(&library, book, alice, Day(start))
+            .unwrap();
+        assert_eq!(
+            circulation.return_book(loan, Day(start + 90)).unwrap().fine,
+            500
+        );
+    }
+    assert_eq!(
+        circulation.checkout(&library, book, alice, Day(400)),
+        Err(LendingError::FinesOutstanding {
+            member: alice,
+            fines: 1250
+        })
+    );
+    assert_eq!(circulation.pay(alice, 1000), Ok(250));
+    assert!(circulation
+        .checkout(&library, book, alice, Day(400))
+        .is_ok());
+}
+
+#[test]
+fn test_suspended_member() {
+    let (library, mut circulation, book, alice, _) = setup();
+    circulation.set_suspended(alice, true).unwrap();
+    assert_eq!(
+        circulation.checkout(&library, book, alice, Day(0)),
+        Err(LendingError::MemberSuspended(alice))
+    );
+    assert_eq!(
+        circulation.place_hold(&library, book, alice),
+        Err(LendingError::MemberSuspended(alice))
+    );
+    circulation.set_suspended(alice, false).unwrap();
+    assert!(circulation.checkout(&library, book, alice, Day(0)).is_ok());
+}
+
+#[test]
+fn test_hold_queue() {
+    let (library, mut circulation, book, alice, bob) = setup();
+    let carol = circulation.add_member("Carol");
+    let loan = circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    assert_eq!(circulation.place_hold(&library, book, carol), Ok(0));
+    assert_eq!(circulation.place_hold(&library, book, bob), Ok(1));
+    assert_eq!(
+        circulation.place_hold(&library, book, bob),
+        Err(LendingError::AlreadyOnHold { book, member: bob })
+    );
+
+    // Holds block renewals, since nobody else could get a copy.
+    assert_eq!(
+        circulation.renew(&library, loan, Day(5)),
+        Err(LendingError::OnHoldForOthers(book))
+    );
+
+    let returned = circulation.return_book(loan, Day(7)).unwrap();
+    assert_eq!(returned.next_hold, Some(carol));
+    // The returned copy is kept for Carol, first in the queue.
+    assert_eq!(
+        circulation.checkout(&library, book, bob, Day(7)),
+        Err(LendingError::NoCopyAvailable(book))
+    );
+    assert_eq!(
+        circulation.checkout(&library, book, alice, Day(7)),
+        Err(LendingError::NoCopyAvailable(book))
+    );
+    circulation.checkout(&library, book, carol, Day(8)).unwrap();
+    assert_eq!(circulation.holds(book).collect::<Vec<_>>(), [bob]);
+
+    assert!(circulation.cancel_hold(book, bob));
+    assert!(!circulation.cancel_hold(book, bob));
+    assert_eq!(circulation.holds(book).count(), 0);
+}
+
+#[test]
+fn test_hold_limit() {
+    let (mut library, mut circulation, _, alice, _) = setup();
+    for i in 0..3 {
+        let book = library.add_book(super::Book::new(&format!("Volume {i}"), 2000));
+        circulation.place_hold(&library, book, alice).unwrap();
+    }
+    let book = library.add_book(super::Book::new("Volume 3", 2000));
+    assert_eq!(
+        circulation.place_hold(&library, book, alice),
+        Err(LendingError::HoldLimitReached(alice))
+    );
+}
+
+#[test]
+fn test_renewals() {
+    let (library, mut circulation, book, alice, _) = setup();
+    let loan = circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    assert_eq!(circulation.renew(&library, loan, Day(20)), Ok(Day(41)));
+    assert_eq!(circulation.renew(&library, loan, Day(40)), Ok(Day(61)));
+    assert_eq!(
+        circulation.renew(&library, loan, Day(60)),
+        Err(LendingError::RenewalLimitReached(loan))
+    );
+}
+
+#[test]
+fn test_overdue_loans_are_not_renewed() {
+    let (library, mut circulation, book, alice, _) = setup();
+    let loan = circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    // The due day itself is still in time.
+    assert_eq!(circulation.renew(&library, loan, Day(21)), Ok(Day(42)));
+    assert_eq!(
+        circulation.renew(&library, loan, Day(60)),
+        Err(LendingError::Overdue(loan))
+    );
+    assert_eq!(circulation.loan(loan).unwrap().due, Day(42));
+    assert_eq!(circulation.loan(loan).unwrap().renewals, 1);
+
+    // The fine is still counted from the due date it was overdue on.
+    let returned = circulation.return_book(loan, Day(60)).unwrap();
+    assert_eq!(returned.fine, (60 - 42) * 25);
+}
+
+#[test]
+fn test_loan_limit() {
+    let (mut library, mut circulation, _, alice, _) = setup();
+    for i in 0..5 {
+        let book = library.add_book(super::Book::new(&format!("Volume {i}"), 2000));
+        circulation.checkout(&library, book, alice, Day(0)).unwrap();
+    }
+    let book = library.add_book(super::Book::new("Volume 5", 2000));
+    assert_eq!(
+        circulation.checkout(&library, book, alice, Day(0)),
+        Err(LendingError::LoanLimitReached(alice))
+    );
+    assert_eq!(circulation.loans_of(alice).count(), 5);
+}
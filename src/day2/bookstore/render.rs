@@ -0,0 +1,247 @@
+// Book listings in several output formats.
+
+use std::fmt::{self, Write};
+use std::io;
+
+use super::interchange::csv_field;
+use super::Book;
+use crate::json::Value;
+
+/// Renders a list of books as text.
+pub(super) trait BookFormat {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result;
+}
+
+/// `#0 Title (1954)`, one book per line.
+pub(super) struct Plain;
+
+/// Columns padded to line up, with a header row.
+pub(super) struct Table;
+
+/// A GitHub-flavored Markdown table.
+pub(super) struct Markdown;
+
+/// Comma-separated values with a header row.
+pub(super) struct Csv;
+
+/// A JSON array of book objects on a single line.
+pub(super) struct Json;
+
+const COLUMNS: [&str; 4] = ["#", "Title", "Year", "Authors"];
+
+fn row(i: usize, book: &Book) -> [String; 4] {
+    [
+        i.to_string(),
+        book.title.clone(),
+        book.year.to_string(),
+        book.authors.join(", "),
+    ]
+}
+
+impl BookFormat for Plain {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result {
+        for (i, Book { title, year, .. }) in books.iter().enumerate() {
+            writeln!(out, "#{i} {title} ({year})")?;
+        }
+        Ok(())
+    }
+}
+
+impl BookFormat for Table {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result {
+        let rows: Vec<_> = books
+            .iter()
+            .enumerate()
+            .map(|(i, book)| row(i, book))
+            .collect();
+        let mut widths = COLUMNS.map(|column| column.chars().count());
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut line = |cells: [&str; 4]| -> fmt::Result {
+            let mut text = String::new();
+            for (i, (cell, width)) in cells.iter().zip(widths).enumerate() {
+                if i > 0 {
+                    text.push_str("  ");
+                }
+                // Numbers read better right-aligned.
+                if i == 0 || i == 2 {
+                    write!(text, "{cell:>width$}")?;
+                } else {
+                    write!(text, "{cell:<width$}")?;
+                }
+            }
+            writeln!(out, "{}", text.trim_end())
+        };
+        line(COLUMNS)?;
+        line(
+            widths
+                .map(|width| "-".repeat(width))
+                .each_ref()
+                .map(String::as_str),
+        )?;
+        for row in &rows {
+            line(row.each_ref().map(String::as_str))?;
+        }
+        Ok(())
+    }
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\n', " ")
+}
+
+impl BookFormat for Markdown {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(out, "| {} |", COLUMNS.join(" | "))?;
+        writeln!(out, "|--:|---|--:|---|")?;
+        for (i, book) in books.iter().enumerate() {
+            let cells = row(i, book).map(|cell| markdown_cell(&cell));
+            writeln!(out, "| {} |", cells.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+impl BookFormat for Csv {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+        for (i, book) in books.iter().enumerate() {
+            let cells = row(i, book).map(|cell| csv_field(&cell));
+            writeln!(out, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl BookFormat for Json {
+    fn render(&self, books: &[&Book], out: &mut dyn fmt::Write) -> fmt::Result {
+        let books = books.iter().map(|book| book.to_json()).collect();
+        writeln!(out, "{}", Value::Array(books))
+    }
+}
+
+/// Lets a `BookFormat` write to an `io::Write`, keeping the I/O error that
+/// `fmt::Write` has no room for.
+pub(super) struct IoAdapter<'a> {
+    pub(super) inner: &'a mut dyn io::Write,
+    pub(super) error: Option<io::Error>,
+}
+
+impl fmt::Write for IoAdapter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+fn render(format: &dyn BookFormat) -> String {
+    let library = super::sample_library();
+    let mut out = String::new();
+    library.render_books(format, &mut out).unwrap();
+    out
+}
+
+#[test]
+fn test_plain() {
+    assert_eq!(
+        render(&Plain),
+        "\
+#0 The Fellowship of the Ring (1954)
+#1 Alice's Adventures in Wonderland (1865)
+#2 Good Omens (1990)
+#3 The Hobbit (1937)
+"
+    );
+}
+
+#[test]
+fn test_table() {
+    assert_eq!(
+        render(&Table),
+        "\
+#  Title                             Year  Authors
+-  --------------------------------  ----  ----------------------------
+0  The Fellowship of the Ring        1954  J. R. R. Tolkien
+1  Alice's Adventures in Wonderland  1865  Lewis Carroll
+2  Good Omens                        1990  Terry Pratchett, Neil Gaiman
+3  The Hobbit                        1937  J. R. R. Tolkien
+"
+    );
+}
+
+#[test]
+fn test_markdown() {
+    let mut library = super::Library::new();
+    library.add_book(Book::new("Either|Or", 1843).with_author("Søren Kierkegaard"));
+    let mut out = String::new();
+    library.render_books(&Markdown, &mut out).unwrap();
+    assert_eq!(
+        out,
+        "\
+| # | Title | Year | Authors |
+|--:|---|--:|---|
+| 0 | Either\\|Or | 1843 | Søren Kierkegaard |
+"
+    );
+}
+
+#[test]
+fn test_csv() {
+    assert_eq!(
+        render(&Csv),
+        "\
+#,Title,Year,Authors
+0,The Fellowship of the Ring,1954,J. R. R. Tolkien
+1,Alice's Adventures in Wonderland,1865,Lewis Carroll
+2,Good Omens,1990,\"Terry Pratchett, Neil Gaiman\"
+3,The Hobbit,1937,J. R. R. Tolkien
+"
+    );
+}
+
+#[test]
+fn test_json() {
+    let mut library = super::Library::new();
+    library.add_book(Book::new("Dune", 1965).with_author("Frank Herbert"));
+    let mut out = String::new();
+    library.render_books(&Json, &mut out).unwrap();
+    assert_eq!(
+        out,
+        "[{\"title\":\"Dune\",\"year\":1965,\"authors\":[\"Frank Herbert\"],\"isbn\":null,\
+         \"publisher\":null,\"tags\":[],\"copies\":1,\"description\":null}]\n"
+    );
+}
+
+#[test]
+fn test_write_to_io() {
+    let library = super::sample_library();
+    let mut out = Vec::new();
+    library.write_books(&Plain, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), render(&Plain));
+}
+
+#[test]
+fn test_write_to_io_reports_errors() {
+    struct Broken;
+    impl io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let library = super::sample_library();
+    let err = library.write_books(&Table, &mut Broken).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,397 @@
+// Ranked full-text search over book titles and descriptions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use super::{Book, BookId, Library};
+
+// BM25 parameters, with the usual defaults.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Title words count this many times as much as description words.
+const TITLE_BOOST: f64 = 2.0;
+
+/// Score multiplier for terms matched with one and two typos.
+const TYPO_PENALTY: [f64; 3] = [1.0, 0.6, 0.3];
+
+/// Words around the first match shown in a description fragment.
+const FRAGMENT_WORDS: usize = 10;
+
+pub(super) const HIGHLIGHT_START: &str = "**";
+pub(super) const HIGHLIGHT_END: &str = "**";
+
+/// Reduce a word to a crude stem so that "dragons" and "dragon", or
+/// "walked" and "walking", find each other. Not a real stemmer, but cheap
+/// and predictable.
+fn stem(word: &str) -> String {
+    const SUFFIXES: [&str; 7] = ["ing", "edly", "ed", "ies", "es", "ly", "s"];
+    for suffix in SUFFIXES {
+        if let Some(base) = word.strip_suffix(suffix) {
+            // Keep short words alone: "is", "bus", "red".
+            if base.chars().count() >= 3 {
+                return match suffix {
+                    "ies" => format!("{base}y"),
+                    _ => base.to_owned(),
+                };
+            }
+        }
+    }
+    word.to_owned()
+}
+
+/// Words of `text` as (byte span, term) pairs, case folded and stemmed.
+fn tokens(text: &str) -> impl Iterator<Item = (Range<usize>, String)> + '_ {
+    let mut start = None;
+    text.char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+        .filter_map(move |(i, c)| match (start, c.is_alphanumeric()) {
+            (None, true) => {
+                start = Some(i);
+                None
+            }
+            (Some(s), false) => {
+                start = None;
+                Some((s..i, stem(&text[s..i].to_lowercase())))
+            }
+            _ => None,
+        })
+}
+
+/// Edit distance between `a` and `b` counting insertions, deletions,
+/// substitutions and swaps of adjacent characters, or `None` if it is
+/// above `max`.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    // Three rows of the dynamic programming table are enough for swaps.
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut cur = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(before[j - 2] + 1);
+            }
+        }
+        if cur.iter().min().is_some_and(|&min| min > max) {
+            return None;
+        }
+        before = std::mem::replace(&mut prev, cur);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+/// Typos tolerated in a query word: none for very short words, where one
+/// edit changes the meaning, and at most two.
+fn max_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hit {
+    pub(super) id: BookId,
+    pub(super) score: f64,
+    /// The title with matching words highlighted.
+    pub(super) title: String,
+    /// Part of the description around the first match, highlighted, if the
+    /// description matched.
+    pub(super) fragment: Option<String>,
+}
+
+/// Inverted index over titles and descriptions, scored with BM25.
+///
+/// The index is separate from the `Library`: build it with `build`, then
+/// keep it current with `insert` and `remove` as books change.
+#[derive(Debug, Default)]
+pub(super) struct SearchIndex {
+    /// Term to weighted frequency in each book containing it.
+    postings: HashMap<String, BTreeMap<BookId, f64>>,
+    /// Weighted number of terms in each book.
+    lengths: BTreeMap<BookId, f64>,
+    total_length: f64,
+}
+
+fn weighted_terms(book: &Book) -> HashMap<String, f64> {
+    let mut terms = HashMap::new();
+    for (_, term) in tokens(&book.title) {
+        *terms.entry(term).or_default() += TITLE_BOOST;
+    }
+    for (_, term) in tokens(book.description.as_deref().unwrap_or_default()) {
+        *terms.entry(term).or_default() += 1.0;
+    }
+    terms
+}
+
+impl SearchIndex {
+    pub(super) fn build(library: &Library) -> Self {
+        let mut index = SearchIndex::default();
+        for (&id, book) in &library.books {
+            index.insert(id, book);
+        }
+        index
+    }
+
+    pub(super) fn insert(&mut self, id: BookId, book: &Book) {
+        self.remove(id);
+        let terms = weighted_terms(book);
+        let length: f64 = terms.values().sum();
+        for (term, weight) in terms {
+            self.postings.entry(term).or_default().insert(id, weight);
+        }
+        self.lengths.insert(id, length);
+        self.total_length += length;
+    }
+
+    pub(super) fn remove(&mut self, id: BookId) {
+        let Some(length) = self.lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= length;
+        self.postings.retain(|_, books| {
+            books.remove(&id);
+            !books.is_empty()
+        });
+    }
+
+    /// Indexed terms close enough to `term`, with their distance.
+    fn expand(&self, term: &str) -> Vec<(&str, usize)> {
+        if self.postings.contains_key(term) {
+            // An exact match wins; typo matches would only add noise.
+            return vec![(self.postings.get_key_value(term).unwrap().0.as_str(), 0)];
+        }
+        let max = max_typos(term);
+        if max == 0 {
+            return Vec::new();
+        }
+        self.postings
+            .keys()
+            .filter_map(|candidate| {
+                edit_distance(term, candidate, max).map(|d| (candidate.as_str(), d))
+            })
+            .collect()
+    }
+
+    /// Books matching any word of `query`, best first, at most `limit`.
+    pub(super) fn search(&self, library: &Library, query: &str, limit: usize) -> Vec<Hit> {
+        let books = self.lengths.len() as f64;
+        if books == 0.0 {
+            return Vec::new();
+        }
+        let average_length = (self.total_length / books).max(1.0);
+
+        let mut scores: BTreeMap<BookId, f64> = BTreeMap::new();
+        let mut matched: BTreeMap<BookId, Vec<&str>> = BTreeMap::new();
+        for (_, query_term) in tokens(query) {
+            // A book scores once per query word, through its best match.
+            let mut best: BTreeMap<BookId, (f64, &str)> = BTreeMap::new();
+            for (term, distance) in self.expand(&query_term) {
+                let postings = &self.postings[term];
+                let df = postings.len() as f64;
+                let idf = (1.0 + (books - df + 0.5) / (df + 0.5)).ln();
+                for (&id, &tf) in postings {
+                    let norm = K1 * (1.0 - B + B * self.lengths[&id] / average_length);
+                    let score = TYPO_PENALTY[distance] * idf * tf * (K1 + 1.0) / (tf + norm);
+                    let entry = best.entry(id).or_insert((0.0, term));
+                    if score > entry.0 {
+                        *entry = (score, term);
+                    }
+                }
+            }
+            for (id, (score, term)) in best {
+                *scores.entry(id).or_default() += score;
+                matched.entry(id).or_default().push(term);
+            }
+        }
+
+        let mut ranked: Vec<(BookId, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+            .into_iter()
+            .take(limit)
+            .filter_map(|(id, score)| {
+                let book = library.get(id)?;
+                let terms = &matched[&id];
+                Some(Hit {
+                    id,
+                    score,
+                    title: highlight(&book.title, terms, None),
+                    fragment: book
+                        .description
+                        .as_deref()
+                        .filter(|text| tokens(text).any(|(_, t)| terms.contains(&t.as_str())))
+                        .map(|text| highlight(text, terms, Some(FRAGMENT_WORDS))),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Wrap the words of `text` whose terms are in `terms` in highlight
+/// markers. With `window`, keep only about that many words around the
+/// first match, marking cut ends with an ellipsis.
+fn highlight(text: &str, terms: &[&str], window: Option<usize>) -> String {
+    let words: Vec<(Range<usize>, bool)> = tokens(text)
+        .map(|(span, term)| (span, terms.contains(&term.as_str())))
+        .collect();
+
+    let (mut from, mut to) = (0, text.len());
+    if let Some(window) = window.filter(|&w| w < words.len()) {
+        let first = words.iter().position(|(_, hit)| *hit).unwrap_or(0);
+        let start = first.saturating_sub(window / 3).min(words.len() - window);
+        let end = start + window;
+        if start > 0 {
+            from = words[start].0.start;
+        }
+        if end < words.len() {
+            to = words[end - 1].0.end;
+        }
+    }
+
+    let mut out = String::new();
+    if from > 0 {
+        out.push('…');
+    }
+    let mut pos = from;
+    for (span, hit) in &words {
+        if !hit || span.start < from || span.end > to {
+            continue;
+        }
+        out.push_str(&text[pos..span.start]);
+        out.push_str(HIGHLIGHT_START);
+        out.push_str(&text[span.clone()]);
+        out.push_str(HIGHLIGHT_END);
+        pos = span.end;
+    }
+    out.push_str(&text[pos..to]);
+    if to < text.len() {
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+fn search_library() -> Library {
+    let mut library = Library::new();
+    library.add_book(Book::new("The Hobbit", 1937).with_description(
+        "Bilbo Baggins is swept into a quest to reclaim the dwarves' treasure \
+         from the dragon Smaug, who sleeps under the Lonely Mountain.",
+    ));
+    library.add_book(Book::new("Dragon's Egg", 1980).with_description(
+        "Life evolves on the surface of a neutron star, where time runs a \
+         million times faster than for the humans watching it.",
+    ));
+    library.add_book(Book::new("A Wizard of Earthsea", 1968).with_description(
+        "A young wizard unleashes a shadow on the world and must hunt it \
+         down across the islands of Earthsea, facing dragons on the way.",
+    ));
+    library.add_book(Book::new("Dune", 1965));
+    library
+}
+
+#[test]
+fn test_stem() {
+    assert_eq!(stem("dragons"), "dragon");
+    assert_eq!(stem("walking"), "walk");
+    assert_eq!(stem("walked"), "walk");
+    assert_eq!(stem("stories"), "story");
+    assert_eq!(stem("is"), "is");
+    assert_eq!(stem("bus"), "bus");
+}
+
+#[test]
+fn test_tokens() {
+    let tokens: Vec<_> = tokens("The Dragons' EGG, re-hatched").collect();
+    assert_eq!(
+        tokens,
+        [
+            (0..3, String::from("the")),
+            (4..11, String::from("dragon")),
+            (13..16, String::from("egg")),
+            (18..20, String::from("re")),
+            (21..28, String::from("hatch")),
+        ]
+    );
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("dragon", "dragon", 2), Some(0));
+    assert_eq!(edit_distance("dragon", "dragn", 2), Some(1));
+    assert_eq!(edit_distance("dragon", "dargon", 2), Some(1));
+    assert_eq!(edit_distance("wizard", "wizzrd", 2), Some(1));
+    assert_eq!(edit_distance("earthsea", "erthsee", 2), Some(2));
+    assert_eq!(edit_distance("dragon", "wizard", 2), None);
+    assert_eq!(edit_distance("a", "abcd", 2), None);
+    assert_eq!(edit_distance("", "ab", 2), Some(2));
+}
+
+#[test]
+fn test_search_ranks_title_matches_first() {
+    let library = search_library();
+    let index = SearchIndex::build(&library);
+    let hits = index.search(&library, "dragons", 10);
+    let titles: Vec<_> = hits.iter().map(|hit| hit.title.as_str()).collect();
+    // The title match outranks the description matches.
+    assert_eq!(titles.len(), 3);
+    assert_eq!(titles[0], "**Dragon**'s Egg");
+    assert!(titles.contains(&"The Hobbit"));
+    assert!(titles.contains(&"A Wizard of Earthsea"));
+    assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+
+    assert_eq!(hits[0].fragment, None);
+    let hobbit = hits.iter().find(|hit| hit.title == "The Hobbit").unwrap();
+    assert_eq!(
+        hobbit.fragment.as_deref(),
+        Some("…treasure from the **dragon** Smaug, who sleeps under the Lonely…")
+    );
+}
+
+#[test]
+fn test_search_tolerates_typos() {
+    let library = search_library();
+    let index = SearchIndex::build(&library);
+    let hits = index.search(&library, "wizzard erthsea", 10);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].title, "A **Wizard** of **Earthsea**");
+
+    // Short words must match exactly.
+    assert!(index.search(&library, "dun", 10).is_empty());
+    assert_eq!(index.search(&library, "DUNE", 10).len(), 1);
+}
+
+#[test]
+fn test_search_limit_and_misses() {
+    let library = search_library();
+    let index = SearchIndex::build(&library);
+    assert_eq!(index.search(&library, "the dragon", 2).len(), 2);
+    assert!(index.search(&library, "spaceship", 10).is_empty());
+    assert!(index.search(&library, "", 10).is_empty());
+    assert!(SearchIndex::default()
+        .search(&library, "dune", 10)
+        .is_empty());
+}
+
+#[test]
+fn test_search_index_updates() {
+    let mut library = search_library();
+    let mut index = SearchIndex::build(&library);
+    let id = library.add_book(Book::new("Dragonflight", 1968));
+    index.insert(id, library.get(id).unwrap());
+    assert_eq!(index.search(&library, "dragonflight", 10)[0].id, id);
+
+    library.remove_book(id).unwrap();
+    index.remove(id);
+    assert!(index.search(&library, "dragonflight", 10).is_empty());
+    assert_eq!(index.lengths.len(), 4);
+}
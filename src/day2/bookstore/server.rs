@@ -0,0 +1,542 @@
+// A JSON API for a `Library` over HTTP/1.1.
+//
+//   GET    /v1/books[?author=NAME&tag=TAG]   list books, optionally filtered
+//   POST   /v1/books                         add a book, which needs an ISBN
+//   GET    /v1/books/{isbn}                  one book
+//   PUT    /v1/books/{isbn}                  replace a book
+//   DELETE /v1/books/{isbn}                  remove a book
+//
+// Books are sent and received in the format of `Book::to_json`. Each
+// connection carries one request and is served on its own thread; the
+// catalog is a `SharedLibrary`, so readers never wait for writers.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use super::shared::{SharedError, SharedLibrary};
+use super::{Book, Library, LibraryError};
+use crate::day2::router::prefix_matches;
+use crate::json::{self, Value};
+
+const USAGE: &str = "usage: foobar serve-books [ADDR] [--import FILE.jsonl]";
+const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// Largest request body accepted.
+const MAX_BODY: usize = 1 << 20;
+/// Largest request line plus headers accepted.
+const MAX_HEAD: u64 = 16 << 10;
+/// How long a connection may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    body: Option<Value>,
+    location: Option<String>,
+}
+
+impl Response {
+    fn json(status: u16, body: Value) -> Response {
+        Response {
+            status,
+            body: Some(body),
+            location: None,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response::json(status, Value::object([("error", message.into().into())]))
+    }
+
+    fn not_found() -> Response {
+        Response::error(404, "not found")
+    }
+
+    fn method_not_allowed() -> Response {
+        Response::error(405, "method not allowed")
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let body = self
+            .body
+            .as_ref()
+            .map_or_else(String::new, |body| format!("{body}\n"));
+        write!(out, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        if self.body.is_some() {
+            write!(out, "Content-Type: application/json\r\n")?;
+        }
+        if let Some(location) = &self.location {
+            write!(out, "Location: {location}\r\n")?;
+        }
+        write!(
+            out,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        out.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Unknown",
+    }
+}
+
+// Decode `%XX` escapes, as used in URL paths.
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = tail
+                    .get(..2)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+                let hex = std::str::from_utf8(hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+                continue;
+            }
+            _ => bytes.push(byte),
+        }
+        rest = tail;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// Decode a query string value, where `+` also stands for a space.
+fn query_decode(text: &str) -> Option<String> {
+    percent_decode(&text.replace('+', " "))
+}
+
+// Read one request, or the response to send instead if it is malformed.
+fn read_request(reader: &mut impl BufRead) -> Result<Request, Response> {
+    let bad_request = |message: &str| Response::error(400, message);
+    let mut head = reader.take(MAX_HEAD);
+    let mut line = String::new();
+    let read_line = |head: &mut io::Take<_>, line: &mut String| -> Result<(), Response> {
+        line.clear();
+        match BufRead::read_line(head, line) {
+            Ok(_) if line.ends_with('\n') => Ok(()),
+            _ => Err(bad_request("incomplete request")),
+        }
+    };
+
+    read_line(&mut head, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad_request("malformed request line"));
+    };
+    let method = method.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut content_length = 0;
+    loop {
+        read_line(&mut head, &mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad_request("malformed header"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| bad_request("invalid Content-Length"))?;
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(Response::error(413, "request body too large"));
+    }
+    let mut body = vec![0; content_length];
+    head.into_inner()
+        .read_exact(&mut body)
+        .map_err(|_| bad_request("incomplete body"))?;
+    Ok(Request {
+        method,
+        path,
+        query,
+        body,
+    })
+}
+
+fn parse_book(body: &[u8]) -> Result<Book, Response> {
+    let text = std::str::from_utf8(body).map_err(|_| Response::error(400, "body is not UTF-8"))?;
+    let value = json::parse(text).map_err(|err| Response::error(400, err.to_string()))?;
+    Book::from_json(&value).map_err(|err| Response::error(400, err))
+}
+
+fn library_error(err: SharedError) -> Response {
+    match err {
+        SharedError::Library(LibraryError::NotFound(_)) => Response::not_found(),
+        err => Response::error(409, err.to_string()),
+    }
+}
+
+fn list_books(library: &SharedLibrary, query: &str) -> Response {
+    let mut author = None;
+    let mut tag = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let Some(value) = query_decode(value) else {
+            return Response::error(400, "malformed query string");
+        };
+        match name {
+            "author" => author = Some(value),
+            "tag" => tag = Some(value),
+            _ => return Response::error(400, format!("unknown parameter {name}")),
+        }
+    }
+
+    let snapshot = library.snapshot();
+    let books = snapshot
+        .library
+        .books()
+        .filter(|book| author.as_ref().is_none_or(|author| book.has_author(author)))
+        .filter(|book| tag.as_ref().is_none_or(|tag| book.has_tag(tag)))
+        .map(Book::to_json)
+        .collect();
+    Response::json(200, Value::Array(books))
+}
+
+fn add_book(library: &SharedLibrary, body: &[u8]) -> Response {
+    let book = match parse_book(body) {
+        Ok(book) => book,
+        Err(response) => return response,
+    };
+    let Some(isbn) = book.isbn.clone() else {
+        return Response::error(400, "missing isbn");
+    };
+    let json = book.to_json();
+    match library.add_book(book) {
+        Ok(_) => Response {
+            location: Some(format!("/v1/books/{isbn}")),
+            ..Response::json(201, json)
+        },
+        Err(err) => library_error(err),
+    }
+}
+
+fn book_by_isbn(library: &SharedLibrary, request: &Request, isbn: &str) -> Response {
+    let snapshot = library.snapshot();
+    let Some(id) = snapshot.library.indexes.isbn(isbn) else {
+        return Response::not_found();
+    };
+    match request.method.as_str() {
+        "GET" => Response::json(200, snapshot.library.books[&id].to_json()),
+        "PUT" => {
+            let mut book = match parse_book(&request.body) {
+                Ok(book) => book,
+                Err(response) => return response,
+            };
+            if book.isbn.as_deref().is_some_and(|other| other != isbn) {
+                return Response::error(400, "isbn does not match the URL");
+            }
+            book.isbn = Some(isbn.to_string());
+            let json = book.to_json();
+            match library.update_book(id, |old| *old = book) {
+                Ok(_) => Response::json(200, json),
+                Err(err) => library_error(err),
+            }
+        }
+        "DELETE" => match library.remove_book(id) {
+            Ok(_) => Response {
+                status: 204,
+                body: None,
+                location: None,
+            },
+            Err(err) => library_error(err),
+        },
+        _ => Response::method_not_allowed(),
+    }
+}
+
+fn route(library: &SharedLibrary, request: &Request) -> Response {
+    let path = request.path.as_str();
+    // `prefix_matches` also accepts longer paths, so the number of segments
+    // is checked too.
+    let segments = path.split('/').count();
+    if prefix_matches("/v1/books/*", path) && segments == 4 {
+        match path.rsplit('/').next().and_then(percent_decode) {
+            Some(isbn) if !isbn.is_empty() => book_by_isbn(library, request, &isbn),
+            _ => Response::not_found(),
+        }
+    } else if prefix_matches("/v1/books", path) && segments == 3 {
+        match request.method.as_str() {
+            "GET" => list_books(library, &request.query),
+            "POST" => add_book(library, &request.body),
+            _ => Response::method_not_allowed(),
+        }
+    } else {
+        Response::not_found()
+    }
+}
+
+fn handle(stream: TcpStream, library: &SharedLibrary) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let response = match read_request(&mut reader) {
+        Ok(request) => route(library, &request),
+        Err(response) => response,
+    };
+    response.write_to(&mut &stream)
+}
+
+pub(super) struct Server {
+    listener: TcpListener,
+    library: SharedLibrary,
+}
+
+impl Server {
+    pub(super) fn bind(addr: impl ToSocketAddrs, library: SharedLibrary) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            library,
+        })
+    }
+
+    pub(super) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve connections forever.
+    pub(super) fn run(self) {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let library = self.library.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = handle(stream, &library) {
+                            eprintln!("connection failed: {err}");
+                        }
+                    });
+                }
+                Err(err) => eprintln!("accept failed: {err}"),
+            }
+        }
+    }
+}
+
+fn serve(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut addr = String::from(DEFAULT_ADDR);
+    let mut library = Library::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--import" => {
+                let path = args.next().ok_or("--import needs a file")?;
+                let input =
+                    std::fs::read_to_string(&path).map_err(|err| format!("{path}: {err}"))?;
+                let report = super::interchange::import_json_lines(&mut library, &input);
+                for rejected in &report.rejected {
+                    eprintln!("{path}:{}: {}", rejected.line, rejected.error);
+                }
+            }
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}\n{USAGE}")),
+            _ => addr = arg,
+        }
+    }
+
+    let server = Server::bind(&addr, SharedLibrary::new(library))
+        .map_err(|err| format!("can't listen on {addr}: {err}"))?;
+    let addr = server.local_addr().map_err(|err| err.to_string())?;
+    eprintln!(
+        "serving {} books on http://{addr}/v1/books",
+        server.library.snapshot().library.len()
+    );
+    server.run();
+    Ok(())
+}
+
+/// Entry point for `foobar serve-books`; `args` excludes the subcommand.
+pub fn main(args: impl Iterator<Item = String>) {
+    if let Err(message) = serve(args) {
+        eprintln!("{message}");
+        std::process::exit(2);
+    }
+}
+
+// Start a server for the sample library on an ephemeral port.
+#[cfg(test)]
+fn start() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0", SharedLibrary::new(super::sample_library())).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+// Send a raw request and return the status and parsed body.
+#[cfg(test)]
+fn send(addr: SocketAddr, request: &str) -> (u16, Option<Value>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = (!body.is_empty()).then(|| json::parse(body.trim_end()).unwrap());
+    (status, body)
+}
+
+#[cfg(test)]
+fn call(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Option<Value>) {
+    send(
+        addr,
+        &format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ),
+    )
+}
+
+#[cfg(test)]
+fn titles(books: &Value) -> Vec<&str> {
+    books
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|book| book.get("title").and_then(Value::as_str).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_list_books() {
+    let addr = start();
+    let (status, body) = call(addr, "GET", "/v1/books", "");
+    assert_eq!(status, 200);
+    assert_eq!(titles(&body.unwrap()).len(), 4);
+
+    let (status, body) = call(addr, "GET", "/v1/books?author=J.+R.+R.%20Tolkien", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        titles(&body.unwrap()),
+        ["The Fellowship of the Ring", "The Hobbit"]
+    );
+    let (_, body) = call(addr, "GET", "/v1/books?tag=comedy", "");
+    assert_eq!(titles(&body.unwrap()), ["Good Omens"]);
+    assert_eq!(call(addr, "GET", "/v1/books?sort=year", "").0, 400);
+}
+
+#[test]
+fn test_get_book() {
+    let addr = start();
+    let (status, body) = call(addr, "GET", "/v1/books/978-0-14-143976-1", "");
+    assert_eq!(status, 200);
+    let body = body.unwrap();
+    assert_eq!(
+        body.get("title").and_then(Value::as_str),
+        Some("Alice's Adventures in Wonderland")
+    );
+    assert_eq!(call(addr, "GET", "/v1/books/978-0-00-000000-0", "").0, 404);
+    assert_eq!(
+        call(addr, "GET", "/v1/books/978-0-14-143976-1/extra", "").0,
+        404
+    );
+    assert_eq!(call(addr, "GET", "/v1/authors", "").0, 404);
+}
+
+#[test]
+fn test_add_update_and_remove() {
+    let addr = start();
+    let dune = r#"{"title":"Dune","year":1965,"isbn":"978-0-441-17271-9"}"#;
+    let response = send(
+        addr,
+        &format!(
+            "POST /v1/books HTTP/1.1\r\nContent-Length: {}\r\n\r\n{dune}",
+            dune.len()
+        ),
+    );
+    assert_eq!(response.0, 201);
+    assert_eq!(call(addr, "POST", "/v1/books", dune).0, 409);
+
+    let revised = r#"{"title":"Dune","year":1965,"copies":4}"#;
+    let (status, body) = call(addr, "PUT", "/v1/books/978-0-441-17271-9", revised);
+    assert_eq!(status, 200);
+    assert_eq!(body.unwrap().get("copies").and_then(Value::as_u64), Some(4));
+    let (_, body) = call(addr, "GET", "/v1/books/978-0-441-17271-9", "");
+    assert_eq!(body.unwrap().get("copies").and_then(Value::as_u64), Some(4));
+
+    assert_eq!(
+        call(addr, "DELETE", "/v1/books/978-0-441-17271-9", ""),
+        (204, None)
+    );
+    assert_eq!(call(addr, "GET", "/v1/books/978-0-441-17271-9", "").0, 404);
+}
+
+#[test]
+fn test_bad_requests() {
+    let addr = start();
+    for (method, path, body) in [
+        ("POST", "/v1/books", "not json"),
+        ("POST", "/v1/books", r#"{"title":"Dune"}"#),
+        // Books are addressed by ISBN, so one is required.
+        ("POST", "/v1/books", r#"{"title":"Dune","year":1965}"#),
+        (
+            "PUT",
+            "/v1/books/978-0-14-143976-1",
+            r#"{"title":"Alice","year":1865,"isbn":"978-0-00-000000-0"}"#,
+        ),
+    ] {
+        let (status, body) = call(addr, method, path, body);
+        assert_eq!(status, 400, "{method} {path}");
+        assert!(body.unwrap().get("error").is_some());
+    }
+    assert_eq!(call(addr, "PATCH", "/v1/books", "").0, 405);
+    assert_eq!(send(addr, "nonsense\r\n\r\n").0, 400);
+    assert_eq!(
+        send(
+            addr,
+            &format!(
+                "POST /v1/books HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY + 1
+            )
+        )
+        .0,
+        413
+    );
+}
+
+#[test]
+fn test_deeply_nested_body() {
+    let addr = start();
+    let (status, body) = call(addr, "POST", "/v1/books", &"[".repeat(200_000));
+    assert_eq!(status, 400);
+    assert!(body.unwrap().get("error").is_some());
+    // The server is still up.
+    assert_eq!(call(addr, "GET", "/v1/books", "").0, 200);
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("a+b%2Fc").as_deref(), Some("a+b/c"));
+    assert_eq!(query_decode("a+b%2Fc%2B").as_deref(), Some("a b/c+"));
+    assert_eq!(percent_decode("%zz"), None);
+    assert_eq!(percent_decode("%4"), None);
+    assert_eq!(percent_decode("%+1"), None);
+}
@@ -0,0 +1,336 @@
+// A `Library` shared between threads.
+//
+// Readers take an immutable snapshot, an `Arc` of the library as of some
+// write, and can keep iterating it for as long as they like. Writers are
+// serialized, build the next version on a copy and publish it by swapping
+// the `Arc`, so they never wait for readers and readers never see a
+// half-applied change. The copy makes writes O(n) in the catalog size:
+// this suits catalogs that are read far more often than they change.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use super::{Book, BookId, Library, LibraryError};
+
+/// Number of committed changes to a book. Starts at 1 when it is added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Version(u64);
+
+/// Immutable view of the library at one point in time.
+#[derive(Clone)]
+pub(super) struct Snapshot {
+    pub(super) library: Library,
+    versions: HashMap<BookId, Version>,
+}
+
+impl Snapshot {
+    #[allow(dead_code)]
+    pub(super) fn get(&self, id: BookId) -> Option<(&Book, Version)> {
+        Some((self.library.get(id)?, self.versions[&id]))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum SharedError {
+    /// The book changed since `expected` was read.
+    #[allow(dead_code)]
+    Conflict {
+        id: BookId,
+        expected: Version,
+        actual: Version,
+    },
+    Library(LibraryError),
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SharedError::Conflict {
+                id: BookId(id),
+                expected: Version(expected),
+                actual: Version(actual),
+            } => write!(
+                f,
+                "book {id} is at version {actual}, expected version {expected}"
+            ),
+            SharedError::Library(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for SharedError {}
+
+impl From<LibraryError> for SharedError {
+    fn from(err: LibraryError) -> Self {
+        SharedError::Library(err)
+    }
+}
+
+struct Inner {
+    current: RwLock<Arc<Snapshot>>,
+    // Held for the whole of a write, so writers don't build competing
+    // copies and lose each other's changes.
+    writer: Mutex<()>,
+}
+
+/// Cloneable, thread-safe handle to a library.
+#[derive(Clone)]
+pub(super) struct SharedLibrary {
+    inner: Arc<Inner>,
+}
+
+impl SharedLibrary {
+    pub(super) fn new(library: Library) -> Self {
+        let versions = library.books.keys().map(|&id| (id, Version(1))).collect();
+        SharedLibrary {
+            inner: Arc::new(Inner {
+                current: RwLock::new(Arc::new(Snapshot { library, versions })),
+                writer: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// The latest committed state. Later writes don't affect it.
+    pub(super) fn snapshot(&self) -> Arc<Snapshot> {
+        // A panicking writer never publishes a partial change, so the
+        // current snapshot is still good after a poisoning.
+        Arc::clone(
+            &self
+                .inner
+                .current
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    // Apply `change` to a copy of the latest snapshot and publish the copy
+    // if `change` succeeds.
+    fn write<R>(
+        &self,
+        change: impl FnOnce(&mut Snapshot) -> Result<R, SharedError>,
+    ) -> Result<R, SharedError> {
+        let _writer = self
+            .inner
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut next = Snapshot::clone(&self.snapshot());
+        let result = change(&mut next)?;
+        *self
+            .inner
+            .current
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Arc::new(next);
+        Ok(result)
+    }
+
+    pub(super) fn add_book(&self, book: Book) -> Result<BookId, SharedError> {
+        self.write(|snapshot| {
+            let id = snapshot.library.try_add_book(book)?;
+            snapshot.versions.insert(id, Version(1));
+            Ok(id)
+        })
+    }
+
+    pub(super) fn remove_book(&self, id: BookId) -> Result<Book, SharedError> {
+        self.write(|snapshot| {
+            let book = snapshot.library.remove_book(id)?;
+            snapshot.versions.remove(&id);
+            Ok(book)
+        })
+    }
+
+    /// Update a book unconditionally, returning its new version.
+    pub(super) fn update_book(
+        &self,
+        id: BookId,
+        change: impl FnOnce(&mut Book),
+    ) -> Result<Version, SharedError> {
+        self.write(|snapshot| {
+            snapshot.library.update_book(id, change)?;
+            let version = snapshot.versions.get_mut(&id).expect("book was updated");
+            version.0 += 1;
+            Ok(*version)
+        })
+    }
+
+    /// Update a book only if it is still at `expected`, the version it was
+    /// read at, so concurrent read-modify-write cycles can't silently
+    /// overwrite each other. On `Conflict`, re-read and try again.
+    #[allow(dead_code)]
+    pub(super) fn update_if(
+        &self,
+        id: BookId,
+        expected: Version,
+        change: impl FnOnce(&mut Book),
+    ) -> Result<Version, SharedError> {
+        self.write(|snapshot| {
+            let actual = *snapshot
+                .versions
+                .get(&id)
+                .ok_or(LibraryError::NotFound(id))?;
+            if actual != expected {
+                return Err(SharedError::Conflict {
+                    id,
+                    expected,
+                    actual,
+                });
+            }
+            snapshot.library.update_book(id, change)?;
+            let version = snapshot.versions.get_mut(&id).expect("book was updated");
+            version.0 += 1;
+            Ok(*version)
+        })
+    }
+}
+
+#[test]
+fn test_snapshots_are_isolated() {
+    let shared = SharedLibrary::new(super::sample_library());
+    let before = shared.snapshot();
+    let id = shared.add_book(Book::new("Dune", 1965)).unwrap();
+    shared
+        .update_book(BookId(0), |book| book.year = 2000)
+        .unwrap();
+
+    assert_eq!(before.library.len(), 4);
+    assert_eq!(before.library.get(BookId(0)).unwrap().year, 1954);
+    let after = shared.snapshot();
+    assert_eq!(after.library.len(), 5);
+    assert_eq!(
+        after.get(BookId(0)).map(|(b, v)| (b.year, v)),
+        Some((2000, Version(2)))
+    );
+    assert_eq!(after.get(id).map(|(_, v)| v), Some(Version(1)));
+}
+
+#[test]
+fn test_update_if_detects_conflicts() {
+    let shared = SharedLibrary::new(super::sample_library());
+    let version = shared.snapshot().get(BookId(1)).map(|(_, v)| v).unwrap();
+
+    let v2 = shared
+        .update_if(BookId(1), version, |book| book.copies = 5)
+        .unwrap();
+    assert_eq!(v2, Version(2));
+    // A second writer that read the same version loses.
+    assert_eq!(
+        shared.update_if(BookId(1), version, |book| book.copies = 7),
+        Err(SharedError::Conflict {
+            id: BookId(1),
+            expected: Version(1),
+            actual: Version(2)
+        })
+    );
+    assert_eq!(shared.snapshot().library.get(BookId(1)).unwrap().copies, 5);
+    assert_eq!(
+        shared.update_if(BookId(99), version, |_| {}),
+        Err(SharedError::Library(LibraryError::NotFound(BookId(99))))
+    );
+}
+
+#[test]
+fn test_failed_write_publishes_nothing() {
+    let shared = SharedLibrary::new(super::sample_library());
+    let before = shared.snapshot();
+    let result = shared.add_book(Book::new("Copy", 2000).with_isbn("978-0-261-10235-4"));
+    assert!(matches!(
+        result,
+        Err(SharedError::Library(LibraryError::DuplicateIsbn(_)))
+    ));
+    assert!(Arc::ptr_eq(&before, &shared.snapshot()));
+}
+
+// Many threads incrementing the same counter through optimistic updates:
+// every increment must survive, which fails if any update is lost.
+#[test]
+fn test_stress_optimistic_increments() {
+    const THREADS: u32 = 8;
+    const INCREMENTS: u32 = 200;
+
+    let shared = SharedLibrary::new(Library::new());
+    let id = shared
+        .add_book(Book::new("Counter", 2000).with_copies(0))
+        .unwrap();
+
+    let conflicts: u32 = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let shared = shared.clone();
+                scope.spawn(move || {
+                    let mut conflicts = 0;
+                    for _ in 0..INCREMENTS {
+                        loop {
+                            let snapshot = shared.snapshot();
+                            let (book, version) = snapshot.get(id).unwrap();
+                            let copies = book.copies + 1;
+                            match shared.update_if(id, version, |book| book.copies = copies) {
+                                Ok(_) => break,
+                                Err(SharedError::Conflict { .. }) => conflicts += 1,
+                                Err(err) => panic!("{err}"),
+                            }
+                        }
+                    }
+                    conflicts
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+
+    let snapshot = shared.snapshot();
+    let (book, version) = snapshot.get(id).unwrap();
+    assert_eq!(book.copies, THREADS * INCREMENTS);
+    assert_eq!(version, Version(1 + u64::from(THREADS * INCREMENTS)));
+    // A retry only fails again if another update landed since its snapshot,
+    // so no thread can have to retry more often than others update.
+    assert!(conflicts <= (THREADS - 1) * THREADS * INCREMENTS);
+}
+
+// Readers iterate snapshots while writers add and remove books. Every
+// snapshot must be internally consistent, whatever the interleaving.
+#[test]
+fn test_stress_readers_and_writers() {
+    const WRITERS: u64 = 4;
+    const BOOKS: u64 = 100;
+
+    let shared = SharedLibrary::new(Library::new());
+    std::thread::scope(|scope| {
+        for w in 0..WRITERS {
+            let shared = shared.clone();
+            scope.spawn(move || {
+                for i in 0..BOOKS {
+                    let isbn = format!("{w}-{i}");
+                    let id = shared
+                        .add_book(Book::new(&format!("Book {isbn}"), 1900).with_isbn(&isbn))
+                        .unwrap();
+                    if i % 2 == 1 {
+                        shared.remove_book(id).unwrap();
+                    }
+                }
+            });
+        }
+        for _ in 0..4 {
+            let shared = shared.clone();
+            scope.spawn(move || {
+                let mut seen = 0;
+                while seen < WRITERS * BOOKS / 2 {
+                    let snapshot = shared.snapshot();
+                    snapshot.library.assert_indexes_consistent();
+                    assert_eq!(snapshot.versions.len(), snapshot.library.len());
+                    let count = snapshot.library.books().count() as u64;
+                    assert_eq!(count, snapshot.library.len() as u64);
+                    // Books only go away after being added, so the count
+                    // never exceeds what the writers add.
+                    assert!(count <= WRITERS * BOOKS);
+                    seen = seen.max(count);
+                }
+            });
+        }
+    });
+
+    let snapshot = shared.snapshot();
+    assert_eq!(snapshot.library.len() as u64, WRITERS * BOOKS / 2);
+    snapshot.library.assert_indexes_consistent();
+}
@@ -0,0 +1,593 @@
+// On-disk persistence for a `Library`: a snapshot of the whole catalog plus
+// an append-only journal of the changes made since.
+//
+// Both files hold one JSON object per line. Every journal entry carries a
+// sequence number and the snapshot records the last one it includes, so
+// entries already folded into the snapshot are skipped on replay even if
+// the journal could not be reset after a compaction.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::{Book, BookId, Library, LibraryError};
+use crate::json::{self, Value};
+
+const SNAPSHOT: &str = "library.snapshot";
+const SNAPSHOT_TMP: &str = "library.snapshot.tmp";
+const JOURNAL: &str = "library.journal";
+const FORMAT_VERSION: u64 = 1;
+
+#[derive(Debug)]
+pub(super) enum StoreError {
+    Io(io::Error),
+    /// A complete line that can't be understood. Only an incomplete last
+    /// journal line is treated as an interrupted write and dropped.
+    Corrupt {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Library(LibraryError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "i/o error: {err}"),
+            StoreError::Corrupt {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", file.display()),
+            StoreError::Library(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+impl From<LibraryError> for StoreError {
+    fn from(err: LibraryError) -> Self {
+        StoreError::Library(err)
+    }
+}
+
+fn strings(values: &[String]) -> Value {
+    Value::Array(values.iter().map(|s| s.as_str().into()).collect())
+}
+
+impl Book {
+    pub(super) fn to_json(&self) -> Value {
+        Value::object([
+            ("title", self.title.as_str().into()),
+            ("year", u32::from(self.year).into()),
+            ("authors", strings(&self.authors)),
+            ("isbn", self.isbn.as_deref().into()),
+            ("publisher", self.publisher.as_deref().into()),
+            ("tags", strings(&self.tags)),
+            ("copies", self.copies.into()),
+            ("description", self.description.as_deref().into()),
+        ])
+    }
+
+    pub(super) fn from_json(value: &Value) -> Result<Book, String> {
+        let title = value
+            .get("title")
+            .and_then(Value::as_str)
+            .ok_or("missing title")?;
+        let year = value
+            .get("year")
+            .and_then(Value::as_u64)
+            .and_then(|year| u16::try_from(year).ok())
+            .ok_or("missing or invalid year")?;
+        let mut book = Book::new(title, year);
+
+        let string_list = |key: &str| -> Result<Vec<String>, String> {
+            match value.get(key) {
+                None | Some(Value::Null) => Ok(Vec::new()),
+                Some(Value::Array(items)) => items
+                    .iter()
+                    .map(|item| item.as_str().map(String::from))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| format!("{key} must be a list of strings")),
+                Some(_) => Err(format!("{key} must be a list of strings")),
+            }
+        };
+        let optional_string = |key: &str| -> Result<Option<String>, String> {
+            match value.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::String(s)) => Ok(Some(s.clone())),
+                Some(_) => Err(format!("{key} must be a string")),
+            }
+        };
+
+        book.authors = string_list("authors")?;
+        book.isbn = optional_string("isbn")?;
+        book.publisher = optional_string("publisher")?;
+        book.tags = string_list("tags")?;
+        book.description = optional_string("description")?;
+        if let Some(copies) = value.get("copies") {
+            book.copies = copies
+                .as_u64()
+                .and_then(|copies| u32::try_from(copies).ok())
+                .ok_or("invalid copies")?;
+        }
+        Ok(book)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Add(BookId, Book),
+    Remove(BookId),
+    Update(BookId, Book),
+}
+
+impl Op {
+    fn to_json(&self, seq: u64) -> Value {
+        let (op, BookId(id), book) = match self {
+            Op::Add(id, book) => ("add", id, Some(book)),
+            Op::Remove(id) => ("remove", id, None),
+            Op::Update(id, book) => ("update", id, Some(book)),
+        };
+        let mut fields = vec![("seq", seq.into()), ("op", op.into()), ("id", (*id).into())];
+        if let Some(book) = book {
+            fields.push(("book", book.to_json()));
+        }
+        Value::object(fields)
+    }
+
+    fn from_json(value: &Value) -> Result<(u64, Op), String> {
+        let seq = value
+            .get("seq")
+            .and_then(Value::as_u64)
+            .ok_or("missing seq")?;
+        let id = BookId(
+            value
+                .get("id")
+                .and_then(Value::as_u64)
+                .ok_or("missing id")?,
+        );
+        let book = || Book::from_json(value.get("book").ok_or("missing book")?);
+        let op = match value.get("op").and_then(Value::as_str) {
+            Some("add") => Op::Add(id, book()?),
+            Some("remove") => Op::Remove(id),
+            Some("update") => Op::Update(id, book()?),
+            _ => return Err(String::from("unknown op")),
+        };
+        Ok((seq, op))
+    }
+
+    fn apply(self, library: &mut Library) -> Result<(), LibraryError> {
+        match self {
+            Op::Add(id, book) => library.restore_book(id, book),
+            Op::Remove(id) => library.remove_book(id).map(drop),
+            Op::Update(id, book) => library.update_book(id, |old| *old = book),
+        }
+    }
+}
+
+/// A `Library` kept in sync with a directory on disk.
+///
+/// Changes go through the store, which checks them against the library,
+/// appends them to the journal and only then applies them, so a change that
+/// could not be saved is not made at all.
+pub(super) struct Store {
+    dir: PathBuf,
+    journal: File,
+    /// Sequence number of the last change made.
+    seq: u64,
+    /// Journal entries written since the last compaction.
+    journal_len: usize,
+    /// Size of the journal up to the end of its last complete entry.
+    journal_bytes: u64,
+    /// Whether a failed append may have left part of an entry after
+    /// `journal_bytes`, to be cut off before the next one.
+    journal_torn: bool,
+}
+
+fn corrupt(file: &Path, line: usize, message: impl Into<String>) -> StoreError {
+    StoreError::Corrupt {
+        file: file.to_owned(),
+        line,
+        message: message.into(),
+    }
+}
+
+// Make a rename or newly created file in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+fn read_snapshot(path: &Path) -> Result<(Library, u64), StoreError> {
+    let mut library = Library::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((library, 0)),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| corrupt(path, 1, "empty snapshot"))??;
+    let header = json::parse(&header).map_err(|err| corrupt(path, 1, err.to_string()))?;
+    if header.get("version").and_then(Value::as_u64) != Some(FORMAT_VERSION) {
+        return Err(corrupt(path, 1, "unsupported snapshot version"));
+    }
+    let field = |key| {
+        header
+            .get(key)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| corrupt(path, 1, format!("missing {key}")))
+    };
+    let (seq, next_id) = (field("seq")?, field("next_id")?);
+
+    for (i, line) in lines.enumerate() {
+        let line_no = i + 2;
+        let value = json::parse(&line?).map_err(|err| corrupt(path, line_no, err.to_string()))?;
+        let id = value
+            .get("id")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| corrupt(path, line_no, "missing id"))?;
+        let book = value
+            .get("book")
+            .ok_or_else(|| String::from("missing book"))
+            .and_then(Book::from_json)
+            .map_err(|msg| corrupt(path, line_no, msg))?;
+        library.restore_book(BookId(id), book)?;
+    }
+    library.next_id = library.next_id.max(next_id);
+    Ok((library, seq))
+}
+
+/// Replay the journal at `path`, returning the number of entries in it and
+/// the last sequence number seen. An incomplete final line is cut off.
+fn replay_journal(
+    path: &Path,
+    library: &mut Library,
+    mut seq: u64,
+) -> Result<(usize, u64), StoreError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, seq)),
+        Err(err) => return Err(err.into()),
+    };
+
+    // Everything after the last newline is a write that never completed.
+    let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let mut entries = 0;
+    for (i, line) in data[..complete].split(|&b| b == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let line_no = i + 1;
+        let text =
+            std::str::from_utf8(line).map_err(|err| corrupt(path, line_no, err.to_string()))?;
+        let value = json::parse(text).map_err(|err| corrupt(path, line_no, err.to_string()))?;
+        let (entry_seq, op) = Op::from_json(&value).map_err(|msg| corrupt(path, line_no, msg))?;
+        entries += 1;
+        if entry_seq <= seq {
+            // Already part of the snapshot.
+            continue;
+        }
+        op.apply(library)?;
+        seq = entry_seq;
+    }
+
+    if complete < data.len() {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok((entries, seq))
+}
+
+impl Store {
+    /// Open the store in `dir`, creating it if needed, and load its library.
+    pub(super) fn open(dir: &Path) -> Result<(Store, Library), StoreError> {
+        fs::create_dir_all(dir)?;
+        let (mut library, seq) = read_snapshot(&dir.join(SNAPSHOT))?;
+        let (journal_len, seq) = replay_journal(&dir.join(JOURNAL), &mut library, seq)?;
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(JOURNAL))?;
+        let journal_bytes = journal.metadata()?.len();
+        let store = Store {
+            dir: dir.to_owned(),
+            journal,
+            seq,
+            journal_len,
+            journal_bytes,
+            journal_torn: false,
+        };
+        Ok((store, library))
+    }
+
+    /// Number of journal entries a `compact` would fold into the snapshot.
+    pub(super) fn journal_len(&self) -> usize {
+        self.journal_len
+    }
+
+    // Write `op` to the journal and make it durable. On failure the journal
+    // is cut back to its last complete entry, now or before the next append,
+    // so a partly written line never ends up in the middle of it.
+    fn append(&mut self, op: &Op) -> Result<(), StoreError> {
+        if self.journal_torn {
+            self.journal.set_len(self.journal_bytes)?;
+            self.journal_torn = false;
+        }
+        let line = format!("{}\n", op.to_json(self.seq + 1));
+        let written = self
+            .journal
+            .write_all(line.as_bytes())
+            .and_then(|()| self.journal.sync_data());
+        if let Err(err) = written {
+            self.journal_torn = self.journal.set_len(self.journal_bytes).is_err();
+            return Err(err.into());
+        }
+        self.journal_bytes += line.len() as u64;
+        self.seq += 1;
+        self.journal_len += 1;
+        Ok(())
+    }
+
+    pub(super) fn add_book(
+        &mut self,
+        library: &mut Library,
+        book: Book,
+    ) -> Result<BookId, StoreError> {
+        let id = BookId(library.next_id);
+        library.check_isbn(id, &book)?;
+        let op = Op::Add(id, book);
+        self.append(&op)?;
+        op.apply(library)?;
+        Ok(id)
+    }
+
+    pub(super) fn remove_book(
+        &mut self,
+        library: &mut Library,
+        id: BookId,
+    ) -> Result<Book, StoreError> {
+        if library.get(id).is_none() {
+            return Err(LibraryError::NotFound(id).into());
+        }
+        self.append(&Op::Remove(id))?;
+        Ok(library.remove_book(id)?)
+    }
+
+    pub(super) fn update_book(
+        &mut self,
+        library: &mut Library,
+        id: BookId,
+        change: impl FnOnce(&mut Book),
+    ) -> Result<(), StoreError> {
+        let mut book = library.get(id).ok_or(LibraryError::NotFound(id))?.clone();
+        change(&mut book);
+        library.check_isbn(id, &book)?;
+        let op = Op::Update(id, book);
+        self.append(&op)?;
+        Ok(op.apply(library)?)
+    }
+
+    /// Write `library` as the new snapshot and start an empty journal.
+    ///
+    /// The snapshot is written to a temporary file and renamed over the old
+    /// one, so a crash leaves either the old or the new snapshot in place.
+    pub(super) fn compact(&mut self, library: &Library) -> Result<(), StoreError> {
+        let tmp = self.dir.join(SNAPSHOT_TMP);
+        {
+            let mut out = io::BufWriter::new(File::create(&tmp)?);
+            let header = Value::object([
+                ("version", FORMAT_VERSION.into()),
+                ("seq", self.seq.into()),
+                ("next_id", library.next_id.into()),
+            ]);
+            writeln!(out, "{header}")?;
+            for (&BookId(id), book) in &library.books {
+                let entry = Value::object([("id", id.into()), ("book", book.to_json())]);
+                writeln!(out, "{entry}")?;
+            }
+            out.into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+        }
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        sync_dir(&self.dir)?;
+
+        // Should this fail, the next open skips the stale entries by their
+        // sequence numbers.
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+        self.journal_len = 0;
+        self.journal_bytes = 0;
+        self.journal_torn = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn titles(library: &Library) -> Vec<&str> {
+    library.books().map(|book| book.title.as_str()).collect()
+}
+
+#[test]
+fn test_book_json_round_trip() {
+    let book = Book::new("Good Omens", 1990)
+        .with_author("Terry Pratchett")
+        .with_author("Neil Gaiman")
+        .with_isbn("978-0-575-04800-6")
+        .with_tag("comedy")
+        .with_copies(2)
+        .with_description("The world ends on Saturday. Next Saturday.");
+    assert_eq!(Book::from_json(&book.to_json()), Ok(book));
+    assert_eq!(
+        Book::from_json(&json::parse(r#"{"title":"Dune","year":1965}"#).unwrap()),
+        Ok(Book::new("Dune", 1965))
+    );
+    assert!(Book::from_json(&json::parse(r#"{"year":1965}"#).unwrap()).is_err());
+    assert!(Book::from_json(&json::parse(r#"{"title":"X","year":70000}"#).unwrap()).is_err());
+}
+
+#[test]
+fn test_store_reopen_replays_journal() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    {
+        let (mut store, mut library) = Store::open(dir.path())?;
+        assert!(library.is_empty());
+        let dune = store.add_book(&mut library, Book::new("Dune", 1965).with_isbn("1"))?;
+        let emma = store.add_book(&mut library, Book::new("Emma", 1815))?;
+        store.add_book(&mut library, Book::new("Ulysses", 1922))?;
+        store.update_book(&mut library, dune, |book| book.copies = 4)?;
+        store.remove_book(&mut library, emma)?;
+        assert_eq!(store.journal_len(), 5);
+    }
+
+    let (mut store, mut library) = Store::open(dir.path())?;
+    assert_eq!(titles(&library), ["Dune", "Ulysses"]);
+    assert_eq!(library.by_isbn("1").map(|book| book.copies), Some(4));
+    library.assert_indexes_consistent();
+    // Ids are not reused after a reload.
+    let id = store.add_book(&mut library, Book::new("Middlemarch", 1871))?;
+    assert_eq!(id, BookId(3));
+    Ok(())
+}
+
+#[test]
+fn test_store_compact() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let (mut store, mut library) = Store::open(dir.path())?;
+    for i in 0..10 {
+        store.add_book(&mut library, Book::new(&format!("Volume {i}"), 2000 + i))?;
+    }
+    store.remove_book(&mut library, BookId(9))?;
+    store.compact(&library)?;
+    assert_eq!(store.journal_len(), 0);
+    assert_eq!(fs::metadata(dir.path().join(JOURNAL))?.len(), 0);
+    assert!(!dir.path().join(SNAPSHOT_TMP).exists());
+
+    store.update_book(&mut library, BookId(0), |book| book.year = 1999)?;
+    drop(store);
+
+    let (store, reloaded) = Store::open(dir.path())?;
+    assert_eq!(store.journal_len(), 1);
+    assert_eq!(reloaded.books, library.books);
+    assert_eq!(reloaded.next_id, 10);
+    reloaded.assert_indexes_consistent();
+    Ok(())
+}
+
+#[test]
+fn test_store_recovers_truncated_journal() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    {
+        let (mut store, mut library) = Store::open(dir.path())?;
+        store.add_book(&mut library, Book::new("Dune", 1965))?;
+        store.add_book(&mut library, Book::new("Emma", 1815))?;
+    }
+    // Simulate a crash halfway through writing the second entry.
+    let path = dir.path().join(JOURNAL);
+    let data = fs::read(&path)?;
+    fs::write(&path, &data[..data.len() - 10])?;
+
+    let (mut store, mut library) = Store::open(dir.path())?;
+    assert_eq!(titles(&library), ["Dune"]);
+    store.add_book(&mut library, Book::new("Ulysses", 1922))?;
+    drop(store);
+
+    let (_, library) = Store::open(dir.path())?;
+    assert_eq!(titles(&library), ["Dune", "Ulysses"]);
+    Ok(())
+}
+
+#[test]
+fn test_store_rejects_corrupt_journal() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    {
+        let (mut store, mut library) = Store::open(dir.path())?;
+        store.add_book(&mut library, Book::new("Dune", 1965))?;
+    }
+    let path = dir.path().join(JOURNAL);
+    let mut data = fs::read(&path)?;
+    data.splice(0..0, b"garbage\n".iter().copied());
+    fs::write(&path, data)?;
+
+    match Store::open(dir.path()) {
+        Err(StoreError::Corrupt { line, .. }) => assert_eq!(line, 1),
+        Err(err) => panic!("unexpected error {err}"),
+        Ok(_) => panic!("corrupt journal was accepted"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_store_skips_entries_already_in_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let (mut store, mut library) = Store::open(dir.path())?;
+    store.add_book(&mut library, Book::new("Dune", 1965))?;
+    store.add_book(&mut library, Book::new("Emma", 1815))?;
+    let journal = fs::read(dir.path().join(JOURNAL))?;
+    store.compact(&library)?;
+    store.add_book(&mut library, Book::new("Ulysses", 1922))?;
+    drop(store);
+
+    // As if the process died after the snapshot rename but before the
+    // journal was reset: the old entries are still there.
+    let mut stale = journal;
+    stale.extend(fs::read(dir.path().join(JOURNAL))?);
+    fs::write(dir.path().join(JOURNAL), stale)?;
+
+    let (_, reloaded) = Store::open(dir.path())?;
+    assert_eq!(titles(&reloaded), ["Dune", "Emma", "Ulysses"]);
+    Ok(())
+}
+
+#[test]
+fn test_store_failed_append_changes_nothing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let path = dir.path().join(JOURNAL);
+    let (mut store, mut library) = Store::open(dir.path())?;
+    let dune = store.add_book(&mut library, Book::new("Dune", 1965))?;
+
+    // A read-only handle makes every write fail, and the truncation after it
+    // too. Part of an entry is then written behind the store's back, as if
+    // the failed write had got that far.
+    let writable = std::mem::replace(&mut store.journal, File::open(&path)?);
+    assert!(store
+        .add_book(&mut library, Book::new("Emma", 1815))
+        .is_err());
+    assert!(store
+        .update_book(&mut library, dune, |book| book.copies = 9)
+        .is_err());
+    assert!(store.remove_book(&mut library, dune).is_err());
+    assert_eq!(titles(&library), ["Dune"]);
+    assert_eq!(library.books[&dune].copies, 1);
+    assert_eq!(library.next_id, 1);
+    assert_eq!(store.journal_len(), 1);
+    OpenOptions::new()
+        .append(true)
+        .open(&path)?
+        .write_all(br#"{"seq":2,"op":"add","#)?;
+
+    // Once writes work again, the torn entry is cut off before the next one.
+    store.journal = writable;
+    store.add_book(&mut library, Book::new("Ulysses", 1922))?;
+    drop(store);
+
+    let (store, reloaded) = Store::open(dir.path())?;
+    assert_eq!(titles(&reloaded), ["Dune", "Ulysses"]);
+    assert_eq!(store.journal_len(), 2);
+    assert_eq!(reloaded.books, library.books);
+    Ok(())
+}
@@ -0,0 +1,414 @@
+// Works, their editions, and series of works.
+//
+// Each `Book` in a `Library` is one edition, with its own ISBN and year. A
+// `Work` groups the editions of the same text, and a `Series` orders works
+// into volumes. Books that belong to no work are treated as works with a
+// single edition.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use super::{Book, BookId, Library};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct WorkId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) struct SeriesId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Format {
+    Hardcover,
+    Paperback,
+    Ebook,
+    Audiobook,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Edition {
+    pub(super) book: BookId,
+    pub(super) format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Work {
+    pub(super) title: String,
+    pub(super) editions: Vec<Edition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Series {
+    pub(super) name: String,
+    /// In reading order.
+    pub(super) volumes: Vec<WorkId>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum WorksError {
+    UnknownBook(BookId),
+    UnknownWork(WorkId),
+    UnknownSeries(SeriesId),
+    AlreadyInWork { book: BookId, work: WorkId },
+    AlreadyInSeries { work: WorkId, series: SeriesId },
+}
+
+impl fmt::Display for WorksError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorksError::UnknownBook(BookId(id)) => write!(f, "no book with id {id}"),
+            WorksError::UnknownWork(WorkId(id)) => write!(f, "no work with id {id}"),
+            WorksError::UnknownSeries(SeriesId(id)) => write!(f, "no series with id {id}"),
+            WorksError::AlreadyInWork {
+                book: BookId(book),
+                work: WorkId(work),
+            } => write!(f, "book {book} is already an edition of work {work}"),
+            WorksError::AlreadyInSeries {
+                work: WorkId(work),
+                series: SeriesId(series),
+            } => write!(f, "work {work} is already in series {series}"),
+        }
+    }
+}
+
+impl std::error::Error for WorksError {}
+
+/// Groups the books of a `Library` into works and series.
+#[derive(Debug, Default)]
+pub(super) struct Works {
+    works: BTreeMap<WorkId, Work>,
+    next_work: u32,
+    series: BTreeMap<SeriesId, Series>,
+    next_series: u32,
+    work_of: HashMap<BookId, WorkId>,
+    series_of: HashMap<WorkId, SeriesId>,
+}
+
+impl Works {
+    pub(super) fn new() -> Self {
+        Works::default()
+    }
+
+    pub(super) fn add_work(&mut self, title: &str) -> WorkId {
+        let id = WorkId(self.next_work);
+        self.next_work += 1;
+        self.works.insert(
+            id,
+            Work {
+                title: String::from(title),
+                editions: Vec::new(),
+            },
+        );
+        id
+    }
+
+    pub(super) fn work(&self, id: WorkId) -> Option<&Work> {
+        self.works.get(&id)
+    }
+
+    pub(super) fn add_edition(
+        &mut self,
+        library: &Library,
+        work: WorkId,
+        book: BookId,
+        format: Format,
+    ) -> Result<(), WorksError> {
+        library.get(book).ok_or(WorksError::UnknownBook(book))?;
+        if let Some(&work) = self.work_of.get(&book) {
+            return Err(WorksError::AlreadyInWork { book, work });
+        }
+        self.works
+            .get_mut(&work)
+            .ok_or(WorksError::UnknownWork(work))?
+            .editions
+            .push(Edition { book, format });
+        self.work_of.insert(book, work);
+        Ok(())
+    }
+
+    /// Drop a book removed from the library from its work, if any.
+    pub(super) fn forget_book(&mut self, book: BookId) {
+        if let Some(work) = self.work_of.remove(&book) {
+            let editions = &mut self.works.get_mut(&work).expect("work exists").editions;
+            editions.retain(|edition| edition.book != book);
+        }
+    }
+
+    pub(super) fn work_of(&self, book: BookId) -> Option<WorkId> {
+        self.work_of.get(&book).copied()
+    }
+
+    /// Editions of `work` held by `library`, oldest first and editions from
+    /// the same year in the order they were added.
+    pub(super) fn editions<'a>(
+        &self,
+        library: &'a Library,
+        work: WorkId,
+    ) -> Result<Vec<(&'a Book, Format)>, WorksError> {
+        let work = self.works.get(&work).ok_or(WorksError::UnknownWork(work))?;
+        let mut editions: Vec<_> = work
+            .editions
+            .iter()
+            .filter_map(|edition| Some((library.get(edition.book)?, edition.format)))
+            .collect();
+        editions.sort_by_key(|(book, _)| book.year);
+        Ok(editions)
+    }
+
+    pub(super) fn first_edition<'a>(&self, library: &'a Library, work: WorkId) -> Option<&'a Book> {
+        let editions = self.editions(library, work).ok()?;
+        editions.first().map(|&(book, _)| book)
+    }
+
+    /// The first edition of every work, plus every book that belongs to no
+    /// work, oldest first. This is the work-level counterpart of
+    /// `Library::published_in(..)`.
+    pub(super) fn first_editions<'a>(&self, library: &'a Library) -> Vec<&'a Book> {
+        // The year index is oldest first, so the first edition of each work
+        // is the first one met.
+        let mut seen = HashSet::new();
+        library
+            .indexes
+            .years(..)
+            .filter(|&id| self.work_of(id).is_none_or(|work| seen.insert(work)))
+            .map(|id| &library.books[&id])
+            .collect()
+    }
+
+    /// Work-level `Library::oldest_book`: the first edition of the oldest
+    /// work.
+    pub(super) fn oldest_work<'a>(&self, library: &'a Library) -> Option<&'a Book> {
+        self.first_editions(library).into_iter().next()
+    }
+
+    /// Works with at least one edition by `author`, in the order they were
+    /// added.
+    pub(super) fn works_by(&self, library: &Library, author: &str) -> Vec<WorkId> {
+        self.works
+            .iter()
+            .filter(|(_, work)| {
+                work.editions.iter().any(|edition| {
+                    library
+                        .get(edition.book)
+                        .is_some_and(|book| book.has_author(author))
+                })
+            })
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    pub(super) fn add_series(&mut self, name: &str) -> SeriesId {
+        let id = SeriesId(self.next_series);
+        self.next_series += 1;
+        self.series.insert(
+            id,
+            Series {
+                name: String::from(name),
+                volumes: Vec::new(),
+            },
+        );
+        id
+    }
+
+    pub(super) fn series(&self, id: SeriesId) -> Option<&Series> {
+        self.series.get(&id)
+    }
+
+    /// Insert `work` into `series` as volume `position`, counting from 1,
+    /// or at the end if `position` is past it.
+    pub(super) fn add_volume(
+        &mut self,
+        series: SeriesId,
+        work: WorkId,
+        position: usize,
+    ) -> Result<(), WorksError> {
+        if !self.works.contains_key(&work) {
+            return Err(WorksError::UnknownWork(work));
+        }
+        if let Some(&series) = self.series_of.get(&work) {
+            return Err(WorksError::AlreadyInSeries { work, series });
+        }
+        let volumes = &mut self
+            .series
+            .get_mut(&series)
+            .ok_or(WorksError::UnknownSeries(series))?
+            .volumes;
+        let index = position.saturating_sub(1).min(volumes.len());
+        volumes.insert(index, work);
+        self.series_of.insert(work, series);
+        Ok(())
+    }
+
+    /// The series `work` belongs to and its volume number, counting from 1.
+    pub(super) fn volume_of(&self, work: WorkId) -> Option<(SeriesId, usize)> {
+        let series = *self.series_of.get(&work)?;
+        let index = self.series[&series]
+            .volumes
+            .iter()
+            .position(|&w| w == work)?;
+        Some((series, index + 1))
+    }
+
+    /// The volume after `work` in its series.
+    pub(super) fn next_volume(&self, work: WorkId) -> Option<WorkId> {
+        let (series, number) = self.volume_of(work)?;
+        self.series[&series].volumes.get(number).copied()
+    }
+}
+
+// The sample library with later editions of the two Tolkien books, grouped
+// into works of the Middle-earth series.
+#[cfg(test)]
+fn setup() -> (Library, Works, WorkId, WorkId) {
+    let mut library = super::sample_library();
+    let paperback = library.add_book(
+        Book::new("The Fellowship of the Ring", 1991)
+            .with_author("J. R. R. Tolkien")
+            .with_isbn("978-0-261-10357-3"),
+    );
+    let ebook = library.add_book(
+        Book::new("The Hobbit", 2009)
+            .with_author("J. R. R. Tolkien")
+            .with_isbn("978-0-007-32260-2"),
+    );
+
+    let mut works = Works::new();
+    let fellowship = works.add_work("The Fellowship of the Ring");
+    for (book, format) in [
+        (paperback, Format::Paperback),
+        (BookId(0), Format::Hardcover),
+    ] {
+        works
+            .add_edition(&library, fellowship, book, format)
+            .unwrap();
+    }
+    let hobbit = works.add_work("The Hobbit");
+    for (book, format) in [(BookId(3), Format::Hardcover), (ebook, Format::Ebook)] {
+        works.add_edition(&library, hobbit, book, format).unwrap();
+    }
+    (library, works, fellowship, hobbit)
+}
+
+#[test]
+fn test_editions() {
+    let (library, works, fellowship, _) = setup();
+    let editions: Vec<_> = works
+        .editions(&library, fellowship)
+        .unwrap()
+        .into_iter()
+        .map(|(book, format)| (book.year, format))
+        .collect();
+    assert_eq!(
+        editions,
+        [(1954, Format::Hardcover), (1991, Format::Paperback)]
+    );
+    assert_eq!(
+        works
+            .first_edition(&library, fellowship)
+            .and_then(|book| book.isbn.as_deref()),
+        Some("978-0-261-10235-4")
+    );
+    assert_eq!(works.work_of(BookId(4)), Some(fellowship));
+    assert_eq!(works.work_of(BookId(1)), None);
+}
+
+#[test]
+fn test_first_editions() {
+    let (library, works, _, _) = setup();
+    let firsts: Vec<_> = works
+        .first_editions(&library)
+        .into_iter()
+        .map(|book| (book.title.as_str(), book.year))
+        .collect();
+    assert_eq!(
+        firsts,
+        [
+            ("Alice's Adventures in Wonderland", 1865),
+            ("The Hobbit", 1937),
+            ("The Fellowship of the Ring", 1954),
+            ("Good Omens", 1990),
+        ]
+    );
+    assert_eq!(
+        works.oldest_work(&library).map(|book| book.year),
+        library.oldest_book().map(|book| book.year)
+    );
+}
+
+#[test]
+fn test_works_by_author() {
+    let (library, works, fellowship, hobbit) = setup();
+    assert_eq!(
+        works.works_by(&library, "J. R. R. Tolkien"),
+        [fellowship, hobbit]
+    );
+    assert_eq!(works.works_by(&library, "Lewis Carroll"), []);
+}
+
+#[test]
+fn test_edition_errors() {
+    let (library, mut works, fellowship, hobbit) = setup();
+    assert_eq!(
+        works.add_edition(&library, hobbit, BookId(0), Format::Ebook),
+        Err(WorksError::AlreadyInWork {
+            book: BookId(0),
+            work: fellowship
+        })
+    );
+    assert_eq!(
+        works.add_edition(&library, hobbit, BookId(99), Format::Ebook),
+        Err(WorksError::UnknownBook(BookId(99)))
+    );
+    assert_eq!(
+        works.add_edition(&library, WorkId(99), BookId(1), Format::Ebook),
+        Err(WorksError::UnknownWork(WorkId(99)))
+    );
+    // A failed add leaves the book free to join another work.
+    assert_eq!(works.work_of(BookId(1)), None);
+    works
+        .add_edition(&library, hobbit, BookId(1), Format::Audiobook)
+        .unwrap();
+    assert_eq!(works.work_of(BookId(1)), Some(hobbit));
+}
+
+#[test]
+fn test_forget_book() {
+    let (mut library, mut works, fellowship, _) = setup();
+    library.remove_book(BookId(0)).unwrap();
+    works.forget_book(BookId(0));
+    assert_eq!(
+        works.first_edition(&library, fellowship).map(|b| b.year),
+        Some(1991)
+    );
+    assert_eq!(works.work(fellowship).unwrap().editions.len(), 1);
+}
+
+#[test]
+fn test_series() {
+    let (_, mut works, fellowship, hobbit) = setup();
+    let towers = works.add_work("The Two Towers");
+    let series = works.add_series("Middle-earth");
+    works.add_volume(series, fellowship, 1).unwrap();
+    works.add_volume(series, towers, 99).unwrap();
+    // The Hobbit comes first, ahead of the volumes already added.
+    works.add_volume(series, hobbit, 1).unwrap();
+
+    assert_eq!(
+        works.series(series).unwrap().volumes,
+        [hobbit, fellowship, towers]
+    );
+    assert_eq!(works.volume_of(fellowship), Some((series, 2)));
+    assert_eq!(works.next_volume(hobbit), Some(fellowship));
+    assert_eq!(works.next_volume(towers), None);
+    assert_eq!(
+        works.add_volume(series, towers, 1),
+        Err(WorksError::AlreadyInSeries {
+            work: towers,
+            series
+        })
+    );
+    let silmarillion = works.add_work("The Silmarillion");
+    assert_eq!(
+        works.add_volume(SeriesId(9), silmarillion, 1),
+        Err(WorksError::UnknownSeries(SeriesId(9)))
+    );
+}
@@ -2,35 +2,230 @@
 // TODO: remove this when you're done with your implementation.
 #![allow(unused_variables, dead_code)]
 
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+pub mod archive;
+pub mod audit;
+pub mod chacha20;
+pub mod clinical;
+pub mod export;
+pub mod fhir;
+pub mod growth;
+pub mod history;
+pub mod registry;
+pub mod sha256;
+pub mod storage;
+pub mod units;
+
+use audit::{AuditLog, Audited, SYSTEM_ACTOR};
+use clinical::{Alert, AlertThresholds, BmiCategory, BpCategory};
+use history::{History, Timestamp};
+use units::{Length, Mass, Pressure};
+
+// Outside these ranges a reading is a typing or unit mistake rather than a
+// measurement.
+const PLAUSIBLE_HEIGHT_CM: RangeInclusive<f32> = 20.0..=280.0;
+const PLAUSIBLE_WEIGHT_KG: RangeInclusive<f32> = 0.2..=650.0;
+const PLAUSIBLE_SYSTOLIC_MMHG: RangeInclusive<u32> = 50..=300;
+const PLAUSIBLE_DIASTOLIC_MMHG: RangeInclusive<u32> = 20..=200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeasurementError {
+    ImplausibleHeight(Length),
+    ImplausibleWeight(Mass),
+    ImplausibleSystolic(Pressure),
+    ImplausibleDiastolic(Pressure),
+    SystolicNotAboveDiastolic {
+        systolic: Pressure,
+        diastolic: Pressure,
+    },
+}
+
+impl fmt::Display for MeasurementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeasurementError::ImplausibleHeight(height) => {
+                write!(f, "a height of {height} is not plausible")
+            }
+            MeasurementError::ImplausibleWeight(weight) => {
+                write!(f, "a weight of {weight} is not plausible")
+            }
+            MeasurementError::ImplausibleSystolic(pressure) => {
+                write!(f, "a systolic pressure of {pressure} is not plausible")
+            }
+            MeasurementError::ImplausibleDiastolic(pressure) => {
+                write!(f, "a diastolic pressure of {pressure} is not plausible")
+            }
+            MeasurementError::SystolicNotAboveDiastolic {
+                systolic,
+                diastolic,
+            } => write!(
+                f,
+                "systolic pressure {systolic} must be above diastolic pressure {diastolic}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeasurementError {}
+
+/// A blood pressure reading, checked to be physiologically possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BloodPressure {
+    systolic: Pressure,
+    diastolic: Pressure,
+}
+
+impl BloodPressure {
+    pub fn new(systolic: Pressure, diastolic: Pressure) -> Result<Self, MeasurementError> {
+        if !PLAUSIBLE_SYSTOLIC_MMHG.contains(&systolic.as_mmhg()) {
+            return Err(MeasurementError::ImplausibleSystolic(systolic));
+        }
+        if !PLAUSIBLE_DIASTOLIC_MMHG.contains(&diastolic.as_mmhg()) {
+            return Err(MeasurementError::ImplausibleDiastolic(diastolic));
+        }
+        if systolic <= diastolic {
+            return Err(MeasurementError::SystolicNotAboveDiastolic {
+                systolic,
+                diastolic,
+            });
+        }
+        Ok(BloodPressure {
+            systolic,
+            diastolic,
+        })
+    }
+
+    pub fn mmhg(systolic: u32, diastolic: u32) -> Result<Self, MeasurementError> {
+        BloodPressure::new(Pressure::mmhg(systolic), Pressure::mmhg(diastolic))
+    }
+
+    pub fn systolic(&self) -> Pressure {
+        self.systolic
+    }
+
+    pub fn diastolic(&self) -> Pressure {
+        self.diastolic
+    }
+
+    fn as_mmhg(&self) -> (u32, u32) {
+        (self.systolic.as_mmhg(), self.diastolic.as_mmhg())
+    }
+}
+
+impl fmt::Display for BloodPressure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.systolic.as_mmhg(), self.diastolic)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Measurements {
-    height: f32,
-    blood_pressure: (u32, u32),
+    height: Length,
+    blood_pressure: BloodPressure,
+    weight: Option<Mass>,
+}
+
+impl Measurements {
+    pub fn new(height: Length, blood_pressure: BloodPressure) -> Self {
+        Measurements {
+            height,
+            blood_pressure,
+            weight: None,
+        }
+    }
+
+    pub fn with_weight(mut self, weight: Mass) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    fn validate(&self) -> Result<(), MeasurementError> {
+        if !PLAUSIBLE_HEIGHT_CM.contains(&self.height.as_cm()) {
+            return Err(MeasurementError::ImplausibleHeight(self.height));
+        }
+        if let Some(weight) = self.weight {
+            if !PLAUSIBLE_WEIGHT_KG.contains(&weight.as_kg()) {
+                return Err(MeasurementError::ImplausibleWeight(weight));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Change in blood pressure since the previous visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureChange {
+    /// There was no previous reading to compare with.
+    NoBaseline,
+    Unchanged,
+    /// Current minus previous, in mmHg.
+    Changed {
+        systolic: i32,
+        diastolic: i32,
+    },
 }
 
+/// A report on one visit. It borrows the patient's name from the `User`;
+/// [`HealthReport::into_owned`] makes a copy that can outlive the borrow.
+#[derive(Debug, Clone, PartialEq)]
 pub struct HealthReport<'a> {
-    patient_name: &'a str,
+    patient_name: Cow<'a, str>,
+    visited_at: Timestamp,
     visit_count: u32,
-    height_change: f32,
-    blood_pressure_change: Option<(i32, i32)>,
+    height_change: Length,
+    blood_pressure_change: PressureChange,
+    blood_pressure_category: Option<BpCategory>,
+    /// Only when weight was measured at the visit.
+    bmi: Option<f32>,
+    bmi_category: Option<BmiCategory>,
+    alerts: Vec<Alert>,
+}
+
+/// A report that doesn't borrow from the user it is about.
+pub type OwnedHealthReport = HealthReport<'static>;
+
+impl HealthReport<'_> {
+    pub fn into_owned(self) -> OwnedHealthReport {
+        HealthReport {
+            patient_name: Cow::Owned(self.patient_name.into_owned()),
+            ..self
+        }
+    }
+
+    pub fn patient_name(&self) -> &str {
+        &self.patient_name
+    }
+
+    pub fn visited_at(&self) -> Timestamp {
+        self.visited_at
+    }
 }
 
 pub struct User {
     name: String,
     age: u32,
-    height: f32,
-    visit_count: usize,
-    last_blood_pressure: Option<(u32, u32)>,
+    height: Length,
+    // Height when the user was created, the baseline for the first visit.
+    initial_height: Length,
+    history: History,
+    alert_thresholds: AlertThresholds,
+    audit: AuditLog,
 }
 
 impl User {
-    pub fn new(name: String, age: u32, height: f32) -> Self {
+    pub fn new(name: String, age: u32, height: Length) -> Self {
         // Create a new User instance
         Self {
             name,
             age,
             height,
-            visit_count: 0,
-            last_blood_pressure: None,
+            initial_height: height,
+            history: History::new(),
+            alert_thresholds: AlertThresholds::default(),
+            audit: AuditLog::new(),
         }
     }
 
@@ -44,80 +239,151 @@ impl User {
         self.age
     }
 
-    pub fn height(&self) -> f32 {
+    pub fn height(&self) -> Length {
         // Return the user's height
         self.height
     }
 
     pub fn doctor_visits(&self) -> u32 {
         // Return the number of time the user has visited the doctor
-        self.visit_count as u32
+        self.history.len() as u32
     }
 
     pub fn set_age(&mut self, new_age: u32) {
         // Set the user's age
-        self.age = new_age
+        self.acting_as(SYSTEM_ACTOR).set_age(new_age)
     }
 
-    pub fn set_height(&mut self, new_height: f32) {
+    pub fn set_height(&mut self, new_height: Length) {
         // Set the user's height
-        self.height = new_height
+        self.acting_as(SYSTEM_ACTOR).set_height(new_height)
+    }
+
+    pub fn set_alert_thresholds(&mut self, thresholds: AlertThresholds) {
+        self.acting_as(SYSTEM_ACTOR)
+            .set_alert_thresholds(thresholds)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Every change made to the user.
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Make changes on behalf of `actor`, who is named in the audit log.
+    /// The setters on `User` itself act as [`SYSTEM_ACTOR`].
+    pub fn acting_as<'a>(&'a mut self, actor: &'a str) -> Audited<'a> {
+        Audited { user: self, actor }
     }
 
-    pub fn visit_doctor(&mut self, measurements: Measurements) -> HealthReport {
+    pub fn visit_doctor(
+        &mut self,
+        measurements: Measurements,
+    ) -> Result<HealthReport<'_>, MeasurementError> {
         // Update a user's statistics based on measurements from a visit to the doctor
-        let Measurements {
-            height,
-            blood_pressure,
-        } = measurements;
+        self.acting_as(SYSTEM_ACTOR).visit_doctor(measurements)
+    }
 
-        self.visit_count += 1;
-        let result = HealthReport {
-            patient_name: &self.name,
-            visit_count: self.visit_count as u32,
-            height_change: height - self.height,
-            blood_pressure_change: match self.last_blood_pressure {
-                Some(prev) => diff_pressure(prev, blood_pressure),
-                None => None,
-            },
-        };
+    /// Record a visit, which may be earlier than ones already recorded,
+    /// and report on it. The user's height is updated only by the latest
+    /// visit. Implausible measurements are rejected and not recorded.
+    pub fn record_visit(
+        &mut self,
+        at: Timestamp,
+        measurements: Measurements,
+        notes: &str,
+    ) -> Result<HealthReport<'_>, MeasurementError> {
+        self.acting_as(SYSTEM_ACTOR)
+            .record_visit(at, measurements, notes)
+    }
 
-        self.height = measurements.height;
-        self.last_blood_pressure = Some(measurements.blood_pressure);
-        result
+    /// Report on the visit at `index` in the history, compared with the
+    /// visit before it.
+    pub fn report(&self, index: usize) -> Option<HealthReport<'_>> {
+        let visit = self.history.get(index)?;
+        let current = &visit.measurements;
+        let earlier = &self.history.visits()[..index];
+        let previous = earlier.last().map(|v| &v.measurements);
+        let previous_weight = earlier.iter().rev().find_map(|v| v.measurements.weight);
+        let previous_height = previous.map_or(self.initial_height, |m| m.height);
+        let bmi = current
+            .weight
+            .map(|weight| clinical::bmi(current.height, weight));
+        Some(HealthReport {
+            patient_name: Cow::Borrowed(&self.name),
+            visited_at: visit.at,
+            visit_count: index as u32 + 1,
+            height_change: current.height - previous_height,
+            blood_pressure_change: diff_pressure(
+                previous.map(|m| m.blood_pressure.as_mmhg()),
+                current.blood_pressure.as_mmhg(),
+            )
+            .expect("plausible readings differ by far less than i32::MAX"),
+            blood_pressure_category: BpCategory::classify(current.blood_pressure, self.age),
+            bmi,
+            bmi_category: bmi.and_then(|bmi| BmiCategory::classify(bmi, self.age)),
+            alerts: clinical::alerts(
+                previous,
+                previous_weight,
+                current,
+                self.age,
+                &self.alert_thresholds,
+            ),
+        })
     }
 }
 
-fn vec2_utoi(v: (u32, u32)) -> (i32, i32) {
-    (v.0 as i32, v.1 as i32)
-}
+/// A difference between two readings too large for an `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaOverflow;
 
-fn diff_pressure(prev: (u32, u32), cur: (u32, u32)) -> Option<(i32, i32)> {
-    if prev == cur {
-        return None;
+impl fmt::Display for DeltaOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pressure change does not fit in an i32")
     }
+}
 
-    let (prev_x, prev_y) = vec2_utoi(prev);
-    let (cur_x, cur_y) = vec2_utoi(cur);
+impl std::error::Error for DeltaOverflow {}
 
-    Some((cur_x - prev_x, cur_y - prev_y))
+// `cur - prev`, computed in i64 where it can't overflow and then narrowed.
+fn signed_delta(prev: u32, cur: u32) -> Result<i32, DeltaOverflow> {
+    i32::try_from(i64::from(cur) - i64::from(prev)).map_err(|_| DeltaOverflow)
+}
+
+fn diff_pressure(
+    prev: Option<(u32, u32)>,
+    cur: (u32, u32),
+) -> Result<PressureChange, DeltaOverflow> {
+    let Some(prev) = prev else {
+        return Ok(PressureChange::NoBaseline);
+    };
+    if prev == cur {
+        return Ok(PressureChange::Unchanged);
+    }
+    Ok(PressureChange::Changed {
+        systolic: signed_delta(prev.0, cur.0)?,
+        diastolic: signed_delta(prev.1, cur.1)?,
+    })
 }
 
 #[test]
 fn test_main() {
-    let bob = User::new(String::from("Bob"), 32, 155.2);
+    let bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
     println!("I'm {} and my age is {}", bob.name(), bob.age());
 }
 
 #[test]
 fn test_height() {
-    let bob = User::new(String::from("Bob"), 32, 155.2);
-    assert_eq!(bob.height(), 155.2);
+    let bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
+    assert_eq!(bob.height(), Length::cm(155.2));
 }
 
 #[test]
 fn test_set_age() {
-    let mut bob = User::new(String::from("Bob"), 32, 155.2);
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
     assert_eq!(bob.age(), 32);
     bob.set_age(33);
     assert_eq!(bob.age(), 33);
@@ -125,21 +391,316 @@ fn test_set_age() {
 
 #[test]
 fn test_visit() {
-    let mut bob = User::new(String::from("Bob"), 32, 155.2);
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
     assert_eq!(bob.doctor_visits(), 0);
-    let report = bob.visit_doctor(Measurements {
-        height: 156.1,
-        blood_pressure: (120, 80),
-    });
+    let report = bob
+        .visit_doctor(Measurements {
+            height: Length::cm(156.1),
+            blood_pressure: BloodPressure::mmhg(120, 80).unwrap(),
+            weight: None,
+        })
+        .unwrap();
     assert_eq!(report.patient_name, "Bob");
     assert_eq!(report.visit_count, 1);
-    assert_eq!(report.blood_pressure_change, None);
+    assert_eq!(report.blood_pressure_change, PressureChange::NoBaseline);
 
-    let report = bob.visit_doctor(Measurements {
-        height: 156.1,
-        blood_pressure: (115, 76),
-    });
+    let report = bob
+        .visit_doctor(Measurements {
+            height: Length::cm(156.1),
+            blood_pressure: BloodPressure::mmhg(115, 76).unwrap(),
+            weight: None,
+        })
+        .unwrap();
+
+    assert_eq!(report.visit_count, 2);
+    assert_eq!(
+        report.blood_pressure_change,
+        PressureChange::Changed {
+            systolic: -5,
+            diastolic: -4
+        }
+    );
+}
+
+#[test]
+fn test_reports_from_history() {
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(155.0));
+    let day = |n: u64| Timestamp(n * 86_400);
+    let measurements = |height, (systolic, diastolic)| {
+        Measurements::new(
+            Length::cm(height),
+            BloodPressure::mmhg(systolic, diastolic).unwrap(),
+        )
+    };
+    bob.record_visit(day(10), measurements(156.0, (130, 85)), "")
+        .unwrap();
+    let report = bob
+        .record_visit(day(20), measurements(157.0, (125, 82)), "")
+        .unwrap();
+    assert_eq!(report.visit_count, 2);
+    assert_eq!(
+        report.blood_pressure_change,
+        PressureChange::Changed {
+            systolic: -5,
+            diastolic: -3
+        }
+    );
+    assert_eq!(bob.height(), Length::cm(157.0));
 
+    // A visit entered late slots in between and is compared with the one
+    // before it; the current height still comes from the latest visit.
+    let report = bob
+        .record_visit(day(15), measurements(156.5, (140, 90)), "follow-up")
+        .unwrap();
     assert_eq!(report.visit_count, 2);
-    assert_eq!(report.blood_pressure_change, Some((-5, -4)));
+    assert_eq!(report.height_change, Length::cm(0.5));
+    assert_eq!(
+        report.blood_pressure_change,
+        PressureChange::Changed {
+            systolic: 10,
+            diastolic: 5
+        }
+    );
+    assert_eq!(bob.height(), Length::cm(157.0));
+    assert_eq!(bob.doctor_visits(), 3);
+    assert_eq!(bob.history().get(1).unwrap().notes, "follow-up");
+
+    let first = bob.report(0).unwrap();
+    assert_eq!(first.height_change, Length::cm(1.0));
+    assert_eq!(first.blood_pressure_change, PressureChange::NoBaseline);
+    assert_eq!(
+        bob.report(2).unwrap().blood_pressure_change,
+        PressureChange::Changed {
+            systolic: -15,
+            diastolic: -8
+        }
+    );
+    assert!(bob.report(3).is_none());
+}
+
+#[test]
+fn test_blood_pressure_validation() {
+    assert!(BloodPressure::mmhg(120, 80).is_ok());
+    assert_eq!(
+        BloodPressure::mmhg(80, 120),
+        Err(MeasurementError::SystolicNotAboveDiastolic {
+            systolic: Pressure::mmhg(80),
+            diastolic: Pressure::mmhg(120)
+        })
+    );
+    assert_eq!(
+        BloodPressure::mmhg(1200, 80),
+        Err(MeasurementError::ImplausibleSystolic(Pressure::mmhg(1200)))
+    );
+    assert_eq!(
+        BloodPressure::mmhg(120, 0),
+        Err(MeasurementError::ImplausibleDiastolic(Pressure::mmhg(0)))
+    );
+    assert_eq!(
+        BloodPressure::new(Pressure::kpa(16.0), Pressure::kpa(10.7))
+            .unwrap()
+            .to_string(),
+        "120/80 mmHg"
+    );
+}
+
+#[test]
+fn test_visit_rejects_implausible_height() {
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
+    let blood_pressure = BloodPressure::mmhg(120, 80).unwrap();
+    // 155.2 metres: a unit mix-up.
+    let result = bob.visit_doctor(Measurements::new(Length::m(155.2), blood_pressure));
+    assert!(matches!(
+        result,
+        Err(MeasurementError::ImplausibleHeight(_))
+    ));
+    assert!(bob
+        .visit_doctor(Measurements::new(Length::cm(f32::NAN), blood_pressure))
+        .is_err());
+    assert_eq!(bob.doctor_visits(), 0);
+
+    // Inches are fine, as long as they say so.
+    bob.visit_doctor(Measurements::new(Length::inches(61.0), blood_pressure))
+        .unwrap();
+    assert!((bob.height().as_cm() - 154.94).abs() < 1e-3);
+}
+
+#[test]
+fn test_report_classification() {
+    let mut alice = User::new(String::from("Alice"), 45, Length::cm(165.0));
+    let day = |n: u64| Timestamp(n * 86_400);
+    let report = alice
+        .record_visit(
+            day(0),
+            Measurements::new(Length::cm(165.0), BloodPressure::mmhg(118, 76).unwrap())
+                .with_weight(Mass::kg(64.0)),
+            "",
+        )
+        .unwrap();
+    assert_eq!(report.blood_pressure_category, Some(BpCategory::Normal));
+    assert!((report.bmi.unwrap() - 23.51).abs() < 0.01);
+    assert_eq!(report.bmi_category, Some(BmiCategory::Normal));
+    assert_eq!(report.alerts, []);
+
+    // Weight is compared with the last visit that recorded it.
+    alice
+        .record_visit(
+            day(30),
+            Measurements::new(Length::cm(165.0), BloodPressure::mmhg(124, 78).unwrap()),
+            "",
+        )
+        .unwrap();
+    let report = alice
+        .record_visit(
+            day(60),
+            Measurements::new(Length::cm(165.0), BloodPressure::mmhg(146, 92).unwrap())
+                .with_weight(Mass::kg(72.0)),
+            "",
+        )
+        .unwrap();
+    assert_eq!(
+        report.blood_pressure_category,
+        Some(BpCategory::Stage2Hypertension)
+    );
+    assert_eq!(report.bmi_category, Some(BmiCategory::Overweight));
+    assert_eq!(
+        report.alerts,
+        [
+            Alert::SystolicChange(22),
+            Alert::DiastolicChange(14),
+            Alert::WeightChange { percent: 12.5 },
+        ]
+    );
+    assert_eq!(alice.report(1).unwrap().bmi, None);
+
+    alice.set_alert_thresholds(AlertThresholds {
+        systolic_change: 30,
+        diastolic_change: 30,
+        ..AlertThresholds::default()
+    });
+    assert_eq!(
+        alice.report(2).unwrap().alerts,
+        [Alert::WeightChange { percent: 12.5 }]
+    );
+}
+
+#[test]
+fn test_report_for_child() {
+    let mut sam = User::new(String::from("Sam"), 9, Length::cm(130.0));
+    let report = sam
+        .visit_doctor(
+            Measurements::new(Length::cm(131.0), BloodPressure::mmhg(125, 75).unwrap())
+                .with_weight(Mass::kg(28.0)),
+        )
+        .unwrap();
+    // Children are assessed against growth percentiles, not adult bands.
+    assert_eq!(report.blood_pressure_category, None);
+    assert!(report.bmi.is_some());
+    assert_eq!(report.bmi_category, None);
+}
+
+#[test]
+fn test_unchanged_pressure_is_not_missing() {
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(155.2));
+    let measurements = Measurements::new(Length::cm(156.0), BloodPressure::mmhg(120, 80).unwrap());
+    bob.visit_doctor(measurements.clone()).unwrap();
+    let report = bob.visit_doctor(measurements).unwrap();
+    assert_eq!(report.blood_pressure_change, PressureChange::Unchanged);
+}
+
+#[test]
+fn test_diff_pressure_edges() {
+    assert_eq!(
+        diff_pressure(None, (120, 80)),
+        Ok(PressureChange::NoBaseline)
+    );
+    // `as i32` used to wrap these to negative numbers.
+    assert_eq!(
+        diff_pressure(Some((0, 0)), (3_000_000_000, 0)),
+        Err(DeltaOverflow)
+    );
+    assert_eq!(
+        diff_pressure(Some((u32::MAX, 0)), (u32::MAX - 1, 0)),
+        Ok(PressureChange::Changed {
+            systolic: -1,
+            diastolic: 0
+        })
+    );
+    assert_eq!(
+        diff_pressure(Some((0, i32::MAX as u32)), (i32::MAX as u32, 0)),
+        Ok(PressureChange::Changed {
+            systolic: i32::MAX,
+            diastolic: -i32::MAX
+        })
+    );
+    assert_eq!(
+        diff_pressure(Some((0, 0)), (0, i32::MAX as u32 + 1)),
+        Err(DeltaOverflow)
+    );
+}
+
+// Properties of `diff_pressure` over random readings, biased towards
+// values near the edges where conversions used to go wrong.
+#[test]
+fn test_diff_pressure_properties() {
+    // SplitMix64; the slight modulo bias doesn't matter here.
+    let mut state: u64 = 43;
+    let mut next = move || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut reading = || -> u32 {
+        match next() % 4 {
+            0 => (next() % 400) as u32,
+            1 => u32::MAX - (next() % 400) as u32,
+            2 => (i32::MAX as u32)
+                .wrapping_add((next() % 800) as u32)
+                .wrapping_sub(400),
+            _ => next() as u32,
+        }
+    };
+
+    for _ in 0..10_000 {
+        let prev = (reading(), reading());
+        let cur = (reading(), reading());
+        let exact = |p: u32, c: u32| i64::from(c) - i64::from(p);
+        let fits = |p, c| i32::try_from(exact(p, c)).is_ok();
+
+        match diff_pressure(Some(prev), cur) {
+            Ok(PressureChange::NoBaseline) => panic!("{prev:?} is a baseline"),
+            Ok(PressureChange::Unchanged) => assert_eq!(prev, cur),
+            Ok(PressureChange::Changed {
+                systolic,
+                diastolic,
+            }) => {
+                assert_ne!(prev, cur);
+                // Adding the change to the previous reading gives the current one.
+                assert_eq!(i64::from(prev.0) + i64::from(systolic), i64::from(cur.0));
+                assert_eq!(i64::from(prev.1) + i64::from(diastolic), i64::from(cur.1));
+                // Swapping the readings negates the change, which only
+                // overflows for a change of exactly i32::MIN.
+                let swapped = match (systolic.checked_neg(), diastolic.checked_neg()) {
+                    (Some(systolic), Some(diastolic)) => Ok(PressureChange::Changed {
+                        systolic,
+                        diastolic,
+                    }),
+                    _ => Err(DeltaOverflow),
+                };
+                assert_eq!(diff_pressure(Some(cur), prev), swapped);
+            }
+            Err(DeltaOverflow) => {
+                assert!(
+                    !fits(prev.0, cur.0) || !fits(prev.1, cur.1),
+                    "{prev:?} {cur:?}"
+                )
+            }
+        }
+        assert_eq!(
+            diff_pressure(Some(prev), prev),
+            Ok(PressureChange::Unchanged)
+        );
+    }
 }
@@ -0,0 +1,385 @@
+// Reports kept after the visits they describe, independent of the users
+// they came from, and their JSON form.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeBounds;
+
+use super::clinical::{Alert, BmiCategory, BpCategory};
+use super::history::Timestamp;
+use super::units::Length;
+use super::{BloodPressure, HealthReport, OwnedHealthReport, PressureChange};
+use crate::json::{self, Value};
+
+impl HealthReport<'_> {
+    pub fn to_json(&self) -> Value {
+        let change = match self.blood_pressure_change {
+            PressureChange::NoBaseline => Value::Null,
+            PressureChange::Unchanged => pressure_change(0, 0),
+            PressureChange::Changed {
+                systolic,
+                diastolic,
+            } => pressure_change(systolic, diastolic),
+        };
+        Value::object([
+            ("patient_name", Value::from(self.patient_name())),
+            ("visited_at", Value::from(self.visited_at.0)),
+            ("visit_count", Value::from(self.visit_count)),
+            (
+                "height_change_cm",
+                Value::from(f64::from(self.height_change.as_cm())),
+            ),
+            ("blood_pressure_change", change),
+            (
+                "blood_pressure_category",
+                Value::from(self.blood_pressure_category.map(|c| c.to_string())),
+            ),
+            ("bmi", Value::from(self.bmi.map(f64::from))),
+            (
+                "bmi_category",
+                Value::from(self.bmi_category.map(|c| c.to_string())),
+            ),
+            (
+                "alerts",
+                Value::Array(self.alerts.iter().map(alert_to_json).collect()),
+            ),
+        ])
+    }
+}
+
+impl OwnedHealthReport {
+    /// Read a report written by [`HealthReport::to_json`].
+    pub fn from_json(value: &Value) -> Result<OwnedHealthReport, String> {
+        let change = match field(value, "blood_pressure_change")? {
+            Value::Null => PressureChange::NoBaseline,
+            change => match (integer(change, "systolic")?, integer(change, "diastolic")?) {
+                (0, 0) => PressureChange::Unchanged,
+                (systolic, diastolic) => PressureChange::Changed {
+                    systolic,
+                    diastolic,
+                },
+            },
+        };
+        let alerts = field(value, "alerts")?
+            .as_array()
+            .ok_or("alerts must be a list")?
+            .iter()
+            .map(alert_from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(HealthReport {
+            patient_name: Cow::Owned(String::from(string(value, "patient_name")?)),
+            visited_at: Timestamp(unsigned(value, "visited_at")?),
+            visit_count: u32::try_from(unsigned(value, "visit_count")?)
+                .map_err(|_| "visit_count is too large")?,
+            height_change: Length::cm(number(value, "height_change_cm")?),
+            blood_pressure_change: change,
+            blood_pressure_category: optional(value, "blood_pressure_category", |name| {
+                named(&BpCategory::ALL, name)
+            })?,
+            bmi: optional(value, "bmi", |bmi| bmi.as_f64().map(|bmi| bmi as f32))?,
+            bmi_category: optional(value, "bmi_category", |name| named(&BmiCategory::ALL, name))?,
+            alerts,
+        })
+    }
+}
+
+fn pressure_change(systolic: i32, diastolic: i32) -> Value {
+    Value::object([
+        ("systolic", Value::from(i64::from(systolic))),
+        ("diastolic", Value::from(i64::from(diastolic))),
+    ])
+}
+
+fn alert_to_json(alert: &Alert) -> Value {
+    match alert {
+        Alert::HypertensiveCrisis(bp) => Value::object([
+            ("kind", Value::from("hypertensive_crisis")),
+            ("systolic", Value::from(bp.systolic().as_mmhg())),
+            ("diastolic", Value::from(bp.diastolic().as_mmhg())),
+        ]),
+        Alert::SystolicChange(change) => Value::object([
+            ("kind", Value::from("systolic_change")),
+            ("mmhg", Value::from(*change)),
+        ]),
+        Alert::DiastolicChange(change) => Value::object([
+            ("kind", Value::from("diastolic_change")),
+            ("mmhg", Value::from(*change)),
+        ]),
+        Alert::WeightChange { percent } => Value::object([
+            ("kind", Value::from("weight_change")),
+            ("percent", Value::from(f64::from(*percent))),
+        ]),
+        Alert::HeightLoss(loss) => Value::object([
+            ("kind", Value::from("height_loss")),
+            ("cm", Value::from(f64::from(loss.as_cm()))),
+        ]),
+    }
+}
+
+fn alert_from_json(value: &Value) -> Result<Alert, String> {
+    match string(value, "kind")? {
+        "hypertensive_crisis" => {
+            let mmhg = |key| u32::try_from(unsigned(value, key)?).map_err(|_| invalid(key));
+            BloodPressure::mmhg(mmhg("systolic")?, mmhg("diastolic")?)
+                .map(Alert::HypertensiveCrisis)
+                .map_err(|err| err.to_string())
+        }
+        "systolic_change" => Ok(Alert::SystolicChange(integer(value, "mmhg")?)),
+        "diastolic_change" => Ok(Alert::DiastolicChange(integer(value, "mmhg")?)),
+        "weight_change" => Ok(Alert::WeightChange {
+            percent: number(value, "percent")?,
+        }),
+        "height_loss" => Ok(Alert::HeightLoss(Length::cm(number(value, "cm")?))),
+        kind => Err(format!("unknown alert kind {kind:?}")),
+    }
+}
+
+// The one of `all` displayed as `name`.
+fn named<T: fmt::Display + Copy>(all: &[T], name: &Value) -> Option<T> {
+    let name = name.as_str()?;
+    all.iter().copied().find(|item| item.to_string() == name)
+}
+
+fn invalid(key: &str) -> String {
+    format!("invalid {key}")
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, String> {
+    value.get(key).ok_or_else(|| format!("missing {key}"))
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> Result<&'a str, String> {
+    field(value, key)?.as_str().ok_or_else(|| invalid(key))
+}
+
+fn unsigned(value: &Value, key: &str) -> Result<u64, String> {
+    field(value, key)?.as_u64().ok_or_else(|| invalid(key))
+}
+
+fn number(value: &Value, key: &str) -> Result<f32, String> {
+    let number = field(value, key)?.as_f64().ok_or_else(|| invalid(key))?;
+    Ok(number as f32)
+}
+
+fn integer<T: TryFrom<i64>>(value: &Value, key: &str) -> Result<T, String> {
+    let number = field(value, key)?.as_f64().ok_or_else(|| invalid(key))?;
+    if number.fract() != 0.0 || number.abs() > i64::MAX as f64 {
+        return Err(invalid(key));
+    }
+    T::try_from(number as i64).map_err(|_| invalid(key))
+}
+
+// A field that is null or missing when there is nothing to record.
+fn optional<T>(
+    value: &Value,
+    key: &str,
+    parse: impl Fn(&Value) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(field) => parse(field).map(Some).ok_or_else(|| invalid(key)),
+    }
+}
+
+/// A line of archived reports that couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+/// Reports from any number of visits and patients, in the order they were
+/// added. They own their data, so the users they came from can go on
+/// changing.
+#[derive(Debug, Clone, Default)]
+pub struct ReportArchive {
+    reports: Vec<OwnedHealthReport>,
+}
+
+impl ReportArchive {
+    pub fn new() -> Self {
+        ReportArchive::default()
+    }
+
+    pub fn add(&mut self, report: HealthReport<'_>) {
+        self.reports.push(report.into_owned());
+    }
+
+    pub fn len(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    pub fn reports(&self) -> &[OwnedHealthReport] {
+        &self.reports
+    }
+
+    pub fn for_patient<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a OwnedHealthReport> + 'a {
+        self.reports
+            .iter()
+            .filter(move |report| report.patient_name() == name)
+    }
+
+    /// Reports on visits made at `times`.
+    pub fn between(
+        &self,
+        times: impl RangeBounds<Timestamp>,
+    ) -> impl Iterator<Item = &OwnedHealthReport> {
+        self.reports
+            .iter()
+            .filter(move |report| times.contains(&report.visited_at))
+    }
+
+    /// One report per line, as JSON.
+    pub fn write_json_lines(&self, out: &mut dyn Write) -> io::Result<()> {
+        for report in &self.reports {
+            writeln!(out, "{}", report.to_json())?;
+        }
+        Ok(())
+    }
+
+    /// Read reports written by [`ReportArchive::write_json_lines`],
+    /// skipping blank lines.
+    pub fn read_json_lines(input: &str) -> Result<ReportArchive, ArchiveError> {
+        let mut archive = ReportArchive::new();
+        for (index, line) in input.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = |message: String| ArchiveError {
+                line: index + 1,
+                message,
+            };
+            let value = json::parse(line).map_err(|err| error(err.to_string()))?;
+            archive
+                .reports
+                .push(HealthReport::from_json(&value).map_err(error)?);
+        }
+        Ok(archive)
+    }
+}
+
+#[cfg(test)]
+fn measurements(
+    height: f32,
+    (systolic, diastolic): (u32, u32),
+    weight: f32,
+) -> super::Measurements {
+    super::Measurements::new(
+        Length::cm(height),
+        BloodPressure::mmhg(systolic, diastolic).unwrap(),
+    )
+    .with_weight(super::units::Mass::kg(weight))
+}
+
+#[test]
+fn test_owned_reports_outlive_the_user() {
+    let mut archive = ReportArchive::new();
+    let mut bob = super::User::new(String::from("Bob"), 52, Length::cm(180.0));
+    for (day, systolic, weight) in [(1, 120, 80.0), (8, 145, 80.0), (15, 190, 70.0)] {
+        let report = bob
+            .record_visit(
+                Timestamp(day * 86_400),
+                measurements(180.0, (systolic, 85), weight),
+                "",
+            )
+            .unwrap();
+        archive.add(report);
+        // Changing the user doesn't disturb what was archived.
+        bob.set_age(53);
+    }
+    drop(bob);
+
+    assert_eq!(archive.len(), 3);
+    assert_eq!(archive.for_patient("Bob").count(), 3);
+    assert_eq!(archive.for_patient("Alice").count(), 0);
+    let latest = &archive.reports()[2];
+    assert_eq!(latest.visit_count, 3);
+    assert_eq!(
+        latest.blood_pressure_category,
+        Some(BpCategory::HypertensiveCrisis)
+    );
+    let days: Vec<_> = archive
+        .between(Timestamp(2 * 86_400)..)
+        .map(|report| report.visited_at().0 / 86_400)
+        .collect();
+    assert_eq!(days, [8, 15]);
+
+    // Owned reports can be handed to another thread.
+    let report = latest.clone();
+    let name = std::thread::spawn(move || String::from(report.patient_name()))
+        .join()
+        .unwrap();
+    assert_eq!(name, "Bob");
+}
+
+#[test]
+fn test_json_round_trip() {
+    let mut archive = ReportArchive::new();
+    let mut alice = super::User::new(String::from("Alice \"Al\" Smith"), 40, Length::cm(170.0));
+    for (day, height, systolic, weight) in [
+        (1, 170.0, (120, 80), 64.0),
+        (2, 170.0, (120, 80), 64.0),
+        (30, 167.5, (185, 95), 72.0),
+    ] {
+        archive.add(
+            alice
+                .record_visit(
+                    Timestamp(day * 86_400),
+                    measurements(height, systolic, weight),
+                    "",
+                )
+                .unwrap(),
+        );
+    }
+    assert_eq!(
+        archive.reports()[1].blood_pressure_change,
+        PressureChange::Unchanged
+    );
+    assert_eq!(archive.reports()[2].alerts.len(), 5);
+
+    let mut out = Vec::new();
+    archive.write_json_lines(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert_eq!(text.lines().count(), 3);
+    let read = ReportArchive::read_json_lines(&text).unwrap();
+    assert_eq!(read.reports(), archive.reports());
+}
+
+#[test]
+fn test_read_errors() {
+    let line = |value: &str| {
+        format!(
+            "{{\"patient_name\":\"Bob\",\"visited_at\":0,\"visit_count\":1,\
+             \"height_change_cm\":0,\"blood_pressure_change\":null,\"alerts\":{value}}}"
+        )
+    };
+    assert_eq!(
+        ReportArchive::read_json_lines(&line("[]")).unwrap().len(),
+        1
+    );
+
+    let input = format!("{}\n\n{}\n", line("[]"), line("[{\"kind\":\"fever\"}]"));
+    let err = ReportArchive::read_json_lines(&input).unwrap_err();
+    assert_eq!(err.line, 3);
+    assert_eq!(err.message, "unknown alert kind \"fever\"");
+
+    let err = ReportArchive::read_json_lines(&line("[]").replace("\"Bob\"", "1")).unwrap_err();
+    assert_eq!(err.message, "invalid patient_name");
+    assert!(ReportArchive::read_json_lines("{").is_err());
+}
@@ -0,0 +1,447 @@
+// An append-only trail of every change made to a user, and who made it.
+//
+// Entries are hash-chained: each one's hash covers its contents and the
+// hash of the entry before it, so altering, removing or reordering any
+// entry breaks the chain from there on. Removing entries from the end
+// leaves a valid but shorter chain, which is caught by checking the head
+// hash against one kept elsewhere.
+
+use std::fmt;
+use std::ops::RangeBounds;
+
+use super::clinical::AlertThresholds;
+use super::history::{Timestamp, Visit};
+use super::sha256::{sha256, Digest};
+use super::units::Length;
+use super::{HealthReport, MeasurementError, Measurements, User};
+
+/// The actor of changes made without naming one, through the plain `User`
+/// setters.
+pub const SYSTEM_ACTOR: &str = "system";
+
+/// Hash of the (nonexistent) entry before the first.
+const GENESIS: Digest = [0; 32];
+
+/// What part of a user an entry is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Age,
+    Height,
+    AlertThresholds,
+    Visits,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [
+        Field::Age,
+        Field::Height,
+        Field::AlertThresholds,
+        Field::Visits,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Age => "age",
+            Field::Height => "height",
+            Field::AlertThresholds => "alert thresholds",
+            Field::Visits => "visits",
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One change, with the old and new values as displayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Position in the log, from 0.
+    pub seq: u64,
+    pub at: Timestamp,
+    pub actor: String,
+    pub field: Field,
+    pub old: String,
+    pub new: String,
+    pub prev_hash: Digest,
+    pub hash: Digest,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> Digest {
+        let mut data = Vec::from(self.prev_hash);
+        data.extend_from_slice(&self.seq.to_be_bytes());
+        data.extend_from_slice(&self.at.0.to_be_bytes());
+        // Length-prefixed, so no two different entries hash the same text.
+        for text in [&self.actor, self.field.name(), &self.old, &self.new] {
+            data.extend_from_slice(&(text.len() as u64).to_be_bytes());
+            data.extend_from_slice(text.as_bytes());
+        }
+        sha256(&data)
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} at {}: {} changed {} from {:?} to {:?}",
+            self.seq, self.at.0, self.actor, self.field, self.old, self.new
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditError {
+    /// The entry's contents don't match its hash.
+    Tampered { seq: u64 },
+    /// The entry doesn't follow on from the one before it.
+    BrokenChain { seq: u64 },
+    /// The log doesn't end where it was expected to.
+    HeadMismatch,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Tampered { seq } => write!(f, "audit entry {seq} has been altered"),
+            AuditError::BrokenChain { seq } => {
+                write!(f, "audit entry {seq} does not follow the entry before it")
+            }
+            AuditError::HeadMismatch => write!(f, "audit log does not end at the expected entry"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    /// A log read back from storage. Check it with [`AuditLog::verify`]
+    /// before trusting it.
+    pub fn from_entries(entries: Vec<AuditEntry>) -> Self {
+        AuditLog { entries }
+    }
+
+    pub(super) fn record(&mut self, actor: &str, field: Field, old: String, new: String) {
+        let mut entry = AuditEntry {
+            seq: self.entries.len() as u64,
+            at: Timestamp::now(),
+            actor: String::from(actor),
+            field,
+            old,
+            new,
+            prev_hash: self.head(),
+            hash: GENESIS,
+        };
+        entry.hash = entry.compute_hash();
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Hash of the last entry. Keep it somewhere the log can't be changed
+    /// from to detect entries cut off the end.
+    pub fn head(&self) -> Digest {
+        self.entries.last().map_or(GENESIS, |entry| entry.hash)
+    }
+
+    /// Check every entry against its hash and the one before it.
+    pub fn verify(&self) -> Result<(), AuditError> {
+        let mut prev_hash = GENESIS;
+        for (seq, entry) in (0..).zip(&self.entries) {
+            if entry.seq != seq || entry.prev_hash != prev_hash {
+                return Err(AuditError::BrokenChain { seq });
+            }
+            if entry.compute_hash() != entry.hash {
+                return Err(AuditError::Tampered { seq });
+            }
+            prev_hash = entry.hash;
+        }
+        Ok(())
+    }
+
+    /// [`AuditLog::verify`], and check the log ends at `head`.
+    pub fn verify_head(&self, head: Digest) -> Result<(), AuditError> {
+        self.verify()?;
+        if self.head() != head {
+            return Err(AuditError::HeadMismatch);
+        }
+        Ok(())
+    }
+
+    /// Changes made by `actor`, oldest first.
+    pub fn by_actor<'a>(&'a self, actor: &'a str) -> impl Iterator<Item = &'a AuditEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.actor == actor)
+    }
+
+    /// Changes to `field`, oldest first.
+    pub fn changes_to(&self, field: Field) -> impl Iterator<Item = &AuditEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.field == field)
+    }
+
+    /// Changes made at `times`, oldest first.
+    pub fn between(&self, times: impl RangeBounds<Timestamp>) -> impl Iterator<Item = &AuditEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| times.contains(&entry.at))
+    }
+
+    /// The latest change to `field`: who made it, and when.
+    pub fn last_change(&self, field: Field) -> Option<(&str, Timestamp)> {
+        self.changes_to(field)
+            .last()
+            .map(|entry| (entry.actor.as_str(), entry.at))
+    }
+}
+
+fn thresholds_text(thresholds: &AlertThresholds) -> String {
+    format!(
+        "systolic {} mmHg, diastolic {} mmHg, weight {}%, height loss {}",
+        thresholds.systolic_change,
+        thresholds.diastolic_change,
+        thresholds.weight_change_percent,
+        thresholds.height_loss
+    )
+}
+
+pub(super) fn visits_text(count: usize) -> String {
+    format!("{count} visits")
+}
+
+fn visit_text(count: usize, visit: &Visit) -> String {
+    let measurements = &visit.measurements;
+    let mut text = format!(
+        "{}; visit at {}: {}, {}",
+        visits_text(count),
+        visit.at.0,
+        measurements.height,
+        measurements.blood_pressure
+    );
+    if let Some(weight) = measurements.weight {
+        text += &format!(", {weight}");
+    }
+    text
+}
+
+/// Changes to a user made on behalf of an actor, each of them recorded in
+/// the user's audit log.
+pub struct Audited<'a> {
+    pub(super) user: &'a mut User,
+    pub(super) actor: &'a str,
+}
+
+impl<'a> Audited<'a> {
+    pub fn set_age(self, new_age: u32) {
+        let user = self.user;
+        let old = user.age.to_string();
+        user.age = new_age;
+        user.audit
+            .record(self.actor, Field::Age, old, new_age.to_string());
+    }
+
+    pub fn set_height(self, new_height: Length) {
+        let user = self.user;
+        let old = user.height.to_string();
+        user.height = new_height;
+        user.audit
+            .record(self.actor, Field::Height, old, new_height.to_string());
+    }
+
+    pub fn set_alert_thresholds(self, thresholds: AlertThresholds) {
+        let user = self.user;
+        let old = thresholds_text(&user.alert_thresholds);
+        let new = thresholds_text(&thresholds);
+        user.alert_thresholds = thresholds;
+        user.audit
+            .record(self.actor, Field::AlertThresholds, old, new);
+    }
+
+    pub fn visit_doctor(
+        self,
+        measurements: Measurements,
+    ) -> Result<HealthReport<'a>, MeasurementError> {
+        self.record_visit(Timestamp::now(), measurements, "")
+    }
+
+    /// As [`User::record_visit`]. A visit that changes the user's height
+    /// is logged as two changes, to the visits and to the height.
+    pub fn record_visit(
+        self,
+        at: Timestamp,
+        measurements: Measurements,
+        notes: &str,
+    ) -> Result<HealthReport<'a>, MeasurementError> {
+        measurements.validate()?;
+        let user = self.user;
+        let old = visits_text(user.history.len());
+        let index = user.history.record(Visit {
+            at,
+            measurements,
+            notes: String::from(notes),
+        });
+        let new = visit_text(user.history.len(), &user.history.visits()[index]);
+        user.audit.record(self.actor, Field::Visits, old, new);
+
+        let height = user.history.visits()[index].measurements.height;
+        if index + 1 == user.history.len() && height != user.height {
+            let old = user.height.to_string();
+            user.height = height;
+            user.audit
+                .record(self.actor, Field::Height, old, height.to_string());
+        }
+        let user: &'a User = user;
+        Ok(user.report(index).expect("visit was just recorded"))
+    }
+}
+
+#[cfg(test)]
+fn sample_user() -> User {
+    let mut bob = User::new(String::from("Bob"), 32, Length::cm(170.0));
+    bob.set_age(33);
+    bob.acting_as("dr.jones")
+        .record_visit(
+            Timestamp(86_400),
+            Measurements::new(
+                Length::cm(171.0),
+                super::BloodPressure::mmhg(120, 80).unwrap(),
+            ),
+            "",
+        )
+        .unwrap();
+    bob.acting_as("nurse.kim").set_height(Length::cm(171.5));
+    bob.acting_as("dr.jones")
+        .set_alert_thresholds(AlertThresholds {
+            systolic_change: 10,
+            ..AlertThresholds::default()
+        });
+    bob
+}
+
+#[test]
+fn test_every_change_is_logged() {
+    let bob = sample_user();
+    let log = bob.audit_log();
+    let changes: Vec<_> = log
+        .entries()
+        .iter()
+        .map(|entry| {
+            (
+                entry.actor.as_str(),
+                entry.field,
+                entry.old.as_str(),
+                entry.new.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (SYSTEM_ACTOR, Field::Age, "32", "33"),
+            (
+                "dr.jones",
+                Field::Visits,
+                "0 visits",
+                "1 visits; visit at 86400: 171.0 cm, 120/80 mmHg"
+            ),
+            ("dr.jones", Field::Height, "170.0 cm", "171.0 cm"),
+            ("nurse.kim", Field::Height, "171.0 cm", "171.5 cm"),
+            (
+                "dr.jones",
+                Field::AlertThresholds,
+                "systolic 20 mmHg, diastolic 10 mmHg, weight 5%, height loss 2.0 cm",
+                "systolic 10 mmHg, diastolic 10 mmHg, weight 5%, height loss 2.0 cm"
+            ),
+        ]
+    );
+    assert_eq!(log.verify(), Ok(()));
+}
+
+#[test]
+fn test_queries() {
+    let mut bob = sample_user();
+    let before = Timestamp::now();
+    bob.visit_doctor(Measurements::new(
+        Length::cm(171.5),
+        super::BloodPressure::mmhg(118, 78).unwrap(),
+    ))
+    .unwrap();
+    let log = bob.audit_log();
+
+    let seqs = |entries: Vec<&AuditEntry>| entries.iter().map(|e| e.seq).collect::<Vec<_>>();
+    assert_eq!(seqs(log.by_actor("dr.jones").collect()), [1, 2, 4]);
+    assert_eq!(seqs(log.changes_to(Field::Height).collect()), [2, 3]);
+    assert_eq!(seqs(log.by_actor("nobody").collect()), []);
+    assert!(seqs(log.between(before..).collect()).ends_with(&[5]));
+    assert_eq!(log.between(..).count(), 6);
+    assert_eq!(log.between(..Timestamp(86_400)).count(), 0);
+    let (actor, at) = log.last_change(Field::Height).unwrap();
+    assert_eq!(actor, "nurse.kim");
+    assert!(at <= before);
+    assert_eq!(log.last_change(Field::Visits).unwrap().0, SYSTEM_ACTOR);
+}
+
+#[test]
+fn test_rejected_visits_are_not_logged() {
+    let mut bob = sample_user();
+    let entries = bob.audit_log().entries().len();
+    let implausible = Measurements::new(
+        Length::cm(1710.0),
+        super::BloodPressure::mmhg(120, 80).unwrap(),
+    );
+    assert!(bob.acting_as("dr.jones").visit_doctor(implausible).is_err());
+    assert_eq!(bob.audit_log().entries().len(), entries);
+}
+
+#[test]
+fn test_tampering_is_detected() {
+    let log = sample_user().audit_log().clone();
+    let head = log.head();
+    assert_eq!(log.verify_head(head), Ok(()));
+
+    let mut entries = log.entries().to_vec();
+    entries[3].actor = String::from("dr.jones");
+    assert_eq!(
+        AuditLog::from_entries(entries).verify(),
+        Err(AuditError::Tampered { seq: 3 })
+    );
+
+    // Rehashing the altered entry doesn't help: the next one points at the
+    // original.
+    let mut entries = log.entries().to_vec();
+    entries[1].new = String::from("1 visits");
+    entries[1].hash = entries[1].compute_hash();
+    assert_eq!(
+        AuditLog::from_entries(entries).verify(),
+        Err(AuditError::BrokenChain { seq: 2 })
+    );
+
+    let mut entries = log.entries().to_vec();
+    entries.remove(2);
+    assert_eq!(
+        AuditLog::from_entries(entries).verify(),
+        Err(AuditError::BrokenChain { seq: 2 })
+    );
+
+    let mut entries = log.entries().to_vec();
+    entries.pop();
+    let truncated = AuditLog::from_entries(entries);
+    assert_eq!(truncated.verify(), Ok(()));
+    assert_eq!(truncated.verify_head(head), Err(AuditError::HeadMismatch));
+}
@@ -0,0 +1,92 @@
+// The ChaCha20 stream cipher (RFC 8439). It only hides data; pair it with
+// a MAC such as `sha256::hmac_sha256` to detect changes.
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 12;
+
+const BLOCK: usize = 64;
+// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; BLOCK] {
+    let words = |bytes: &[u8]| -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().expect("4 bytes")))
+            .collect()
+    };
+    let mut initial = [0u32; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    initial[4..12].copy_from_slice(&words(key));
+    initial[12] = counter;
+    initial[13..].copy_from_slice(&words(nonce));
+
+    let mut state = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut keystream = [0u8; BLOCK];
+    for ((bytes, word), start) in keystream.chunks_exact_mut(4).zip(state).zip(initial) {
+        bytes.copy_from_slice(&word.wrapping_add(start).to_le_bytes());
+    }
+    keystream
+}
+
+/// Encrypt or decrypt `data` in place, starting at block `counter`. A
+/// nonce must never be used twice with the same key.
+pub fn apply_keystream(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    counter: u32,
+    data: &mut [u8],
+) {
+    for (i, chunk) in data.chunks_mut(BLOCK).enumerate() {
+        let counter = u32::try_from(i)
+            .ok()
+            .and_then(|i| counter.checked_add(i))
+            .expect("at most 256 GiB per nonce");
+        for (byte, key_byte) in chunk.iter_mut().zip(block(key, counter, nonce)) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+// The example of section 2.4.2 of RFC 8439.
+#[test]
+fn test_rfc_8439_encryption() {
+    let key: [u8; KEY_LEN] = std::array::from_fn(|i| i as u8);
+    let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+    let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only \
+        one tip for the future, sunscreen would be it.";
+    let mut data = plaintext.to_vec();
+    apply_keystream(&key, &nonce, 1, &mut data);
+    assert_eq!(
+        super::sha256::hex(&data),
+        "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+         f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+         07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+         5af90bbf74a35be6b40b8eedf2785e42874d"
+    );
+
+    apply_keystream(&key, &nonce, 1, &mut data);
+    assert_eq!(data, plaintext);
+}
@@ -0,0 +1,311 @@
+// Clinical interpretation of measurements: blood pressure categories, body
+// mass index, and alerts for worrying changes between visits.
+//
+// Blood pressure bands follow the 2017 ACC/AHA guideline, which the 2017
+// AAP guideline also applies from age 13. BMI bands are the WHO adult ones,
+// used from age 20. Younger patients are classified against growth
+// percentiles instead, so they get no category here.
+
+use std::fmt;
+
+use super::units::{Length, Mass};
+use super::{BloodPressure, Measurements};
+
+const ADULT_BP_FROM_AGE: u32 = 13;
+const ADULT_BMI_FROM_AGE: u32 = 20;
+/// People still growing can't lose height; adults lose it slowly with age.
+const ADULT_HEIGHT_FROM_AGE: u32 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BpCategory {
+    Normal,
+    Elevated,
+    Stage1Hypertension,
+    Stage2Hypertension,
+    HypertensiveCrisis,
+}
+
+impl BpCategory {
+    pub const ALL: [BpCategory; 5] = [
+        BpCategory::Normal,
+        BpCategory::Elevated,
+        BpCategory::Stage1Hypertension,
+        BpCategory::Stage2Hypertension,
+        BpCategory::HypertensiveCrisis,
+    ];
+
+    /// The category of `bp` for a patient aged `age`, if the adult bands
+    /// apply to them.
+    pub fn classify(bp: BloodPressure, age: u32) -> Option<BpCategory> {
+        if age < ADULT_BP_FROM_AGE {
+            return None;
+        }
+        let (systolic, diastolic) = (bp.systolic().as_mmhg(), bp.diastolic().as_mmhg());
+        // A reading takes the higher of its systolic and diastolic bands.
+        Some(if systolic > 180 || diastolic > 120 {
+            BpCategory::HypertensiveCrisis
+        } else if systolic >= 140 || diastolic >= 90 {
+            BpCategory::Stage2Hypertension
+        } else if systolic >= 130 || diastolic >= 80 {
+            BpCategory::Stage1Hypertension
+        } else if systolic >= 120 {
+            BpCategory::Elevated
+        } else {
+            BpCategory::Normal
+        })
+    }
+}
+
+impl fmt::Display for BpCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BpCategory::Normal => "normal",
+            BpCategory::Elevated => "elevated",
+            BpCategory::Stage1Hypertension => "stage 1 hypertension",
+            BpCategory::Stage2Hypertension => "stage 2 hypertension",
+            BpCategory::HypertensiveCrisis => "hypertensive crisis",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Body mass index in kg/m².
+pub fn bmi(height: Length, weight: Mass) -> f32 {
+    weight.as_kg() / height.as_m().powi(2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BmiCategory {
+    Underweight,
+    Normal,
+    Overweight,
+    Obese,
+}
+
+impl BmiCategory {
+    pub const ALL: [BmiCategory; 4] = [
+        BmiCategory::Underweight,
+        BmiCategory::Normal,
+        BmiCategory::Overweight,
+        BmiCategory::Obese,
+    ];
+
+    pub fn classify(bmi: f32, age: u32) -> Option<BmiCategory> {
+        if age < ADULT_BMI_FROM_AGE {
+            return None;
+        }
+        Some(if bmi < 18.5 {
+            BmiCategory::Underweight
+        } else if bmi < 25.0 {
+            BmiCategory::Normal
+        } else if bmi < 30.0 {
+            BmiCategory::Overweight
+        } else {
+            BmiCategory::Obese
+        })
+    }
+}
+
+impl fmt::Display for BmiCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BmiCategory::Underweight => "underweight",
+            BmiCategory::Normal => "normal",
+            BmiCategory::Overweight => "overweight",
+            BmiCategory::Obese => "obese",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How large a change between visits has to be to raise an alert.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertThresholds {
+    /// In mmHg, up or down.
+    pub systolic_change: u32,
+    /// In mmHg, up or down.
+    pub diastolic_change: u32,
+    /// As a percentage of the earlier weight, up or down.
+    pub weight_change_percent: f32,
+    /// Height lost by an adult.
+    pub height_loss: Length,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        AlertThresholds {
+            systolic_change: 20,
+            diastolic_change: 10,
+            weight_change_percent: 5.0,
+            height_loss: Length::cm(2.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alert {
+    /// Needs attention whatever the earlier readings were.
+    HypertensiveCrisis(BloodPressure),
+    SystolicChange(i64),
+    DiastolicChange(i64),
+    WeightChange {
+        percent: f32,
+    },
+    HeightLoss(Length),
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Alert::HypertensiveCrisis(bp) => write!(f, "hypertensive crisis: {bp}"),
+            Alert::SystolicChange(change) => {
+                write!(f, "systolic pressure changed by {change:+} mmHg")
+            }
+            Alert::DiastolicChange(change) => {
+                write!(f, "diastolic pressure changed by {change:+} mmHg")
+            }
+            Alert::WeightChange { percent } => write!(f, "weight changed by {percent:+.1}%"),
+            Alert::HeightLoss(loss) => write!(f, "height decreased by {loss}"),
+        }
+    }
+}
+
+/// Alerts for `current` compared with the visit before it, `previous`, and
+/// the last earlier visit where weight was recorded, `previous_weight`.
+pub fn alerts(
+    previous: Option<&Measurements>,
+    previous_weight: Option<Mass>,
+    current: &Measurements,
+    age: u32,
+    thresholds: &AlertThresholds,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    let bp = current.blood_pressure;
+    if BpCategory::classify(bp, age) == Some(BpCategory::HypertensiveCrisis) {
+        alerts.push(Alert::HypertensiveCrisis(bp));
+    }
+
+    if let Some(previous) = previous {
+        let change = |now: u32, then: u32| i64::from(now) - i64::from(then);
+        let systolic = change(
+            bp.systolic().as_mmhg(),
+            previous.blood_pressure.systolic().as_mmhg(),
+        );
+        if systolic.unsigned_abs() >= u64::from(thresholds.systolic_change) {
+            alerts.push(Alert::SystolicChange(systolic));
+        }
+        let diastolic = change(
+            bp.diastolic().as_mmhg(),
+            previous.blood_pressure.diastolic().as_mmhg(),
+        );
+        if diastolic.unsigned_abs() >= u64::from(thresholds.diastolic_change) {
+            alerts.push(Alert::DiastolicChange(diastolic));
+        }
+
+        let loss = previous.height - current.height;
+        if age >= ADULT_HEIGHT_FROM_AGE && loss >= thresholds.height_loss {
+            alerts.push(Alert::HeightLoss(loss));
+        }
+    }
+
+    if let (Some(before), Some(now)) = (previous_weight, current.weight) {
+        let percent = (now - before).as_kg() / before.as_kg() * 100.0;
+        if percent.abs() >= thresholds.weight_change_percent {
+            alerts.push(Alert::WeightChange { percent });
+        }
+    }
+    alerts
+}
+
+#[cfg(test)]
+fn bp(systolic: u32, diastolic: u32) -> BloodPressure {
+    BloodPressure::mmhg(systolic, diastolic).unwrap()
+}
+
+#[test]
+fn test_bp_categories() {
+    for (systolic, diastolic, category) in [
+        (115, 75, BpCategory::Normal),
+        (125, 75, BpCategory::Elevated),
+        (125, 82, BpCategory::Stage1Hypertension),
+        (135, 70, BpCategory::Stage1Hypertension),
+        (118, 92, BpCategory::Stage2Hypertension),
+        (150, 85, BpCategory::Stage2Hypertension),
+        (185, 100, BpCategory::HypertensiveCrisis),
+        (170, 125, BpCategory::HypertensiveCrisis),
+    ] {
+        assert_eq!(
+            BpCategory::classify(bp(systolic, diastolic), 40),
+            Some(category),
+            "{systolic}/{diastolic}"
+        );
+    }
+    // Adolescents use the adult bands, younger children don't.
+    assert_eq!(
+        BpCategory::classify(bp(125, 75), 13),
+        Some(BpCategory::Elevated)
+    );
+    assert_eq!(BpCategory::classify(bp(125, 75), 12), None);
+}
+
+#[test]
+fn test_bmi() {
+    let value = bmi(Length::cm(180.0), Mass::kg(81.0));
+    assert!((value - 25.0).abs() < 1e-4, "{value}");
+    assert_eq!(
+        BmiCategory::classify(18.4, 30),
+        Some(BmiCategory::Underweight)
+    );
+    assert_eq!(BmiCategory::classify(24.9, 30), Some(BmiCategory::Normal));
+    assert_eq!(
+        BmiCategory::classify(value, 30),
+        Some(BmiCategory::Overweight)
+    );
+    assert_eq!(BmiCategory::classify(30.0, 30), Some(BmiCategory::Obese));
+    assert_eq!(BmiCategory::classify(30.0, 19), None);
+}
+
+#[test]
+fn test_alerts() {
+    let measurements = |height, (systolic, diastolic), weight: Option<f32>| Measurements {
+        height: Length::cm(height),
+        blood_pressure: bp(systolic, diastolic),
+        weight: weight.map(Mass::kg),
+    };
+    let thresholds = AlertThresholds::default();
+    let before = measurements(175.0, (120, 80), Some(80.0));
+
+    let steady = measurements(175.0, (125, 85), Some(81.0));
+    assert_eq!(
+        alerts(Some(&before), before.weight, &steady, 50, &thresholds),
+        []
+    );
+
+    let worse = measurements(172.5, (190, 95), Some(70.0));
+    assert_eq!(
+        alerts(Some(&before), before.weight, &worse, 50, &thresholds),
+        [
+            Alert::HypertensiveCrisis(bp(190, 95)),
+            Alert::SystolicChange(70),
+            Alert::DiastolicChange(15),
+            Alert::HeightLoss(Length::cm(2.5)),
+            Alert::WeightChange { percent: -12.5 },
+        ]
+    );
+    // No crisis band or height rule for young children; the changes still
+    // count.
+    assert_eq!(
+        alerts(Some(&before), None, &worse, 10, &thresholds),
+        [Alert::SystolicChange(70), Alert::DiastolicChange(15)]
+    );
+
+    let strict = AlertThresholds {
+        systolic_change: 5,
+        ..AlertThresholds::default()
+    };
+    assert_eq!(
+        alerts(Some(&before), None, &steady, 50, &strict),
+        [Alert::SystolicChange(5)]
+    );
+    assert_eq!(alerts(None, None, &steady, 50, &strict), []);
+}
@@ -0,0 +1,355 @@
+// De-identified exports of health reports for research.
+//
+// Names are replaced by keyed hashes, so only someone holding the key can
+// link a pseudonym back to a patient, and ages are widened into brackets.
+// The age bracket is then the only quasi-identifier left, and rows in
+// brackets with fewer than `k` patients are dropped so that every row is
+// indistinguishable from at least `k - 1` others by it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeBounds;
+
+use super::clinical::{BmiCategory, BpCategory};
+use super::history::Timestamp;
+use super::registry::{AgeBracket, Registry};
+use super::sha256::{hex, hmac_sha256};
+use super::{HealthReport, PressureChange};
+use crate::json::Value;
+
+// Bytes of the HMAC kept in a pseudonym; 128 bits won't collide by chance.
+const PSEUDONYM_BYTES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    /// Secret key for the pseudonyms. Exports made with the same key give
+    /// a patient the same pseudonym, so keep it for follow-up exports and
+    /// never share it with the recipients.
+    pub key: Vec<u8>,
+    pub bracket_width: u32,
+    /// Smallest number of patients an age bracket may have.
+    pub k: usize,
+}
+
+impl ExportConfig {
+    pub fn validate(&self) -> Result<(), ExportError> {
+        if self.key.is_empty() {
+            return Err(ExportError::EmptyKey);
+        }
+        if self.bracket_width == 0 {
+            return Err(ExportError::ZeroBracketWidth);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportError {
+    /// Pseudonyms made without a secret key could be recomputed by anyone.
+    EmptyKey,
+    ZeroBracketWidth,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::EmptyKey => write!(f, "pseudonyms need a secret key"),
+            ExportError::ZeroBracketWidth => write!(f, "age brackets must span at least a year"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// One report with everything identifying removed or generalized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRow {
+    pub pseudonym: String,
+    pub ages: AgeBracket,
+    pub visit_count: u32,
+    pub height_change_cm: f32,
+    pub blood_pressure_change: PressureChange,
+    pub blood_pressure_category: Option<BpCategory>,
+    pub bmi: Option<f32>,
+    pub bmi_category: Option<BmiCategory>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub rows: Vec<ExportRow>,
+    /// Rows dropped because their age bracket had fewer than `k` patients.
+    pub suppressed: usize,
+}
+
+/// The pseudonym of a patient called `name`. Case and surrounding
+/// whitespace don't matter, as when looking patients up by name.
+pub fn pseudonym(key: &[u8], name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    hex(&hmac_sha256(key, name.as_bytes())[..PSEUDONYM_BYTES])
+}
+
+fn row(report: &HealthReport, age: u32, config: &ExportConfig) -> ExportRow {
+    ExportRow {
+        pseudonym: pseudonym(&config.key, report.patient_name()),
+        ages: AgeBracket::of(age, config.bracket_width),
+        visit_count: report.visit_count,
+        height_change_cm: report.height_change.as_cm(),
+        blood_pressure_change: report.blood_pressure_change,
+        blood_pressure_category: report.blood_pressure_category,
+        bmi: report.bmi,
+        bmi_category: report.bmi_category,
+    }
+}
+
+/// De-identified reports on the latest visit at `times` of each active
+/// patient, youngest bracket first.
+pub fn export(
+    registry: &Registry,
+    times: impl RangeBounds<Timestamp>,
+    config: &ExportConfig,
+) -> Result<Export, ExportError> {
+    config.validate()?;
+    let mut brackets: BTreeMap<AgeBracket, Vec<ExportRow>> = BTreeMap::new();
+    for (id, report) in registry.reports_between(times) {
+        let age = registry.get(id).expect("reported patients exist").age();
+        let row = row(&report, age, config);
+        brackets.entry(row.ages).or_default().push(row);
+    }
+
+    let mut export = Export {
+        rows: Vec::new(),
+        suppressed: 0,
+    };
+    for (_, rows) in brackets {
+        if rows.len() < config.k {
+            export.suppressed += rows.len();
+        } else {
+            export.rows.extend(rows);
+        }
+    }
+    Ok(export)
+}
+
+fn pressure_changes(change: PressureChange) -> Option<(i32, i32)> {
+    match change {
+        PressureChange::NoBaseline => None,
+        PressureChange::Unchanged => Some((0, 0)),
+        PressureChange::Changed {
+            systolic,
+            diastolic,
+        } => Some((systolic, diastolic)),
+    }
+}
+
+fn optional(value: Option<impl ToString>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// None of the fields can contain a comma, quote or newline, so they are
+// written without quoting.
+pub fn write_csv(export: &Export, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "pseudonym,age_bracket,visit_count,height_change_cm,systolic_change,\
+         diastolic_change,bp_category,bmi,bmi_category"
+    )?;
+    for row in &export.rows {
+        let changes = pressure_changes(row.blood_pressure_change);
+        let fields = [
+            row.pseudonym.clone(),
+            row.ages.to_string(),
+            row.visit_count.to_string(),
+            format!("{:.1}", row.height_change_cm),
+            optional(changes.map(|(systolic, _)| systolic)),
+            optional(changes.map(|(_, diastolic)| diastolic)),
+            optional(row.blood_pressure_category),
+            optional(row.bmi.map(|bmi| format!("{bmi:.1}"))),
+            optional(row.bmi_category),
+        ];
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+impl ExportRow {
+    fn to_json(&self) -> Value {
+        let changes = pressure_changes(self.blood_pressure_change);
+        let rounded = |value: f32| (f64::from(value) * 10.0).round() / 10.0;
+        Value::object([
+            ("pseudonym", Value::from(self.pseudonym.as_str())),
+            ("age_bracket", Value::from(self.ages.to_string())),
+            ("visit_count", Value::from(self.visit_count)),
+            (
+                "height_change_cm",
+                Value::from(rounded(self.height_change_cm)),
+            ),
+            (
+                "systolic_change",
+                Value::from(changes.map(|(systolic, _)| i64::from(systolic))),
+            ),
+            (
+                "diastolic_change",
+                Value::from(changes.map(|(_, diastolic)| i64::from(diastolic))),
+            ),
+            (
+                "bp_category",
+                Value::from(self.blood_pressure_category.map(|c| c.to_string())),
+            ),
+            ("bmi", Value::from(self.bmi.map(rounded))),
+            (
+                "bmi_category",
+                Value::from(self.bmi_category.map(|c| c.to_string())),
+            ),
+        ])
+    }
+}
+
+/// A JSON array of rows, on one line.
+pub fn write_json(export: &Export, out: &mut dyn Write) -> io::Result<()> {
+    let rows = export.rows.iter().map(ExportRow::to_json).collect();
+    writeln!(out, "{}", Value::Array(rows))
+}
+
+#[cfg(test)]
+fn sample_registry() -> Registry {
+    use super::units::{Length, Mass};
+    use super::{BloodPressure, Measurements, User};
+
+    let mut registry = Registry::new();
+    // Three patients in their thirties and one in their seventies.
+    for (name, age, systolic) in [
+        ("Alice Smith", 34, 120),
+        ("Bob Jones", 38, 135),
+        ("Carol White", 31, 118),
+        ("Dmitri Ivanov", 72, 150),
+    ] {
+        let id = registry.register(User::new(String::from(name), age, Length::cm(200.0)));
+        let user = registry.get_mut(id).unwrap();
+        for (day, change) in [(10, 0), (20, 4)] {
+            let measurements = Measurements::new(
+                Length::cm(200.0),
+                BloodPressure::mmhg(systolic + change, 75).unwrap(),
+            )
+            .with_weight(Mass::kg(100.0));
+            let notes = format!("{name} phoned ahead");
+            user.record_visit(Timestamp(day * 86_400), measurements, &notes)
+                .unwrap();
+        }
+    }
+    registry
+}
+
+#[cfg(test)]
+fn config(k: usize) -> ExportConfig {
+    ExportConfig {
+        key: b"research key".to_vec(),
+        bracket_width: 10,
+        k,
+    }
+}
+
+#[cfg(test)]
+fn to_string(export: &Export, write: fn(&Export, &mut dyn Write) -> io::Result<()>) -> String {
+    let mut out = Vec::new();
+    write(export, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_pseudonym() {
+    let alice = pseudonym(b"key", "Alice Smith");
+    assert_eq!(alice.len(), 2 * PSEUDONYM_BYTES);
+    assert_eq!(pseudonym(b"key", " alice smith"), alice);
+    assert_ne!(pseudonym(b"other key", "Alice Smith"), alice);
+    assert_ne!(pseudonym(b"key", "Bob Jones"), alice);
+}
+
+#[test]
+fn test_small_brackets_are_suppressed() {
+    let registry = sample_registry();
+    let thirties = export(&registry, .., &config(3)).unwrap();
+    assert_eq!(thirties.suppressed, 1);
+    assert_eq!(thirties.rows.len(), 3);
+    assert!(thirties
+        .rows
+        .iter()
+        .all(|row| row.ages.to_string() == "30-39"));
+
+    assert_eq!(export(&registry, .., &config(1)).unwrap().suppressed, 0);
+    let nobody = export(&registry, .., &config(5)).unwrap();
+    assert_eq!((nobody.rows.len(), nobody.suppressed), (0, 4));
+}
+
+#[test]
+fn test_invalid_config() {
+    let registry = sample_registry();
+    let no_key = ExportConfig {
+        key: Vec::new(),
+        ..config(3)
+    };
+    assert_eq!(export(&registry, .., &no_key), Err(ExportError::EmptyKey));
+    let no_width = ExportConfig {
+        bracket_width: 0,
+        ..config(3)
+    };
+    assert_eq!(
+        export(&registry, .., &no_width),
+        Err(ExportError::ZeroBracketWidth)
+    );
+}
+
+#[test]
+fn test_no_direct_identifiers_survive() {
+    let registry = sample_registry();
+    let everyone = export(&registry, .., &config(1)).unwrap();
+    let csv = to_string(&everyone, write_csv);
+    let json = to_string(&everyone, write_json);
+
+    for output in [&csv, &json] {
+        for (_, user) in registry.patients() {
+            for part in user.name().split_whitespace() {
+                assert!(!output.contains(part), "{part} in {output}");
+                assert!(!output.to_lowercase().contains(&part.to_lowercase()));
+            }
+        }
+        // Free-text notes and visit times may identify a patient too.
+        assert!(!output.contains("phoned"));
+        assert!(!output.contains(&(20 * 86_400).to_string()));
+    }
+    assert_eq!(csv.lines().count(), 1 + registry.patients().count());
+
+    // Exact ages only survive inside their brackets.
+    let json = crate::json::parse(&json).unwrap();
+    for (row, (_, user)) in json.as_array().unwrap().iter().zip(registry.patients()) {
+        let Value::Object(fields) = row else {
+            panic!("{row} is not an object")
+        };
+        let age = Value::from(user.age());
+        assert!(fields.iter().all(|(_, value)| *value != age), "{row}");
+    }
+}
+
+#[test]
+fn test_formats() {
+    let registry = sample_registry();
+    let thirties = export(&registry, .., &config(3)).unwrap();
+    let alice = pseudonym(b"research key", "Alice Smith");
+
+    let csv = to_string(&thirties, write_csv);
+    assert!(csv.starts_with("pseudonym,age_bracket,visit_count,"));
+    assert!(csv.contains(&format!(
+        "{alice},30-39,2,0.0,4,0,elevated,25.0,overweight\n"
+    )));
+
+    let json = crate::json::parse(&to_string(&thirties, write_json)).unwrap();
+    let rows = json.as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0].get("pseudonym").unwrap().as_str(), Some(&*alice));
+    assert_eq!(rows[0].get("systolic_change").unwrap().as_f64(), Some(4.0));
+    assert_eq!(rows[0].get("bmi").unwrap().as_f64(), Some(25.0));
+    assert_eq!(
+        rows[0].get("bmi_category").unwrap().as_str(),
+        Some("overweight")
+    );
+}
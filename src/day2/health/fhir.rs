@@ -0,0 +1,790 @@
+// FHIR R4 JSON for exchanging patients and their visits with an EHR.
+//
+// A user is a Patient resource and each visit is a set of vital-sign
+// Observations taken at the same time: body height, a blood pressure panel
+// and, when measured, body weight, all coded with LOINC and UCUM units.
+// FHIR records a birth date rather than an age, so the age travels in an
+// extension.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::history::{Timestamp, Visit};
+use super::units::{Length, Mass, Pressure};
+use super::{BloodPressure, MeasurementError, Measurements, User};
+use crate::json::{self, ParseError, Value};
+
+const LOINC: &str = "http://loinc.org";
+const UCUM: &str = "http://unitsofmeasure.org";
+const OBSERVATION_CATEGORY: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+const AGE_EXTENSION: &str = "https://foobar.example/fhir/StructureDefinition/age-in-years";
+
+const BODY_HEIGHT: &str = "8302-2";
+const BODY_WEIGHT: &str = "29463-7";
+const BLOOD_PRESSURE_PANEL: &str = "85354-9";
+const SYSTOLIC: &str = "8480-6";
+const DIASTOLIC: &str = "8462-4";
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FhirError {
+    Json(ParseError),
+    /// A required element, by its path from the resource.
+    Missing(String),
+    Invalid {
+        element: String,
+        value: String,
+    },
+    /// Two observations of the same kind at the same time.
+    Duplicate {
+        code: &'static str,
+        at: String,
+    },
+    Measurement(MeasurementError),
+}
+
+impl fmt::Display for FhirError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FhirError::Json(err) => write!(f, "invalid JSON: {err}"),
+            FhirError::Missing(element) => write!(f, "missing {element}"),
+            FhirError::Invalid { element, value } => write!(f, "invalid {element}: {value}"),
+            FhirError::Duplicate { code, at } => {
+                write!(f, "more than one observation of {code} at {at}")
+            }
+            FhirError::Measurement(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for FhirError {}
+
+impl From<ParseError> for FhirError {
+    fn from(err: ParseError) -> Self {
+        FhirError::Json(err)
+    }
+}
+
+impl From<MeasurementError> for FhirError {
+    fn from(err: MeasurementError) -> Self {
+        FhirError::Measurement(err)
+    }
+}
+
+/// A Patient resource and the visits recorded for it, as exchanged in a
+/// collection Bundle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatientBundle {
+    /// The Patient's logical id, which the Observations refer to.
+    pub id: String,
+    pub name: String,
+    pub age: u32,
+    pub visits: Vec<Visit>,
+}
+
+impl PatientBundle {
+    pub fn from_user(user: &User, id: &str) -> PatientBundle {
+        PatientBundle {
+            id: String::from(id),
+            name: user.name.clone(),
+            age: user.age,
+            visits: user.history.visits().to_vec(),
+        }
+    }
+
+    /// A user with the bundle's visits recorded through
+    /// [`User::record_visit`], as if each had gone through `visit_doctor`
+    /// at the time it was made. Their height starts at the one measured at
+    /// the first visit.
+    pub fn into_user(self) -> Result<User, FhirError> {
+        let first = self
+            .visits
+            .first()
+            .ok_or_else(|| FhirError::Missing(format!("Observation {BODY_HEIGHT}")))?;
+        let mut user = User::new(self.name, self.age, first.measurements.height);
+        for visit in self.visits {
+            user.record_visit(visit.at, visit.measurements, &visit.notes)?;
+        }
+        Ok(user)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut entries = vec![self.patient()];
+        for (index, visit) in self.visits.iter().enumerate() {
+            entries.extend(self.observations(index, visit));
+        }
+        let entries = entries
+            .into_iter()
+            .map(|resource| Value::object([("resource", resource)]))
+            .collect();
+        Value::object([
+            ("resourceType", Value::from("Bundle")),
+            ("type", Value::from("collection")),
+            ("entry", Value::Array(entries)),
+        ])
+    }
+
+    pub fn parse(input: &str) -> Result<PatientBundle, FhirError> {
+        PatientBundle::from_json(&json::parse(input)?)
+    }
+
+    /// Read a Bundle holding one Patient and Observations about them.
+    /// Observations taken at the same time make up one visit, which needs
+    /// a height and a blood pressure; other kinds of Observation are
+    /// ignored.
+    pub fn from_json(bundle: &Value) -> Result<PatientBundle, FhirError> {
+        expect_str(bundle, "Bundle.resourceType", "Bundle")?;
+        let entries = array(bundle, "entry", "Bundle.entry")?;
+        let resources = entries
+            .iter()
+            .map(|entry| require(entry, "resource", "Bundle.entry.resource"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let is = |resource: &Value, kind: &str| {
+            resource.get("resourceType").and_then(Value::as_str) == Some(kind)
+        };
+
+        let mut patients = resources.iter().filter(|resource| is(resource, "Patient"));
+        let patient = patients
+            .next()
+            .ok_or_else(|| FhirError::Missing(String::from("Patient")))?;
+        if patients.next().is_some() {
+            return Err(invalid("Bundle.entry", "more than one Patient"));
+        }
+        let id = string(patient, "id", "Patient.id")?;
+        let subject = format!("Patient/{id}");
+
+        let mut visits: BTreeMap<Timestamp, PartialVisit> = BTreeMap::new();
+        for observation in resources
+            .iter()
+            .filter(|resource| is(resource, "Observation"))
+        {
+            let reference = require(observation, "subject", "Observation.subject")?;
+            let reference = string(reference, "reference", "Observation.subject.reference")?;
+            if reference != subject {
+                return Err(invalid("Observation.subject.reference", reference));
+            }
+            let effective = string(
+                observation,
+                "effectiveDateTime",
+                "Observation.effectiveDateTime",
+            )?;
+            let at = parse_date_time(effective)
+                .ok_or_else(|| invalid("Observation.effectiveDateTime", effective))?;
+            visits.entry(at).or_default().add(observation, effective)?;
+        }
+
+        Ok(PatientBundle {
+            id: String::from(id),
+            name: patient_name(patient)?,
+            age: patient_age(patient)?,
+            visits: visits
+                .into_iter()
+                .map(|(at, partial)| partial.into_visit(at))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn patient(&self) -> Value {
+        Value::object([
+            ("resourceType", Value::from("Patient")),
+            ("id", Value::from(self.id.as_str())),
+            (
+                "extension",
+                Value::Array(vec![Value::object([
+                    ("url", Value::from(AGE_EXTENSION)),
+                    ("valueInteger", Value::from(self.age)),
+                ])]),
+            ),
+            (
+                "name",
+                Value::Array(vec![Value::object([(
+                    "text",
+                    Value::from(self.name.as_str()),
+                )])]),
+            ),
+        ])
+    }
+
+    fn observations(&self, index: usize, visit: &Visit) -> Vec<Value> {
+        let measurements = &visit.measurements;
+        let observation = |kind: &str, code: &str, display: &str, value: (&str, Value)| {
+            let mut fields = vec![
+                ("resourceType", Value::from("Observation")),
+                ("id", Value::from(format!("{}-{index}-{kind}", self.id))),
+                ("status", Value::from("final")),
+                (
+                    "category",
+                    Value::Array(vec![Value::object([(
+                        "coding",
+                        Value::Array(vec![Value::object([
+                            ("system", Value::from(OBSERVATION_CATEGORY)),
+                            ("code", Value::from("vital-signs")),
+                        ])]),
+                    )])]),
+                ),
+                ("code", concept(code, display)),
+                (
+                    "subject",
+                    Value::object([("reference", Value::from(format!("Patient/{}", self.id)))]),
+                ),
+                ("effectiveDateTime", Value::from(format_date_time(visit.at))),
+                value,
+            ];
+            if !visit.notes.is_empty() {
+                fields.push((
+                    "note",
+                    Value::Array(vec![Value::object([(
+                        "text",
+                        Value::from(visit.notes.as_str()),
+                    )])]),
+                ));
+            }
+            Value::object(fields)
+        };
+
+        let bp = measurements.blood_pressure;
+        let component = |code, display, pressure: Pressure| {
+            Value::object([
+                ("code", concept(code, display)),
+                (
+                    "valueQuantity",
+                    quantity(f64::from(pressure.as_mmhg()), "mm[Hg]", "mmHg"),
+                ),
+            ])
+        };
+        let mut observations = vec![
+            observation(
+                "height",
+                BODY_HEIGHT,
+                "Body height",
+                (
+                    "valueQuantity",
+                    quantity(tenths(measurements.height.as_cm()), "cm", "cm"),
+                ),
+            ),
+            observation(
+                "blood-pressure",
+                BLOOD_PRESSURE_PANEL,
+                "Blood pressure panel with all children optional",
+                (
+                    "component",
+                    Value::Array(vec![
+                        component(SYSTOLIC, "Systolic blood pressure", bp.systolic()),
+                        component(DIASTOLIC, "Diastolic blood pressure", bp.diastolic()),
+                    ]),
+                ),
+            ),
+        ];
+        if let Some(weight) = measurements.weight {
+            observations.push(observation(
+                "weight",
+                BODY_WEIGHT,
+                "Body weight",
+                (
+                    "valueQuantity",
+                    quantity(tenths(weight.as_kg()), "kg", "kg"),
+                ),
+            ));
+        }
+        observations
+    }
+}
+
+// The observations of one visit, as they are found.
+#[derive(Default)]
+struct PartialVisit {
+    height: Option<Length>,
+    blood_pressure: Option<BloodPressure>,
+    weight: Option<Mass>,
+    notes: Vec<String>,
+}
+
+impl PartialVisit {
+    fn add(&mut self, observation: &Value, at: &str) -> Result<(), FhirError> {
+        let code = require(observation, "code", "Observation.code")?;
+        let duplicate = |code| FhirError::Duplicate {
+            code,
+            at: String::from(at),
+        };
+        if has_code(code, BODY_HEIGHT) {
+            let height = length(value_quantity(observation)?)?;
+            self.height
+                .replace(height)
+                .map_or(Ok(()), |_| Err(duplicate(BODY_HEIGHT)))?;
+        } else if has_code(code, BODY_WEIGHT) {
+            let weight = mass(value_quantity(observation)?)?;
+            self.weight
+                .replace(weight)
+                .map_or(Ok(()), |_| Err(duplicate(BODY_WEIGHT)))?;
+        } else if has_code(code, BLOOD_PRESSURE_PANEL) {
+            let bp = blood_pressure(observation)?;
+            self.blood_pressure
+                .replace(bp)
+                .map_or(Ok(()), |_| Err(duplicate(BLOOD_PRESSURE_PANEL)))?;
+        } else {
+            return Ok(());
+        }
+
+        if let Some(notes) = observation.get("note").and_then(Value::as_array) {
+            for note in notes {
+                let text = string(note, "text", "Observation.note.text")?;
+                if !self.notes.iter().any(|seen| seen == text) {
+                    self.notes.push(String::from(text));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn into_visit(self, at: Timestamp) -> Result<Visit, FhirError> {
+        let missing =
+            |code| FhirError::Missing(format!("Observation {code} at {}", format_date_time(at)));
+        let height = self.height.ok_or_else(|| missing(BODY_HEIGHT))?;
+        let blood_pressure = self
+            .blood_pressure
+            .ok_or_else(|| missing(BLOOD_PRESSURE_PANEL))?;
+        let mut measurements = Measurements::new(height, blood_pressure);
+        if let Some(weight) = self.weight {
+            measurements = measurements.with_weight(weight);
+        }
+        Ok(Visit {
+            at,
+            measurements,
+            notes: self.notes.join("\n"),
+        })
+    }
+}
+
+fn concept(code: &str, display: &str) -> Value {
+    Value::object([(
+        "coding",
+        Value::Array(vec![Value::object([
+            ("system", Value::from(LOINC)),
+            ("code", Value::from(code)),
+            ("display", Value::from(display)),
+        ])]),
+    )])
+}
+
+fn quantity(value: f64, code: &str, unit: &str) -> Value {
+    Value::object([
+        ("value", Value::from(value)),
+        ("unit", Value::from(unit)),
+        ("system", Value::from(UCUM)),
+        ("code", Value::from(code)),
+    ])
+}
+
+// Rounded to a tenth, the precision heights and weights are taken to,
+// without the noise of widening an `f32`.
+fn tenths(value: f32) -> f64 {
+    (f64::from(value) * 10.0).round() / 10.0
+}
+
+fn invalid(element: &str, value: &str) -> FhirError {
+    FhirError::Invalid {
+        element: String::from(element),
+        value: String::from(value),
+    }
+}
+
+fn require<'a>(value: &'a Value, key: &str, path: &str) -> Result<&'a Value, FhirError> {
+    value
+        .get(key)
+        .ok_or_else(|| FhirError::Missing(String::from(path)))
+}
+
+fn string<'a>(value: &'a Value, key: &str, path: &str) -> Result<&'a str, FhirError> {
+    let field = require(value, key, path)?;
+    field
+        .as_str()
+        .ok_or_else(|| invalid(path, &field.to_string()))
+}
+
+fn array<'a>(value: &'a Value, key: &str, path: &str) -> Result<&'a [Value], FhirError> {
+    let field = require(value, key, path)?;
+    field
+        .as_array()
+        .ok_or_else(|| invalid(path, &field.to_string()))
+}
+
+fn expect_str(value: &Value, path: &str, expected: &str) -> Result<(), FhirError> {
+    let key = path.rsplit('.').next().expect("paths are not empty");
+    match string(value, key, path)? {
+        found if found == expected => Ok(()),
+        found => Err(invalid(path, found)),
+    }
+}
+
+fn has_code(concept: &Value, code: &str) -> bool {
+    let codings = concept.get("coding").and_then(Value::as_array);
+    codings.unwrap_or_default().iter().any(|coding| {
+        coding.get("system").and_then(Value::as_str) == Some(LOINC)
+            && coding.get("code").and_then(Value::as_str) == Some(code)
+    })
+}
+
+fn patient_name(patient: &Value) -> Result<String, FhirError> {
+    let name = array(patient, "name", "Patient.name")?
+        .first()
+        .ok_or_else(|| FhirError::Missing(String::from("Patient.name")))?;
+    if let Some(text) = name.get("text").and_then(Value::as_str) {
+        return Ok(String::from(text));
+    }
+    // Without a display text, the given names followed by the family name.
+    let mut parts: Vec<&str> = name
+        .get("given")
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    parts.extend(name.get("family").and_then(Value::as_str));
+    if parts.is_empty() {
+        return Err(FhirError::Missing(String::from("Patient.name.text")));
+    }
+    Ok(parts.join(" "))
+}
+
+fn patient_age(patient: &Value) -> Result<u32, FhirError> {
+    let extensions = patient
+        .get("extension")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+    let age = extensions
+        .iter()
+        .find(|extension| extension.get("url").and_then(Value::as_str) == Some(AGE_EXTENSION))
+        .ok_or_else(|| FhirError::Missing(format!("Patient.extension {AGE_EXTENSION}")))?;
+    let value = require(age, "valueInteger", "Patient.extension.valueInteger")?;
+    value
+        .as_u64()
+        .and_then(|age| u32::try_from(age).ok())
+        .ok_or_else(|| invalid("Patient.extension.valueInteger", &value.to_string()))
+}
+
+fn value_quantity(observation: &Value) -> Result<(f64, &str), FhirError> {
+    quantity_value(
+        require(observation, "valueQuantity", "Observation.valueQuantity")?,
+        "Observation.valueQuantity",
+    )
+}
+
+// The value and UCUM unit code of a Quantity.
+fn quantity_value<'a>(quantity: &'a Value, path: &str) -> Result<(f64, &'a str), FhirError> {
+    let value = require(quantity, "value", &format!("{path}.value"))?;
+    let value = value
+        .as_f64()
+        .ok_or_else(|| invalid(&format!("{path}.value"), &value.to_string()))?;
+    let code = string(quantity, "code", &format!("{path}.code"))?;
+    if quantity.get("system").and_then(Value::as_str) != Some(UCUM) {
+        return Err(invalid(&format!("{path}.system"), "expected UCUM"));
+    }
+    Ok((value, code))
+}
+
+fn length((value, unit): (f64, &str)) -> Result<Length, FhirError> {
+    let value = value as f32;
+    match unit {
+        "cm" => Ok(Length::cm(value)),
+        "m" => Ok(Length::m(value)),
+        "[in_i]" => Ok(Length::inches(value)),
+        _ => Err(invalid("body height unit", unit)),
+    }
+}
+
+fn mass((value, unit): (f64, &str)) -> Result<Mass, FhirError> {
+    let value = value as f32;
+    match unit {
+        "kg" => Ok(Mass::kg(value)),
+        "[lb_av]" => Ok(Mass::pounds(value)),
+        _ => Err(invalid("body weight unit", unit)),
+    }
+}
+
+fn pressure((value, unit): (f64, &str)) -> Result<Pressure, FhirError> {
+    match unit {
+        "mm[Hg]" if value >= 0.0 => Ok(Pressure::mmhg(value.round() as u32)),
+        "kPa" => Ok(Pressure::kpa(value as f32)),
+        _ => Err(invalid("blood pressure", &format!("{value} {unit}"))),
+    }
+}
+
+fn blood_pressure(observation: &Value) -> Result<BloodPressure, FhirError> {
+    let components = array(observation, "component", "Observation.component")?;
+    let component = |code| {
+        let component = components
+            .iter()
+            .find(|component| component.get("code").is_some_and(|c| has_code(c, code)))
+            .ok_or_else(|| FhirError::Missing(format!("Observation.component {code}")))?;
+        let quantity = require(
+            component,
+            "valueQuantity",
+            "Observation.component.valueQuantity",
+        )?;
+        pressure(quantity_value(
+            quantity,
+            "Observation.component.valueQuantity",
+        )?)
+    };
+    Ok(BloodPressure::new(
+        component(SYSTOLIC)?,
+        component(DIASTOLIC)?,
+    )?)
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = i64::from((month + 9) % 12);
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = (month_from_march + 2) as u32 % 12 + 1;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `at` as a FHIR dateTime in UTC, such as `2024-03-01T09:30:00Z`.
+fn format_date_time(at: Timestamp) -> String {
+    let (days, seconds) = (at.0 / SECONDS_PER_DAY, at.0 % SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// A FHIR dateTime with a time of day, `YYYY-MM-DDThh:mm:ss`, optional
+/// fractional seconds, which are dropped, and `Z` or a `+hh:mm` offset.
+fn parse_date_time(text: &str) -> Option<Timestamp> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = text.get(range)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if !separators
+        .iter()
+        .all(|&(at, separator)| text.as_bytes().get(at) == Some(&separator))
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let (month, day) = (u32::try_from(month).ok()?, u32::try_from(day).ok()?);
+    let days = days_from_civil(year, month, day);
+    if !(1..=12).contains(&month) || civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        b"Z" => 0,
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] => {
+            let digit = |b: &u8| b.is_ascii_digit().then(|| i64::from(b - b'0'));
+            let (hours, minutes) = (digit(h1)? * 10 + digit(h2)?, digit(m1)? * 10 + digit(m2)?);
+            if hours > 14 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        _ => return None,
+    };
+
+    let seconds = days * SECONDS_PER_DAY as i64 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(seconds).ok().map(Timestamp)
+}
+
+#[cfg(test)]
+const ADULT_BUNDLE: &str = include_str!("fhir/adult-bundle.json");
+#[cfg(test)]
+const CHILD_BUNDLE: &str = include_str!("fhir/child-bundle.json");
+
+#[test]
+fn test_date_times() {
+    assert_eq!(format_date_time(Timestamp(0)), "1970-01-01T00:00:00Z");
+    assert_eq!(
+        format_date_time(Timestamp(1_709_285_400)),
+        "2024-03-01T09:30:00Z"
+    );
+    assert_eq!(
+        parse_date_time("2024-03-01T09:30:00Z"),
+        Some(Timestamp(1_709_285_400))
+    );
+    assert_eq!(
+        parse_date_time("2024-03-01T10:30:00.250+01:00"),
+        Some(Timestamp(1_709_285_400))
+    );
+    assert_eq!(
+        parse_date_time("2024-02-29T23:00:00-10:30"),
+        parse_date_time("2024-03-01T09:30:00Z")
+    );
+    for invalid in [
+        "2023-02-29T00:00:00Z",
+        "2024-13-01T00:00:00Z",
+        "2024-03-01T24:00:00Z",
+        "2024-03-01T09:30:00",
+        "2024-03-01",
+        "1969-12-31T23:59:59Z",
+        "2024-03-01T09:30:00.Z",
+    ] {
+        assert_eq!(parse_date_time(invalid), None, "{invalid}");
+    }
+    // Every day for a few centuries survives the round trip.
+    for days in (0..200_000).step_by(7) {
+        let at = Timestamp(days * SECONDS_PER_DAY + 45_296);
+        assert_eq!(parse_date_time(&format_date_time(at)), Some(at));
+    }
+}
+
+#[test]
+fn test_sample_bundles_round_trip() {
+    for sample in [ADULT_BUNDLE, CHILD_BUNDLE] {
+        let bundle = PatientBundle::parse(sample).unwrap();
+        assert_eq!(bundle.to_json(), json::parse(sample).unwrap());
+
+        let user = bundle.clone().into_user().unwrap();
+        assert_eq!(PatientBundle::from_user(&user, &bundle.id), bundle);
+    }
+}
+
+#[test]
+fn test_replay_sample_bundle() {
+    let bundle = PatientBundle::parse(ADULT_BUNDLE).unwrap();
+    assert_eq!(bundle.name, "Maria Garcia");
+    assert_eq!(bundle.age, 47);
+    assert_eq!(bundle.visits.len(), 3);
+    assert_eq!(bundle.visits[1].notes, "Started on lisinopril.");
+
+    let user = bundle.into_user().unwrap();
+    assert_eq!(user.doctor_visits(), 3);
+    assert_eq!(user.height(), Length::cm(163.5));
+    let report = user.report(2).unwrap();
+    assert_eq!(
+        report.blood_pressure_change,
+        super::PressureChange::Changed {
+            systolic: -14,
+            diastolic: -6
+        }
+    );
+    assert_eq!(
+        report.bmi_category,
+        Some(super::clinical::BmiCategory::Overweight)
+    );
+}
+
+#[test]
+fn test_parse_other_units_and_names() {
+    let input = r#"{"resourceType": "Bundle", "type": "collection", "entry": [
+        {"resource": {"resourceType": "Patient", "id": "p1",
+            "extension": [{"url": "https://foobar.example/fhir/StructureDefinition/age-in-years",
+                "valueInteger": 30}],
+            "name": [{"family": "Okafor", "given": ["Ada", "N."]}]}},
+        {"resource": {"resourceType": "Observation", "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "8302-2"}]},
+            "subject": {"reference": "Patient/p1"},
+            "effectiveDateTime": "2024-03-01T10:30:00+01:00",
+            "valueQuantity": {"value": 65, "system": "http://unitsofmeasure.org", "code": "[in_i]"}}},
+        {"resource": {"resourceType": "Observation", "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "85354-9"}]},
+            "subject": {"reference": "Patient/p1"},
+            "effectiveDateTime": "2024-03-01T09:30:00Z",
+            "component": [
+                {"code": {"coding": [{"system": "http://loinc.org", "code": "8462-4"}]},
+                 "valueQuantity": {"value": 10.7, "system": "http://unitsofmeasure.org", "code": "kPa"}},
+                {"code": {"coding": [{"system": "http://loinc.org", "code": "8480-6"}]},
+                 "valueQuantity": {"value": 16, "system": "http://unitsofmeasure.org", "code": "kPa"}}
+            ]}},
+        {"resource": {"resourceType": "Observation", "status": "final",
+            "code": {"coding": [{"system": "http://loinc.org", "code": "8867-4"}]},
+            "subject": {"reference": "Patient/p1"},
+            "effectiveDateTime": "2024-03-01T09:30:00Z",
+            "valueQuantity": {"value": 72, "system": "http://unitsofmeasure.org", "code": "/min"}}}
+    ]}"#;
+    let bundle = PatientBundle::parse(input).unwrap();
+    assert_eq!(bundle.name, "Ada N. Okafor");
+    assert_eq!(bundle.visits.len(), 1);
+    let measurements = &bundle.visits[0].measurements;
+    assert_eq!(
+        measurements.blood_pressure,
+        BloodPressure::mmhg(120, 80).unwrap()
+    );
+    assert!((measurements.height.as_cm() - 165.1).abs() < 1e-3);
+}
+
+#[test]
+fn test_parse_errors() {
+    let sample = json::parse(ADULT_BUNDLE).unwrap();
+    let Value::Object(fields) = &sample else {
+        panic!("the sample is an object")
+    };
+    let with_entries = |keep: &dyn Fn(&Value) -> bool| {
+        let entries = sample.get("entry").unwrap().as_array().unwrap();
+        let mut fields = fields.clone();
+        fields[2].1 = Value::Array(entries.iter().filter(|e| keep(e)).cloned().collect());
+        PatientBundle::from_json(&Value::Object(fields))
+    };
+    let id = |entry: &Value| {
+        let resource = entry.get("resource").unwrap();
+        resource.get("id").unwrap().as_str().unwrap().to_owned()
+    };
+
+    assert!(matches!(
+        with_entries(&|entry| !id(entry).ends_with("-1-blood-pressure")),
+        Err(FhirError::Missing(element)) if element.contains(BLOOD_PRESSURE_PANEL)
+    ));
+    assert!(matches!(
+        with_entries(&|entry| id(entry).starts_with("maria-")),
+        Err(FhirError::Missing(element)) if element == "Patient"
+    ));
+    assert!(matches!(
+        PatientBundle::parse("{\"resourceType\": \"Patient\"}"),
+        Err(FhirError::Invalid { .. })
+    ));
+    assert!(matches!(
+        PatientBundle::parse("[1,"),
+        Err(FhirError::Json(_))
+    ));
+
+    let implausible = ADULT_BUNDLE.replacen("\"value\": 163.5", "\"value\": 1635", 1);
+    assert!(matches!(
+        PatientBundle::parse(&implausible).unwrap().into_user(),
+        Err(FhirError::Measurement(MeasurementError::ImplausibleHeight(
+            _
+        )))
+    ));
+}
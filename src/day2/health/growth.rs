@@ -0,0 +1,534 @@
+// Height-for-age percentiles for children, by the LMS method.
+//
+// A growth reference gives, for each sex and age, the three LMS parameters
+// of the height distribution: the Box-Cox power L, the median M and the
+// coefficient of variation S. A height X is then at
+//
+//   z = ((X / M)^L - 1) / (L * S)      (z = ln(X / M) / S when L = 0)
+//
+// standard deviations from the median, and at the percentile of z under
+// the standard normal distribution. Parameters between two tabulated ages
+// are interpolated linearly.
+//
+// No reference data is bundled. Load the official tables, such as the WHO
+// 2007 height-for-age LMS tables, converted to the CSV format described at
+// `GrowthReference::load`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::history::{History, Timestamp};
+use super::units::Length;
+use super::User;
+
+#[cfg(test)]
+const TEST_REFERENCE: &str = include_str!("growth/test-reference.csv");
+
+const DAYS_PER_MONTH: f64 = 365.25 / 12.0;
+
+/// z-scores of the percentile curves drawn on charts, and their marks.
+const CHART_CURVES: [(f64, char); 5] = [
+    (-1.880_794, '.'),
+    (-1.036_433, '-'),
+    (0.0, '='),
+    (1.036_433, '-'),
+    (1.880_794, '.'),
+];
+const MEASURED_MARK: char = '*';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sex {
+    Male,
+    Female,
+}
+
+/// The LMS parameters at one age.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lms {
+    pub l: f64,
+    pub m: f64,
+    pub s: f64,
+}
+
+impl Lms {
+    pub fn z_score(&self, value: f64) -> f64 {
+        let ratio = value / self.m;
+        if self.l.abs() < 1e-9 {
+            ratio.ln() / self.s
+        } else {
+            (ratio.powf(self.l) - 1.0) / (self.l * self.s)
+        }
+    }
+
+    /// The value `z` standard deviations from the median.
+    pub fn value_at(&self, z: f64) -> f64 {
+        if self.l.abs() < 1e-9 {
+            self.m * (self.s * z).exp()
+        } else {
+            self.m * (1.0 + self.l * self.s * z).powf(1.0 / self.l)
+        }
+    }
+}
+
+/// Probability that a standard normal variable is below `z`, to within
+/// about 1e-7 (Abramowitz and Stegun 7.1.26).
+pub fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    if z >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthAssessment {
+    pub z_score: f64,
+    /// From 0 to 100.
+    pub percentile: f64,
+}
+
+/// A height measured at an age.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthPoint {
+    pub age_months: f64,
+    pub height: Length,
+}
+
+#[derive(Debug)]
+pub enum ReferenceError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReferenceError::Io(err) => write!(f, "i/o error: {err}"),
+            ReferenceError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ReferenceError {}
+
+impl From<io::Error> for ReferenceError {
+    fn from(err: io::Error) -> Self {
+        ReferenceError::Io(err)
+    }
+}
+
+/// LMS tables by sex, each in increasing order of age.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthReference {
+    tables: HashMap<Sex, Vec<(f64, Lms)>>,
+}
+
+impl GrowthReference {
+    /// Read a table with a `sex,age_months,L,M,S` header and one row per
+    /// sex and age, sex being `M` or `F`. Blank lines and lines starting
+    /// with `#` are skipped.
+    pub fn load(path: &Path) -> Result<GrowthReference, ReferenceError> {
+        GrowthReference::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<GrowthReference, ReferenceError> {
+        let mut tables: HashMap<Sex, Vec<(f64, Lms)>> = HashMap::new();
+        let mut header_seen = false;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| ReferenceError::Parse {
+                line: index + 1,
+                message,
+            };
+            if !header_seen {
+                if line != "sex,age_months,L,M,S" {
+                    return Err(error(String::from(
+                        "expected a sex,age_months,L,M,S header",
+                    )));
+                }
+                header_seen = true;
+                continue;
+            }
+
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            let [sex, age, l, m, s] = fields[..] else {
+                return Err(error(format!("expected 5 fields, found {}", fields.len())));
+            };
+            let sex = match sex {
+                "M" => Sex::Male,
+                "F" => Sex::Female,
+                _ => return Err(error(format!("unknown sex {sex:?}"))),
+            };
+            let number = |name: &str, text: &str| {
+                text.parse::<f64>()
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .ok_or_else(|| error(format!("invalid {name} {text:?}")))
+            };
+            let age = number("age", age)?;
+            let lms = Lms {
+                l: number("L", l)?,
+                m: number("M", m)?,
+                s: number("S", s)?,
+            };
+            if age < 0.0 || lms.m <= 0.0 || lms.s <= 0.0 {
+                return Err(error(String::from(
+                    "age must not be negative, M and S must be positive",
+                )));
+            }
+            let table = tables.entry(sex).or_default();
+            if table.last().is_some_and(|&(last, _)| age <= last) {
+                return Err(error(String::from("ages must increase")));
+            }
+            table.push((age, lms));
+        }
+        if tables.is_empty() {
+            return Err(ReferenceError::Parse {
+                line: text.lines().count(),
+                message: String::from("no rows"),
+            });
+        }
+        Ok(GrowthReference { tables })
+    }
+
+    /// First and last tabulated ages for `sex`.
+    pub fn ages(&self, sex: Sex) -> Option<(f64, f64)> {
+        let table = self.tables.get(&sex)?;
+        Some((table.first()?.0, table.last()?.0))
+    }
+
+    /// Parameters at `age_months`, if it is within the table.
+    pub fn lms(&self, sex: Sex, age_months: f64) -> Option<Lms> {
+        let table = self.tables.get(&sex)?;
+        let after = table.partition_point(|&(age, _)| age < age_months);
+        let &(age1, upper) = table.get(after)?;
+        if age1 == age_months {
+            return Some(upper);
+        }
+        let &(age0, lower) = table.get(after.checked_sub(1)?)?;
+        let t = (age_months - age0) / (age1 - age0);
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Some(Lms {
+            l: lerp(lower.l, upper.l),
+            m: lerp(lower.m, upper.m),
+            s: lerp(lower.s, upper.s),
+        })
+    }
+
+    pub fn assess(&self, sex: Sex, age_months: f64, height: Length) -> Option<GrowthAssessment> {
+        let z_score = self
+            .lms(sex, age_months)?
+            .z_score(f64::from(height.as_cm()));
+        Some(GrowthAssessment {
+            z_score,
+            percentile: 100.0 * normal_cdf(z_score),
+        })
+    }
+
+    /// Where the user's current height stands. Only whole years of age are
+    /// known, so they are taken to be half way through their current year.
+    pub fn assess_user(&self, user: &User, sex: Sex) -> Option<GrowthAssessment> {
+        let age_months = f64::from(user.age()) * 12.0 + 6.0;
+        self.assess(sex, age_months, user.height())
+    }
+}
+
+/// The heights measured at each visit, at the age they were measured for
+/// someone born at `born`. Visits before the birth are left out.
+pub fn growth_points(history: &History, born: Timestamp) -> Vec<GrowthPoint> {
+    history
+        .visits()
+        .iter()
+        .filter(|visit| visit.at >= born)
+        .map(|visit| GrowthPoint {
+            age_months: visit.at.days_since(born) / DAYS_PER_MONTH,
+            height: visit.measurements.height,
+        })
+        .collect()
+}
+
+/// An ASCII growth chart of `points` against the 3rd, 15th, 50th, 85th
+/// and 97th percentile curves, `width` columns by `rows` rows of plot.
+/// The ages shown cover whole years around the points, within the table.
+/// `None` if there is no table for `sex` or no room to draw.
+pub fn render_chart(
+    reference: &GrowthReference,
+    sex: Sex,
+    points: &[GrowthPoint],
+    width: usize,
+    rows: usize,
+) -> Option<String> {
+    if width < 2 || rows < 2 {
+        return None;
+    }
+    let (first_age, last_age) = reference.ages(sex)?;
+    let (mut from, mut to) = (first_age, last_age);
+    if !points.is_empty() {
+        let ages = points.iter().map(|point| point.age_months);
+        from = ((ages.clone().fold(f64::INFINITY, f64::min) / 12.0).floor() * 12.0).max(first_age);
+        to = ((ages.fold(f64::NEG_INFINITY, f64::max) / 12.0).ceil() * 12.0).min(last_age);
+        if to - from < 12.0 {
+            to = (from + 12.0).min(last_age);
+            from = (to - 12.0).max(first_age);
+        }
+    }
+    let age_of = |column: usize| from + (to - from) * column as f64 / (width - 1) as f64;
+    let column_of = |age: f64| ((age - from) / (to - from) * (width - 1) as f64).round();
+
+    // Each column's height on each curve, then the vertical range to fit
+    // them and the points in.
+    let curves: Vec<Vec<Option<f64>>> = CHART_CURVES
+        .iter()
+        .map(|&(z, _)| {
+            (0..width)
+                .map(|column| Some(reference.lms(sex, age_of(column))?.value_at(z)))
+                .collect()
+        })
+        .collect();
+    let heights = curves
+        .iter()
+        .flatten()
+        .flatten()
+        .copied()
+        .chain(points.iter().map(|point| f64::from(point.height.as_cm())));
+    let (low, high) = heights.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), cm| {
+        (low.min(cm), high.max(cm))
+    });
+    let (low, high) = ((low - 2.0).floor(), (high + 2.0).ceil());
+    let row_of = |cm: f64| ((high - cm) / (high - low) * (rows - 1) as f64).round();
+
+    let mut grid = vec![vec![' '; width]; rows];
+    for (curve, &(_, mark)) in curves.iter().zip(&CHART_CURVES) {
+        for (column, cm) in curve.iter().enumerate() {
+            if let Some(cm) = cm {
+                grid[row_of(*cm) as usize][column] = mark;
+            }
+        }
+    }
+    for point in points {
+        let (row, column) = (
+            row_of(f64::from(point.height.as_cm())),
+            column_of(point.age_months),
+        );
+        if (0.0..rows as f64).contains(&row) && (0.0..width as f64).contains(&column) {
+            grid[row as usize][column as usize] = MEASURED_MARK;
+        }
+    }
+
+    let mut chart = String::from("height (cm)\n");
+    for (row, cells) in grid.iter().enumerate() {
+        let label = if row % 4 == 0 || row == rows - 1 {
+            let cm = high - (high - low) * row as f64 / (rows - 1) as f64;
+            format!("{cm:5.0}")
+        } else {
+            String::new()
+        };
+        let cells: String = cells.iter().collect();
+        chart += &format!("{label:>5} |{}\n", cells.trim_end());
+    }
+    chart += &format!("{:>5} +{}\n", "", "-".repeat(width));
+
+    // Whole years along the bottom, where they fit.
+    let mut labels = vec![' '; width + 8];
+    let mut free_from = 0;
+    for year in (from / 12.0).ceil() as u32..=(to / 12.0).floor() as u32 {
+        let text = year.to_string();
+        let at = 7 + column_of(f64::from(year) * 12.0) as usize;
+        if at >= free_from && at + text.len() <= labels.len() {
+            labels[at..at + text.len()].copy_from_slice(&text.chars().collect::<Vec<_>>());
+            free_from = at + text.len() + 1;
+        }
+    }
+    let labels: String = labels.iter().collect();
+    chart += &format!("{}  age (years)\n", labels.trim_end());
+    chart += &format!("{MEASURED_MARK} measured  = 50th  - 15th/85th  . 3rd/97th percentile\n");
+    Some(chart)
+}
+
+#[cfg(test)]
+fn assert_close(a: f64, b: f64, tolerance: f64) {
+    assert!((a - b).abs() < tolerance, "{a} != {b}");
+}
+
+#[test]
+fn test_lms() {
+    let normal = Lms {
+        l: 1.0,
+        m: 100.0,
+        s: 0.05,
+    };
+    assert_close(normal.z_score(105.0), 1.0, 1e-9);
+    assert_close(normal.value_at(-2.0), 90.0, 1e-9);
+
+    let log_normal = Lms { l: 0.0, ..normal };
+    assert_close(log_normal.z_score(100.0 * 0.05f64.exp()), 1.0, 1e-9);
+
+    let skewed = Lms { l: -1.5, ..normal };
+    for x in [80.0, 100.0, 123.4] {
+        assert_close(skewed.value_at(skewed.z_score(x)), x, 1e-9);
+    }
+}
+
+#[test]
+fn test_normal_cdf() {
+    assert_close(normal_cdf(0.0), 0.5, 1e-7);
+    assert_close(normal_cdf(1.959_964), 0.975, 1e-6);
+    assert_close(normal_cdf(-1.880_794), 0.03, 1e-6);
+    assert_close(normal_cdf(1.036_433) - normal_cdf(-1.036_433), 0.7, 1e-6);
+}
+
+#[cfg(test)]
+fn test_reference() -> GrowthReference {
+    GrowthReference::parse(TEST_REFERENCE).unwrap()
+}
+
+#[test]
+fn test_reference_lookup() {
+    let reference = test_reference();
+    assert_eq!(reference.ages(Sex::Female), Some((24.0, 228.0)));
+
+    let median = reference
+        .assess(Sex::Male, 120.0, Length::cm(137.8))
+        .unwrap();
+    assert_close(median.z_score, 0.0, 1e-6);
+    assert_close(median.percentile, 50.0, 1e-4);
+
+    // Half way between the tabulated ages.
+    let lms = reference.lms(Sex::Male, 126.0).unwrap();
+    assert_close(lms.m, (137.8 + 143.1) / 2.0, 1e-9);
+    let tall = reference
+        .assess(
+            Sex::Female,
+            120.0,
+            Length::cm((138.6 * (1.0 + 2.0 * 0.0451)) as f32),
+        )
+        .unwrap();
+    assert_close(tall.z_score, 2.0, 1e-4);
+    assert_close(tall.percentile, 97.72, 0.01);
+
+    assert_eq!(reference.lms(Sex::Male, 12.0), None);
+    assert_eq!(reference.lms(Sex::Male, 240.0), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    let parse = |rows: &str| GrowthReference::parse(&format!("sex,age_months,L,M,S\n{rows}"));
+    assert!(parse("M,24,1,87.1,0.04\nM,36,1,96.1,0.04").is_ok());
+    for (rows, line) in [
+        ("M,24,1,87.1", 2),
+        ("X,24,1,87.1,0.04", 2),
+        ("M,24,1,87.1,0.04\n\nM,24,1,88,0.04", 4),
+        ("M,24,1,-87.1,0.04", 2),
+        ("M,24,one,87.1,0.04", 2),
+    ] {
+        match parse(rows) {
+            Err(ReferenceError::Parse { line: found, .. }) => assert_eq!(found, line, "{rows}"),
+            other => panic!("{rows}: {other:?}"),
+        }
+    }
+    assert!(GrowthReference::parse("M,24,1,87.1,0.04").is_err());
+    assert!(GrowthReference::parse("# nothing\n").is_err());
+}
+
+#[test]
+fn test_load() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let path = dir.path().join("height-for-age.csv");
+    fs::write(&path, TEST_REFERENCE)?;
+    assert_eq!(GrowthReference::load(&path)?, test_reference());
+
+    fs::write(&path, "sex,age_months,L,M,S\nM,24,1,87.1\n")?;
+    assert!(matches!(
+        GrowthReference::load(&path),
+        Err(ReferenceError::Parse { line: 2, .. })
+    ));
+    assert!(matches!(
+        GrowthReference::load(&dir.path().join("missing.csv")),
+        Err(ReferenceError::Io(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_user_and_history() {
+    use super::units::Pressure;
+    use super::{BloodPressure, Measurements};
+
+    let reference = test_reference();
+    // Nine and a half, at the median for boys of that age in the table.
+    let mut child = User::new(String::from("Sam"), 9, Length::cm(135.2));
+    let assessment = reference.assess_user(&child, Sex::Male).unwrap();
+    assert_close(assessment.percentile, 50.0, 0.1);
+
+    let born = Timestamp(0);
+    let year = |years: f64| Timestamp((years * 365.25 * 86_400.0) as u64);
+    for (age, height) in [(6.0, 116.0), (7.5, 124.0), (9.0, 132.6)] {
+        let measurements = Measurements::new(
+            Length::cm(height),
+            BloodPressure::new(Pressure::mmhg(100), Pressure::mmhg(60)).unwrap(),
+        );
+        child.record_visit(year(age), measurements, "").unwrap();
+    }
+    let points = growth_points(child.history(), born);
+    assert_eq!(points.len(), 3);
+    assert_close(points[1].age_months, 90.0, 1e-6);
+    assert_eq!(points[2].height, Length::cm(132.6));
+    assert_eq!(growth_points(child.history(), year(7.0)).len(), 2);
+}
+
+#[test]
+fn test_render_chart() {
+    let reference = test_reference();
+    let points = [
+        GrowthPoint {
+            age_months: 72.0,
+            height: Length::cm(116.0),
+        },
+        GrowthPoint {
+            age_months: 108.0,
+            height: Length::cm(140.0),
+        },
+    ];
+    let chart = render_chart(&reference, Sex::Male, &points, 40, 16).unwrap();
+    let lines: Vec<_> = chart.lines().collect();
+    // Title, plot rows, axis, years and legend.
+    assert_eq!(lines.len(), 1 + 16 + 3);
+    assert_eq!(chart.matches(MEASURED_MARK).count(), 2 + 1);
+    assert!(lines[17].starts_with("      +----"));
+    assert!(lines[18].contains('6') && lines[18].contains('9'));
+    assert!(lines[18].ends_with("age (years)"));
+    assert!(lines[1..18]
+        .iter()
+        .all(|line| line.chars().count() <= 7 + 40));
+
+    // The first point is on the median, at the left edge of the plot.
+    let plot = &lines[1..17];
+    let median_row = plot
+        .iter()
+        .position(|line| line.chars().nth(7) == Some(MEASURED_MARK))
+        .unwrap();
+    assert!(plot[median_row].contains('='));
+    // The second, well above it, is above the 97th percentile.
+    let high_row = plot
+        .iter()
+        .position(|line| line.trim_end().ends_with(MEASURED_MARK))
+        .unwrap();
+    assert!(high_row < median_row);
+
+    let full = render_chart(&reference, Sex::Female, &[], 60, 20).unwrap();
+    assert!(full.contains(" 2 ") && full.contains("19"));
+    assert_eq!(render_chart(&reference, Sex::Male, &points, 1, 16), None);
+}
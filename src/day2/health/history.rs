@@ -0,0 +1,285 @@
+// Every visit a user has made, with queries over the measurements taken.
+
+use std::ops::{Range, RangeBounds};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::units::Length;
+use super::Measurements;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Timestamp(since_epoch.as_secs())
+    }
+
+    /// Days from `earlier` to `self`, negative if `earlier` is later.
+    pub fn days_since(self, earlier: Timestamp) -> f64 {
+        (self.0 as f64 - earlier.0 as f64) / SECONDS_PER_DAY
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Visit {
+    pub at: Timestamp,
+    pub measurements: Measurements,
+    pub notes: String,
+}
+
+/// A quantity measured at each visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Height,
+    Systolic,
+    Diastolic,
+}
+
+impl Metric {
+    fn of(self, measurements: &Measurements) -> f64 {
+        match self {
+            Metric::Height => f64::from(measurements.height.as_cm()),
+            Metric::Systolic => f64::from(measurements.blood_pressure.systolic().as_mmhg()),
+            Metric::Diastolic => f64::from(measurements.blood_pressure.diastolic().as_mmhg()),
+        }
+    }
+}
+
+/// Difference between two visits, later minus earlier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub days: f64,
+    pub height: Length,
+    pub blood_pressure: (i64, i64),
+}
+
+/// Visits in time order.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    visits: Vec<Visit>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Add a visit, which may be earlier than ones already recorded, and
+    /// return its position. Visits at the same time keep the order they
+    /// were recorded in.
+    pub fn record(&mut self, visit: Visit) -> usize {
+        let index = self.visits.partition_point(|v| v.at <= visit.at);
+        self.visits.insert(index, visit);
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.visits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visits.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Visit> {
+        self.visits.get(index)
+    }
+
+    pub fn latest(&self) -> Option<&Visit> {
+        self.visits.last()
+    }
+
+    pub fn visits(&self) -> &[Visit] {
+        &self.visits
+    }
+
+    pub fn between(&self, times: impl RangeBounds<Timestamp>) -> &[Visit] {
+        &self.visits[self.positions_between(times)]
+    }
+
+    /// Positions of the visits made at `times`.
+    pub fn positions_between(&self, times: impl RangeBounds<Timestamp>) -> Range<usize> {
+        let start = self.visits.partition_point(|v| before(&times, v.at));
+        let len = self.visits[start..].partition_point(|v| times.contains(&v.at));
+        start..start + len
+    }
+
+    pub fn series(&self, metric: Metric) -> impl Iterator<Item = (Timestamp, f64)> + '_ {
+        self.visits
+            .iter()
+            .map(move |v| (v.at, metric.of(&v.measurements)))
+    }
+
+    /// Lowest value of `metric` and when it was measured, the earliest if
+    /// it was measured more than once.
+    pub fn min(&self, metric: Metric) -> Option<(Timestamp, f64)> {
+        self.series(metric)
+            .reduce(|low, next| if next.1 < low.1 { next } else { low })
+    }
+
+    /// Highest value of `metric`, the earliest if measured more than once.
+    pub fn max(&self, metric: Metric) -> Option<(Timestamp, f64)> {
+        self.series(metric)
+            .reduce(|high, next| if next.1 > high.1 { next } else { high })
+    }
+
+    /// Mean of each `window` consecutive visits, dated at the last of them.
+    /// Empty if there are fewer visits than `window`.
+    pub fn moving_average(&self, metric: Metric, window: usize) -> Vec<(Timestamp, f64)> {
+        assert!(window > 0, "the window must hold at least one visit");
+        let values: Vec<_> = self.series(metric).collect();
+        values
+            .windows(window)
+            .map(|w| {
+                let sum: f64 = w.iter().map(|&(_, value)| value).sum();
+                (w[window - 1].0, sum / window as f64)
+            })
+            .collect()
+    }
+
+    /// Least-squares slope of `metric` per day, or `None` without two
+    /// visits at different times.
+    pub fn trend(&self, metric: Metric) -> Option<f64> {
+        let first = self.visits.first()?.at;
+        let points: Vec<_> = self
+            .series(metric)
+            .map(|(at, value)| (at.days_since(first), value))
+            .collect();
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|&(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|&(_, y)| y).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|&(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = points.iter().map(|&(x, _)| (x - mean_x).powi(2)).sum();
+        (variance > 0.0).then(|| covariance / variance)
+    }
+
+    /// Change from visit `from` to visit `to`, by position.
+    pub fn change(&self, from: usize, to: usize) -> Option<Change> {
+        let (from, to) = (self.visits.get(from)?, self.visits.get(to)?);
+        let (before, after) = (&from.measurements, &to.measurements);
+        Some(Change {
+            days: to.at.days_since(from.at),
+            height: after.height - before.height,
+            blood_pressure: (
+                i64::from(after.blood_pressure.systolic().as_mmhg())
+                    - i64::from(before.blood_pressure.systolic().as_mmhg()),
+                i64::from(after.blood_pressure.diastolic().as_mmhg())
+                    - i64::from(before.blood_pressure.diastolic().as_mmhg()),
+            ),
+        })
+    }
+}
+
+// Whether `at` comes before every time in `times`.
+fn before(times: &impl RangeBounds<Timestamp>, at: Timestamp) -> bool {
+    use std::ops::Bound;
+    match times.start_bound() {
+        Bound::Included(start) => at < *start,
+        Bound::Excluded(start) => at <= *start,
+        Bound::Unbounded => false,
+    }
+}
+
+#[cfg(test)]
+fn day(n: u64) -> Timestamp {
+    Timestamp(n * 86_400)
+}
+
+#[cfg(test)]
+fn visit(at: Timestamp, height: f32, (systolic, diastolic): (u32, u32)) -> Visit {
+    Visit {
+        at,
+        measurements: Measurements::new(
+            Length::cm(height),
+            super::BloodPressure::mmhg(systolic, diastolic).unwrap(),
+        ),
+        notes: String::new(),
+    }
+}
+
+// Four weekly visits with falling blood pressure.
+#[cfg(test)]
+fn sample_history() -> History {
+    let mut history = History::new();
+    for (week, systolic, diastolic) in [(0, 140, 90), (1, 136, 88), (2, 130, 85), (3, 130, 82)] {
+        history.record(visit(day(week * 7), 170.0, (systolic, diastolic)));
+    }
+    history
+}
+
+#[test]
+fn test_record_keeps_time_order() {
+    let mut history = sample_history();
+    // A visit entered late goes where it belongs.
+    assert_eq!(history.record(visit(day(10), 170.0, (133, 86))), 2);
+    let days: Vec<_> = history.visits().iter().map(|v| v.at).collect();
+    assert_eq!(days, [day(0), day(7), day(10), day(14), day(21)]);
+    assert_eq!(history.latest().map(|v| v.at), Some(day(21)));
+    // Same time: after the visits already there.
+    assert_eq!(history.record(visit(day(10), 170.0, (120, 80))), 3);
+}
+
+#[test]
+fn test_between() {
+    let history = sample_history();
+    assert_eq!(history.between(day(7)..day(21)).len(), 2);
+    assert_eq!(history.between(day(7)..=day(21)).len(), 3);
+    assert_eq!(history.between(..day(1)).len(), 1);
+    assert_eq!(history.between(day(22)..).len(), 0);
+    assert_eq!(history.between(..).len(), 4);
+}
+
+#[test]
+fn test_min_max() {
+    let history = sample_history();
+    assert_eq!(history.min(Metric::Systolic), Some((day(14), 130.0)));
+    assert_eq!(history.max(Metric::Diastolic), Some((day(0), 90.0)));
+    assert_eq!(History::new().min(Metric::Height), None);
+}
+
+#[test]
+fn test_moving_average() {
+    let history = sample_history();
+    assert_eq!(
+        history.moving_average(Metric::Systolic, 2),
+        [(day(7), 138.0), (day(14), 133.0), (day(21), 130.0)]
+    );
+    assert_eq!(history.moving_average(Metric::Systolic, 5), []);
+}
+
+#[test]
+fn test_trend() {
+    let history = sample_history();
+    let slope = history.trend(Metric::Diastolic).unwrap();
+    // About 8 mmHg in three weeks.
+    assert!((slope - -0.38).abs() < 0.01, "{slope}");
+    assert_eq!(history.trend(Metric::Height), Some(0.0));
+
+    let mut single = History::new();
+    single.record(visit(day(0), 170.0, (120, 80)));
+    assert_eq!(single.trend(Metric::Systolic), None);
+}
+
+#[test]
+fn test_change() {
+    let history = sample_history();
+    assert_eq!(
+        history.change(0, 3),
+        Some(Change {
+            days: 21.0,
+            height: Length::cm(0.0),
+            blood_pressure: (-10, -8),
+        })
+    );
+    assert_eq!(history.change(3, 1).map(|c| c.blood_pressure), Some((6, 6)));
+    assert_eq!(history.change(0, 4), None);
+}
@@ -0,0 +1,466 @@
+// Every patient of a clinic, under IDs that stay valid for good: deleted
+// patients are only marked as such, and a duplicate record merged into
+// another keeps resolving to the record it was merged into.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeBounds;
+
+use super::audit::{self, Field};
+use super::history::Timestamp;
+use super::{HealthReport, User};
+
+/// The actor named in the audit logs of merged patients.
+const REGISTRY_ACTOR: &str = "registry";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PatientId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    UnknownPatient(PatientId),
+    Deleted(PatientId),
+    MergeWithSelf(PatientId),
+    Merged { patient: PatientId, into: PatientId },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::UnknownPatient(PatientId(id)) => write!(f, "no patient with id {id}"),
+            RegistryError::Deleted(PatientId(id)) => write!(f, "patient {id} was deleted"),
+            RegistryError::MergeWithSelf(PatientId(id)) => {
+                write!(f, "patient {id} can't be merged into itself")
+            }
+            RegistryError::Merged {
+                patient: PatientId(patient),
+                into: PatientId(into),
+            } => write!(f, "patient {patient} was merged into patient {into}"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Ages `from` up to `from + width`, exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AgeBracket {
+    pub from: u32,
+    pub width: u32,
+}
+
+impl AgeBracket {
+    /// The bracket of `width` years containing `age`, starting at a multiple
+    /// of `width`.
+    pub fn of(age: u32, width: u32) -> AgeBracket {
+        assert!(width > 0, "age brackets must span at least a year");
+        AgeBracket {
+            from: age / width * width,
+            width,
+        }
+    }
+
+    pub fn contains(&self, age: u32) -> bool {
+        (self.from..self.from + self.width).contains(&age)
+    }
+}
+
+impl fmt::Display for AgeBracket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.from, self.from + self.width - 1)
+    }
+}
+
+/// Mean blood pressure change, from first to latest visit, of the patients
+/// in an age bracket with at least two visits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cohort {
+    pub ages: AgeBracket,
+    pub patients: usize,
+    pub systolic_change: f64,
+    pub diastolic_change: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Active,
+    Deleted,
+    MergedInto(PatientId),
+}
+
+struct Record {
+    user: User,
+    status: Status,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    records: BTreeMap<PatientId, Record>,
+    next_id: u32,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    pub fn register(&mut self, user: User) -> PatientId {
+        let id = PatientId(self.next_id);
+        self.next_id += 1;
+        self.records.insert(
+            id,
+            Record {
+                user,
+                status: Status::Active,
+            },
+        );
+        id
+    }
+
+    /// The active record `id` refers to, following merges.
+    pub fn resolve(&self, mut id: PatientId) -> Result<PatientId, RegistryError> {
+        loop {
+            let record = self
+                .records
+                .get(&id)
+                .ok_or(RegistryError::UnknownPatient(id))?;
+            match record.status {
+                Status::Active => return Ok(id),
+                Status::Deleted => return Err(RegistryError::Deleted(id)),
+                Status::MergedInto(into) => id = into,
+            }
+        }
+    }
+
+    pub fn get(&self, id: PatientId) -> Result<&User, RegistryError> {
+        let id = self.resolve(id)?;
+        Ok(&self.records[&id].user)
+    }
+
+    pub fn get_mut(&mut self, id: PatientId) -> Result<&mut User, RegistryError> {
+        let id = self.resolve(id)?;
+        Ok(&mut self.records.get_mut(&id).expect("resolved").user)
+    }
+
+    /// Active patients, in the order they were registered.
+    pub fn patients(&self) -> impl Iterator<Item = (PatientId, &User)> {
+        self.records
+            .iter()
+            .filter(|(_, record)| record.status == Status::Active)
+            .map(|(&id, record)| (id, &record.user))
+    }
+
+    /// Active patients named `name`, ignoring case and surrounding spaces.
+    pub fn find_by_name(&self, name: &str) -> Vec<PatientId> {
+        let name = name.trim().to_lowercase();
+        self.patients()
+            .filter(|(_, user)| user.name.trim().to_lowercase() == name)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Mark a patient deleted. Their record is kept and can be restored.
+    /// Unlike most methods this does not follow merges: deleting a record
+    /// merged into another would otherwise delete the patient it became.
+    pub fn delete(&mut self, id: PatientId) -> Result<(), RegistryError> {
+        let record = self
+            .records
+            .get_mut(&id)
+            .ok_or(RegistryError::UnknownPatient(id))?;
+        match record.status {
+            Status::Active => {
+                record.status = Status::Deleted;
+                Ok(())
+            }
+            Status::Deleted => Err(RegistryError::Deleted(id)),
+            Status::MergedInto(into) => Err(RegistryError::Merged { patient: id, into }),
+        }
+    }
+
+    pub fn restore(&mut self, id: PatientId) -> Result<(), RegistryError> {
+        let record = self
+            .records
+            .get_mut(&id)
+            .ok_or(RegistryError::UnknownPatient(id))?;
+        if record.status == Status::Deleted {
+            record.status = Status::Active;
+        }
+        Ok(())
+    }
+
+    /// Move the visits of `duplicate` into `keep`, after which `duplicate`
+    /// resolves to `keep`.
+    pub fn merge(&mut self, keep: PatientId, duplicate: PatientId) -> Result<(), RegistryError> {
+        let keep = self.resolve(keep)?;
+        let duplicate = self.resolve(duplicate)?;
+        if keep == duplicate {
+            return Err(RegistryError::MergeWithSelf(keep));
+        }
+
+        let record = self.records.get_mut(&duplicate).expect("resolved");
+        record.status = Status::MergedInto(keep);
+        let visits = std::mem::take(&mut record.user.history);
+        record.user.audit.record(
+            REGISTRY_ACTOR,
+            Field::Visits,
+            audit::visits_text(visits.len()),
+            format!("{}; merged into patient {}", audit::visits_text(0), keep.0),
+        );
+
+        let user = &mut self.records.get_mut(&keep).expect("resolved").user;
+        let old = audit::visits_text(user.history.len());
+        let latest = user.history.latest().map(|visit| visit.at);
+        for visit in visits.visits() {
+            user.history.record(visit.clone());
+        }
+        let new = format!(
+            "{}; merged from patient {}",
+            audit::visits_text(user.history.len()),
+            duplicate.0
+        );
+        user.audit.record(REGISTRY_ACTOR, Field::Visits, old, new);
+        // The height on record comes from the latest visit, which may now be
+        // one of the duplicate's.
+        if let Some(visit) = user.history.latest() {
+            let height = visit.measurements.height;
+            if latest.is_none_or(|at| visit.at > at) && height != user.height {
+                let old = user.height.to_string();
+                user.height = height;
+                user.audit
+                    .record(REGISTRY_ACTOR, Field::Height, old, height.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Blood pressure change by age bracket, youngest first. Brackets
+    /// without patients with two or more visits are left out.
+    pub fn bp_change_by_age(&self, bracket_width: u32) -> Vec<Cohort> {
+        let mut totals: BTreeMap<AgeBracket, (usize, i64, i64)> = BTreeMap::new();
+        for (_, user) in self.patients() {
+            let history = &user.history;
+            if history.len() < 2 {
+                continue;
+            }
+            let change = history.change(0, history.len() - 1).expect("two visits");
+            let total = totals
+                .entry(AgeBracket::of(user.age, bracket_width))
+                .or_default();
+            total.0 += 1;
+            total.1 += change.blood_pressure.0;
+            total.2 += change.blood_pressure.1;
+        }
+        totals
+            .into_iter()
+            .map(|(ages, (patients, systolic, diastolic))| Cohort {
+                ages,
+                patients,
+                systolic_change: systolic as f64 / patients as f64,
+                diastolic_change: diastolic as f64 / patients as f64,
+            })
+            .collect()
+    }
+
+    /// A report on the latest visit at `times` of every active patient who
+    /// visited then.
+    pub fn reports_between(
+        &self,
+        times: impl RangeBounds<Timestamp>,
+    ) -> Vec<(PatientId, HealthReport<'_>)> {
+        // As a pair of bounds, which can be copied for each patient.
+        let times = (times.start_bound().cloned(), times.end_bound().cloned());
+        self.patients()
+            .filter_map(|(id, user)| {
+                let latest = user.history.positions_between(times).last()?;
+                Some((id, user.report(latest)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn visit(user: &mut User, day: u64, (systolic, diastolic): (u32, u32)) {
+    let measurements = super::Measurements::new(
+        super::units::Length::cm(170.0),
+        super::BloodPressure::mmhg(systolic, diastolic).unwrap(),
+    );
+    user.record_visit(Timestamp(day * 86_400), measurements, "")
+        .unwrap();
+}
+
+#[cfg(test)]
+fn user(name: &str, age: u32) -> User {
+    User::new(String::from(name), age, super::units::Length::cm(170.0))
+}
+
+#[test]
+fn test_register_and_find() {
+    let mut registry = Registry::new();
+    let alice = registry.register(user("Alice Smith", 34));
+    let bob = registry.register(user("Bob", 61));
+    let other_alice = registry.register(user("alice smith ", 52));
+
+    assert_eq!(registry.get(bob).unwrap().name(), "Bob");
+    assert_eq!(registry.find_by_name("Alice Smith"), [alice, other_alice]);
+    assert_eq!(registry.find_by_name("Carol"), []);
+    assert!(matches!(
+        registry.get(PatientId(9)),
+        Err(RegistryError::UnknownPatient(PatientId(9)))
+    ));
+}
+
+#[test]
+fn test_soft_delete() {
+    let mut registry = Registry::new();
+    let alice = registry.register(user("Alice", 34));
+    let bob = registry.register(user("Bob", 61));
+    registry.delete(alice).unwrap();
+
+    assert!(matches!(
+        registry.get(alice),
+        Err(RegistryError::Deleted(_))
+    ));
+    assert_eq!(registry.find_by_name("Alice"), []);
+    assert_eq!(
+        registry.patients().map(|(id, _)| id).collect::<Vec<_>>(),
+        [bob]
+    );
+
+    registry.restore(alice).unwrap();
+    assert_eq!(registry.get(alice).unwrap().age(), 34);
+    // Ids are never reused.
+    assert_eq!(registry.register(user("Carol", 20)), PatientId(2));
+}
+
+#[test]
+fn test_merge() {
+    let mut registry = Registry::new();
+    let alice = registry.register(user("Alice", 34));
+    let duplicate = registry.register(user("Alice", 34));
+    visit(registry.get_mut(alice).unwrap(), 10, (130, 85));
+    visit(registry.get_mut(duplicate).unwrap(), 5, (140, 90));
+    visit(registry.get_mut(duplicate).unwrap(), 20, (120, 80));
+
+    registry.merge(alice, duplicate).unwrap();
+    let merged = registry.get(duplicate).unwrap();
+    assert_eq!(registry.resolve(duplicate), Ok(alice));
+    assert_eq!(merged.doctor_visits(), 3);
+    let days: Vec<_> = merged
+        .history()
+        .visits()
+        .iter()
+        .map(|v| v.at.0 / 86_400)
+        .collect();
+    assert_eq!(days, [5, 10, 20]);
+    assert_eq!(registry.find_by_name("Alice"), [alice]);
+    let log = merged.audit_log();
+    assert_eq!(
+        log.last_change(Field::Visits).map(|(actor, _)| actor),
+        Some(REGISTRY_ACTOR)
+    );
+    assert_eq!(
+        log.entries().last().unwrap().new,
+        "3 visits; merged from patient 1"
+    );
+
+    assert_eq!(
+        registry.merge(duplicate, alice),
+        Err(RegistryError::MergeWithSelf(alice))
+    );
+    // Merges chain: a record merged into a merged record follows both.
+    let third = registry.register(user("Alice", 34));
+    let fourth = registry.register(user("Alice", 34));
+    registry.merge(third, fourth).unwrap();
+    registry.merge(alice, third).unwrap();
+    assert_eq!(registry.resolve(fourth), Ok(alice));
+}
+
+#[test]
+fn test_delete_merged_record() {
+    let mut registry = Registry::new();
+    let alice = registry.register(user("Alice", 34));
+    let duplicate = registry.register(user("Alice", 34));
+    registry.merge(alice, duplicate).unwrap();
+
+    assert_eq!(
+        registry.delete(duplicate),
+        Err(RegistryError::Merged {
+            patient: duplicate,
+            into: alice
+        })
+    );
+    // The surviving patient is untouched.
+    assert_eq!(registry.find_by_name("Alice"), [alice]);
+    assert_eq!(registry.resolve(duplicate), Ok(alice));
+
+    registry.delete(alice).unwrap();
+    assert_eq!(registry.delete(alice), Err(RegistryError::Deleted(alice)));
+    assert_eq!(
+        registry.delete(PatientId(9)),
+        Err(RegistryError::UnknownPatient(PatientId(9)))
+    );
+}
+
+#[test]
+fn test_cohorts() {
+    let mut registry = Registry::new();
+    for (name, age, first, last) in [
+        ("A", 31, (140, 90), (130, 86)),
+        ("B", 38, (150, 95), (130, 85)),
+        ("C", 64, (120, 80), (135, 88)),
+    ] {
+        let id = registry.register(user(name, age));
+        visit(registry.get_mut(id).unwrap(), 0, first);
+        visit(registry.get_mut(id).unwrap(), 90, last);
+    }
+    // One visit isn't enough to measure a change.
+    let single = registry.register(user("D", 45));
+    visit(registry.get_mut(single).unwrap(), 0, (120, 80));
+
+    let cohorts = registry.bp_change_by_age(10);
+    assert_eq!(
+        cohorts,
+        [
+            Cohort {
+                ages: AgeBracket {
+                    from: 30,
+                    width: 10
+                },
+                patients: 2,
+                systolic_change: -15.0,
+                diastolic_change: -7.0,
+            },
+            Cohort {
+                ages: AgeBracket {
+                    from: 60,
+                    width: 10
+                },
+                patients: 1,
+                systolic_change: 15.0,
+                diastolic_change: 8.0,
+            },
+        ]
+    );
+    assert_eq!(cohorts[0].ages.to_string(), "30-39");
+}
+
+#[test]
+fn test_reports_between() {
+    let mut registry = Registry::new();
+    let alice = registry.register(user("Alice", 34));
+    let bob = registry.register(user("Bob", 61));
+    let carol = registry.register(user("Carol", 45));
+    for day in [1, 8, 15] {
+        visit(registry.get_mut(alice).unwrap(), day, (120, 80));
+    }
+    visit(registry.get_mut(bob).unwrap(), 30, (130, 85));
+    visit(registry.get_mut(carol).unwrap(), 9, (125, 82));
+    registry.delete(carol).unwrap();
+
+    let reports = registry.reports_between(Timestamp(0)..Timestamp(10 * 86_400));
+    assert_eq!(reports.len(), 1);
+    let (id, report) = &reports[0];
+    assert_eq!(*id, alice);
+    // The latest visit in the range, Alice's second.
+    assert_eq!(report.visit_count, 2);
+}
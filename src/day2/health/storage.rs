@@ -0,0 +1,613 @@
+// Encrypted on-disk storage for users and their visit histories, one file
+// per patient.
+//
+// Records are encrypted with ChaCha20 and authenticated with HMAC-SHA-256
+// over the ciphertext (encrypt-then-MAC), using two keys derived from one
+// master key kept in a keyfile. The MAC also covers the record header and
+// the patient id, so a record altered in any way, or moved to another
+// patient's file, is rejected. A record file is
+//
+//   magic (4) | version (1) | key id (8) | nonce (12) | ciphertext | tag (32)
+//
+// where the key id tells records under an old key apart from tampered ones.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::audit::{AuditEntry, AuditLog, Field};
+use super::chacha20::{self, KEY_LEN, NONCE_LEN};
+use super::clinical::AlertThresholds;
+use super::history::{History, Timestamp, Visit};
+use super::registry::PatientId;
+use super::sha256::{hex, hmac_sha256, unhex};
+use super::units::{Length, Mass};
+use super::{BloodPressure, Measurements, User};
+use crate::json::{self, Value};
+
+const MAGIC: &[u8; 4] = b"FBHR";
+const FORMAT_VERSION: u8 = 1;
+const KEY_ID_LEN: usize = 8;
+const HEADER_LEN: usize = MAGIC.len() + 1 + KEY_ID_LEN + NONCE_LEN;
+const TAG_LEN: usize = 32;
+const EXTENSION: &str = "record";
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    InvalidKeyFile(PathBuf),
+    /// The record was encrypted under a different key.
+    WrongKey(PatientId),
+    /// The record was altered after it was written.
+    Tampered(PatientId),
+    /// Not a record this version can read.
+    Malformed {
+        patient: PatientId,
+        message: String,
+    },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "i/o error: {err}"),
+            StoreError::InvalidKeyFile(path) => {
+                write!(f, "{} does not hold a 256-bit hex key", path.display())
+            }
+            StoreError::WrongKey(PatientId(id)) => {
+                write!(f, "record of patient {id} is encrypted under another key")
+            }
+            StoreError::Tampered(PatientId(id)) => {
+                write!(f, "record of patient {id} has been tampered with")
+            }
+            StoreError::Malformed {
+                patient: PatientId(id),
+                message,
+            } => write!(f, "record of patient {id} is malformed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        StoreError::Io(err)
+    }
+}
+
+/// A 256-bit master key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LEN]);
+
+impl fmt::Debug for Key {
+    // Keys stay out of logs; the id is enough to tell them apart.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key({})", hex(&self.id()))
+    }
+}
+
+impl Key {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Key {
+        Key(bytes)
+    }
+
+    /// A new key from the operating system's random number generator.
+    pub fn generate() -> io::Result<Key> {
+        random_bytes().map(Key)
+    }
+
+    /// Read a keyfile written by [`Key::save`]: the key as 64 hex digits.
+    pub fn load(path: &Path) -> Result<Key, StoreError> {
+        let text = fs::read_to_string(path)?;
+        unhex(text.trim())
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Key)
+            .ok_or_else(|| StoreError::InvalidKeyFile(path.to_owned()))
+    }
+
+    /// Write the key to a new file, readable only by its owner. An
+    /// existing file is never overwritten, so a key can't be lost that way.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        writeln!(file, "{}", hex(&self.0))?;
+        file.sync_all()
+    }
+
+    // Independent keys for each use of the master key.
+    fn derive(&self, purpose: &str) -> [u8; KEY_LEN] {
+        hmac_sha256(&self.0, purpose.as_bytes())
+    }
+
+    fn id(&self) -> [u8; KEY_ID_LEN] {
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&self.derive("key id")[..KEY_ID_LEN]);
+        id
+    }
+
+    fn encrypt(&self, patient: PatientId, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = random_bytes()?;
+        let mut record = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+        record.extend_from_slice(MAGIC);
+        record.push(FORMAT_VERSION);
+        record.extend_from_slice(&self.id());
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(plaintext);
+        chacha20::apply_keystream(
+            &self.derive("encryption"),
+            &nonce,
+            1,
+            &mut record[HEADER_LEN..],
+        );
+        let tag = self.tag(patient, &record);
+        record.extend_from_slice(&tag);
+        Ok(record)
+    }
+
+    fn decrypt(&self, patient: PatientId, record: &[u8]) -> Result<Vec<u8>, StoreError> {
+        let malformed = |message: &str| StoreError::Malformed {
+            patient,
+            message: String::from(message),
+        };
+        if record.len() < HEADER_LEN + TAG_LEN || !record.starts_with(MAGIC) {
+            return Err(malformed("not a patient record"));
+        }
+        if record[MAGIC.len()] != FORMAT_VERSION {
+            return Err(malformed("unsupported format version"));
+        }
+        let (authenticated, tag) = record.split_at(record.len() - TAG_LEN);
+        if !constant_time_eq(&self.tag(patient, authenticated), tag) {
+            let key_id = &record[MAGIC.len() + 1..][..KEY_ID_LEN];
+            return Err(if key_id == self.id() {
+                StoreError::Tampered(patient)
+            } else {
+                StoreError::WrongKey(patient)
+            });
+        }
+
+        let nonce = record[HEADER_LEN - NONCE_LEN..HEADER_LEN]
+            .try_into()
+            .expect("nonce length");
+        let mut plaintext = authenticated[HEADER_LEN..].to_vec();
+        chacha20::apply_keystream(&self.derive("encryption"), &nonce, 1, &mut plaintext);
+        Ok(plaintext)
+    }
+
+    // MAC of everything before the tag, bound to the patient it is for.
+    fn tag(&self, PatientId(id): PatientId, authenticated: &[u8]) -> [u8; TAG_LEN] {
+        let mut message = id.to_be_bytes().to_vec();
+        message.extend_from_slice(authenticated);
+        hmac_sha256(&self.derive("authentication"), &message)
+    }
+}
+
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Compares every byte, so the time taken doesn't reveal how much of a
+// forged tag was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn user_to_json(user: &User) -> Value {
+    let thresholds = &user.alert_thresholds;
+    let visits = user.history.visits().iter().map(|visit| {
+        let measurements = &visit.measurements;
+        let bp = measurements.blood_pressure;
+        Value::object([
+            ("at", visit.at.0.into()),
+            ("height_cm", f64::from(measurements.height.as_cm()).into()),
+            ("systolic", bp.systolic().as_mmhg().into()),
+            ("diastolic", bp.diastolic().as_mmhg().into()),
+            (
+                "weight_kg",
+                measurements.weight.map(|w| f64::from(w.as_kg())).into(),
+            ),
+            ("notes", visit.notes.as_str().into()),
+        ])
+    });
+    Value::object([
+        ("name", user.name.as_str().into()),
+        ("age", user.age.into()),
+        ("height_cm", f64::from(user.height.as_cm()).into()),
+        (
+            "initial_height_cm",
+            f64::from(user.initial_height.as_cm()).into(),
+        ),
+        (
+            "alert_thresholds",
+            Value::object([
+                ("systolic_change", thresholds.systolic_change.into()),
+                ("diastolic_change", thresholds.diastolic_change.into()),
+                (
+                    "weight_change_percent",
+                    f64::from(thresholds.weight_change_percent).into(),
+                ),
+                (
+                    "height_loss_cm",
+                    f64::from(thresholds.height_loss.as_cm()).into(),
+                ),
+            ]),
+        ),
+        ("visits", Value::Array(visits.collect())),
+        (
+            "audit",
+            Value::Array(
+                user.audit
+                    .entries()
+                    .iter()
+                    .map(audit_entry_to_json)
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+fn audit_entry_to_json(entry: &AuditEntry) -> Value {
+    Value::object([
+        ("seq", entry.seq.into()),
+        ("at", entry.at.0.into()),
+        ("actor", entry.actor.as_str().into()),
+        ("field", entry.field.name().into()),
+        ("old", entry.old.as_str().into()),
+        ("new", entry.new.as_str().into()),
+        ("prev_hash", hex(&entry.prev_hash).into()),
+        ("hash", hex(&entry.hash).into()),
+    ])
+}
+
+fn audit_entry_from_json(value: &Value) -> Result<AuditEntry, String> {
+    let text = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("invalid audit {key}"))
+    };
+    let whole = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("invalid audit {key}"))
+    };
+    let digest = |key: &str| {
+        unhex(text(key)?)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("invalid audit {key}"))
+    };
+    let field = text("field")?;
+    Ok(AuditEntry {
+        seq: whole("seq")?,
+        at: Timestamp(whole("at")?),
+        actor: String::from(text("actor")?),
+        field: Field::ALL
+            .into_iter()
+            .find(|f| f.name() == field)
+            .ok_or_else(|| format!("unknown audit field {field:?}"))?,
+        old: String::from(text("old")?),
+        new: String::from(text("new")?),
+        prev_hash: digest("prev_hash")?,
+        hash: digest("hash")?,
+    })
+}
+
+fn user_from_json(value: &Value) -> Result<User, String> {
+    let field = |value: &'_ Value, key: &str| {
+        value
+            .get(key)
+            .cloned()
+            .ok_or_else(|| format!("missing {key}"))
+    };
+    let number = |value: &Value, key: &str| {
+        field(value, key)?
+            .as_f64()
+            .map(|n| n as f32)
+            .ok_or_else(|| format!("invalid {key}"))
+    };
+    let whole = |value: &Value, key: &str| {
+        field(value, key)?
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| format!("invalid {key}"))
+    };
+
+    let mut history = History::new();
+    for visit in field(value, "visits")?.as_array().ok_or("invalid visits")? {
+        let blood_pressure =
+            BloodPressure::mmhg(whole(visit, "systolic")?, whole(visit, "diastolic")?)
+                .map_err(|err| err.to_string())?;
+        let weight = match visit.get("weight_kg") {
+            None | Some(Value::Null) => None,
+            Some(_) => Some(Mass::kg(number(visit, "weight_kg")?)),
+        };
+        history.record(Visit {
+            at: Timestamp(field(visit, "at")?.as_u64().ok_or("invalid at")?),
+            measurements: Measurements {
+                height: Length::cm(number(visit, "height_cm")?),
+                blood_pressure,
+                weight,
+            },
+            notes: String::from(field(visit, "notes")?.as_str().ok_or("invalid notes")?),
+        });
+    }
+
+    let audit = AuditLog::from_entries(
+        field(value, "audit")?
+            .as_array()
+            .ok_or("invalid audit")?
+            .iter()
+            .map(audit_entry_from_json)
+            .collect::<Result<_, _>>()?,
+    );
+    // Records are authenticated, so this only fails for a log that was
+    // already broken when it was saved.
+    audit.verify().map_err(|err| err.to_string())?;
+
+    let thresholds = field(value, "alert_thresholds")?;
+    Ok(User {
+        name: String::from(field(value, "name")?.as_str().ok_or("invalid name")?),
+        age: whole(value, "age")?,
+        height: Length::cm(number(value, "height_cm")?),
+        initial_height: Length::cm(number(value, "initial_height_cm")?),
+        history,
+        alert_thresholds: AlertThresholds {
+            systolic_change: whole(&thresholds, "systolic_change")?,
+            diastolic_change: whole(&thresholds, "diastolic_change")?,
+            weight_change_percent: number(&thresholds, "weight_change_percent")?,
+            height_loss: Length::cm(number(&thresholds, "height_loss_cm")?),
+        },
+        audit,
+    })
+}
+
+/// A directory of encrypted patient records.
+pub struct EncryptedStore {
+    dir: PathBuf,
+    key: Key,
+}
+
+impl EncryptedStore {
+    /// Open the store in `dir`, creating the directory if needed.
+    pub fn open(dir: &Path, key: Key) -> io::Result<EncryptedStore> {
+        fs::create_dir_all(dir)?;
+        Ok(EncryptedStore {
+            dir: dir.to_owned(),
+            key,
+        })
+    }
+
+    fn path(&self, PatientId(id): PatientId) -> PathBuf {
+        self.dir.join(format!("patient-{id}.{EXTENSION}"))
+    }
+
+    /// Write `user` as patient `id`, replacing any earlier record
+    /// atomically.
+    pub fn save(&self, id: PatientId, user: &User) -> Result<(), StoreError> {
+        let plaintext = user_to_json(user).to_string();
+        self.write(id, &self.key.encrypt(id, plaintext.as_bytes())?)
+    }
+
+    fn write(&self, id: PatientId, record: &[u8]) -> Result<(), StoreError> {
+        let path = self.path(id);
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(record)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn load(&self, id: PatientId) -> Result<User, StoreError> {
+        let record = fs::read(self.path(id))?;
+        let plaintext = self.key.decrypt(id, &record)?;
+        let malformed = |message: String| StoreError::Malformed {
+            patient: id,
+            message,
+        };
+        let text = String::from_utf8(plaintext).map_err(|err| malformed(err.to_string()))?;
+        let value = json::parse(&text).map_err(|err| malformed(err.to_string()))?;
+        user_from_json(&value).map_err(malformed)
+    }
+
+    pub fn remove(&self, id: PatientId) -> Result<(), StoreError> {
+        fs::remove_file(self.path(id))?;
+        Ok(())
+    }
+
+    /// Ids of the stored patients, in ascending order.
+    pub fn ids(&self) -> Result<Vec<PatientId>, StoreError> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str()?.strip_prefix("patient-")?.parse().ok());
+            ids.extend(id.map(PatientId));
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Re-encrypt every record under `new_key` and use it from now on,
+    /// returning how many records were re-encrypted. Save the new key
+    /// before rotating and keep the old one until this succeeds: should it
+    /// fail part way, running it again with the same keys finishes the
+    /// job, skipping the records already under the new key.
+    pub fn rotate_key(&mut self, new_key: Key) -> Result<usize, StoreError> {
+        let mut rotated = 0;
+        for id in self.ids()? {
+            let record = fs::read(self.path(id))?;
+            let plaintext = match new_key.decrypt(id, &record) {
+                Ok(_) => continue,
+                Err(StoreError::WrongKey(_)) => self.key.decrypt(id, &record)?,
+                Err(err) => return Err(err),
+            };
+            self.write(id, &new_key.encrypt(id, &plaintext)?)?;
+            rotated += 1;
+        }
+        self.key = new_key;
+        Ok(rotated)
+    }
+}
+
+#[cfg(test)]
+fn sample_user() -> User {
+    let mut user = User::new(String::from("Alice Smith"), 47, Length::cm(165.0));
+    user.set_alert_thresholds(AlertThresholds {
+        systolic_change: 15,
+        ..AlertThresholds::default()
+    });
+    for (day, systolic, weight) in [(3, 142, Some(70.5)), (40, 131, None)] {
+        let mut measurements = Measurements::new(
+            Length::cm(165.5),
+            BloodPressure::mmhg(systolic, 88).unwrap(),
+        );
+        if let Some(weight) = weight {
+            measurements = measurements.with_weight(Mass::kg(weight));
+        }
+        user.record_visit(Timestamp(day * 86_400), measurements, "Diabetic, type 2")
+            .unwrap();
+    }
+    user
+}
+
+#[cfg(test)]
+fn assert_same_user(a: &User, b: &User) {
+    assert_eq!(user_to_json(a), user_to_json(b));
+}
+
+#[test]
+fn test_round_trip_without_plaintext_on_disk() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let store = EncryptedStore::open(dir.path(), Key::generate()?)?;
+    let user = sample_user();
+    store.save(PatientId(7), &user)?;
+
+    assert_eq!(store.ids()?, [PatientId(7)]);
+    assert_same_user(&store.load(PatientId(7))?, &user);
+    let loaded = store.load(PatientId(7))?;
+    assert_eq!(loaded.report(1).unwrap(), user.report(1).unwrap());
+    assert_eq!(loaded.audit_log(), user.audit_log());
+    assert_eq!(loaded.audit_log().entries().len(), 4);
+
+    let record = fs::read(store.path(PatientId(7)))?;
+    for secret in ["Alice", "Diabetic", "142"] {
+        assert!(
+            !record.windows(secret.len()).any(|w| w == secret.as_bytes()),
+            "{secret} in plaintext"
+        );
+    }
+    // A fresh nonce each time, so saving the same user twice differs.
+    store.save(PatientId(7), &user)?;
+    assert_ne!(fs::read(store.path(PatientId(7)))?, record);
+    Ok(())
+}
+
+#[test]
+fn test_altered_bytes_are_rejected() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let store = EncryptedStore::open(dir.path(), Key::from_bytes([7; KEY_LEN]))?;
+    store.save(PatientId(1), &sample_user())?;
+    let path = store.path(PatientId(1));
+    let record = fs::read(&path)?;
+
+    for i in 0..record.len() {
+        let mut altered = record.clone();
+        altered[i] ^= 0x01;
+        fs::write(&path, &altered)?;
+        let result = store.load(PatientId(1));
+        // Changes to the key id read as a record under another key.
+        if (HEADER_LEN - NONCE_LEN..record.len()).contains(&i) {
+            assert!(
+                matches!(result, Err(StoreError::Tampered(PatientId(1)))),
+                "byte {i}: {result:?}",
+                result = result.map(|_| ())
+            );
+        } else {
+            assert!(result.is_err(), "byte {i}");
+        }
+    }
+
+    let mut truncated = record.clone();
+    truncated.pop();
+    fs::write(&path, &truncated)?;
+    assert!(matches!(
+        store.load(PatientId(1)),
+        Err(StoreError::Tampered(_))
+    ));
+
+    // A valid record copied to another patient's file is rejected too.
+    fs::write(&path, &record)?;
+    fs::copy(&path, store.path(PatientId(2)))?;
+    assert!(matches!(
+        store.load(PatientId(2)),
+        Err(StoreError::Tampered(PatientId(2)))
+    ));
+    assert!(store.load(PatientId(1)).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_key_rotation() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let old_key = Key::from_bytes([1; KEY_LEN]);
+    let new_key = Key::from_bytes([2; KEY_LEN]);
+    let mut store = EncryptedStore::open(dir.path(), old_key.clone())?;
+    let user = sample_user();
+    for id in 0..3 {
+        store.save(PatientId(id), &user)?;
+    }
+
+    // As if an earlier rotation had stopped after the first record.
+    let partial = EncryptedStore::open(dir.path(), new_key.clone())?;
+    partial.save(PatientId(0), &user)?;
+
+    assert_eq!(store.rotate_key(new_key.clone())?, 2);
+    assert_same_user(&store.load(PatientId(2))?, &user);
+    let stale = EncryptedStore::open(dir.path(), old_key)?;
+    assert!(matches!(
+        stale.load(PatientId(1)),
+        Err(StoreError::WrongKey(PatientId(1)))
+    ));
+    assert_eq!(store.rotate_key(new_key)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_keyfile() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::TempDir::new()?;
+    let path = dir.path().join("health.key");
+    let key = Key::generate()?;
+    key.save(&path)?;
+    assert_eq!(Key::load(&path)?, key);
+    // Never silently replaced.
+    assert!(Key::generate()?.save(&path).is_err());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+    }
+
+    let short = dir.path().join("short.key");
+    fs::write(&short, "abcd\n")?;
+    assert!(matches!(
+        Key::load(&short),
+        Err(StoreError::InvalidKeyFile(_))
+    ));
+    assert!(!format!("{key:?}").contains(&hex(&key.0)));
+    Ok(())
+}
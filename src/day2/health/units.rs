@@ -0,0 +1,202 @@
+// Physical quantities with explicit units.
+//
+// Each type stores one canonical unit and converts on the way in and out,
+// so values entered in different units can be compared and subtracted
+// without mix-ups.
+
+use std::fmt;
+use std::ops::Sub;
+
+const CM_PER_INCH: f32 = 2.54;
+const KG_PER_POUND: f32 = 0.453_592_37;
+const KPA_PER_MMHG: f32 = 0.133_322_4;
+
+/// A length, such as a height, or a difference between two lengths.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Length {
+    cm: f32,
+}
+
+impl Length {
+    pub fn cm(cm: f32) -> Length {
+        Length { cm }
+    }
+
+    pub fn m(m: f32) -> Length {
+        Length { cm: m * 100.0 }
+    }
+
+    pub fn inches(inches: f32) -> Length {
+        Length {
+            cm: inches * CM_PER_INCH,
+        }
+    }
+
+    pub fn as_cm(self) -> f32 {
+        self.cm
+    }
+
+    pub fn as_m(self) -> f32 {
+        self.cm / 100.0
+    }
+
+    pub fn as_inches(self) -> f32 {
+        self.cm / CM_PER_INCH
+    }
+}
+
+impl Sub for Length {
+    type Output = Length;
+
+    fn sub(self, other: Length) -> Length {
+        Length::cm(self.cm - other.cm)
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} cm", self.cm)
+    }
+}
+
+/// A body mass, or a difference between two.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Mass {
+    kg: f32,
+}
+
+impl Mass {
+    pub fn kg(kg: f32) -> Mass {
+        Mass { kg }
+    }
+
+    pub fn pounds(pounds: f32) -> Mass {
+        Mass {
+            kg: pounds * KG_PER_POUND,
+        }
+    }
+
+    pub fn as_kg(self) -> f32 {
+        self.kg
+    }
+
+    pub fn as_pounds(self) -> f32 {
+        self.kg / KG_PER_POUND
+    }
+}
+
+impl Sub for Mass {
+    type Output = Mass;
+
+    fn sub(self, other: Mass) -> Mass {
+        Mass::kg(self.kg - other.kg)
+    }
+}
+
+impl fmt::Display for Mass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} kg", self.kg)
+    }
+}
+
+/// A pressure in whole millimetres of mercury, the precision blood
+/// pressure is measured to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Pressure {
+    mmhg: u32,
+}
+
+impl Pressure {
+    pub fn mmhg(mmhg: u32) -> Pressure {
+        Pressure { mmhg }
+    }
+
+    /// Rounded to the nearest mmHg; negative pressures become zero.
+    pub fn kpa(kpa: f32) -> Pressure {
+        Pressure {
+            mmhg: (kpa / KPA_PER_MMHG).round() as u32,
+        }
+    }
+
+    pub fn as_mmhg(self) -> u32 {
+        self.mmhg
+    }
+
+    pub fn as_kpa(self) -> f32 {
+        self.mmhg as f32 * KPA_PER_MMHG
+    }
+}
+
+impl fmt::Display for Pressure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} mmHg", self.mmhg)
+    }
+}
+
+/// A body temperature.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Temperature {
+    celsius: f32,
+}
+
+impl Temperature {
+    pub fn celsius(celsius: f32) -> Temperature {
+        Temperature { celsius }
+    }
+
+    pub fn fahrenheit(fahrenheit: f32) -> Temperature {
+        Temperature {
+            celsius: (fahrenheit - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    pub fn as_celsius(self) -> f32 {
+        self.celsius
+    }
+
+    pub fn as_fahrenheit(self) -> f32 {
+        self.celsius * 9.0 / 5.0 + 32.0
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} °C", self.celsius)
+    }
+}
+
+#[cfg(test)]
+fn assert_close(a: f32, b: f32) {
+    assert!((a - b).abs() < 1e-3, "{a} != {b}");
+}
+
+#[test]
+fn test_length() {
+    assert_close(Length::inches(70.0).as_cm(), 177.8);
+    assert_close(Length::cm(177.8).as_inches(), 70.0);
+    assert_close(Length::m(1.75).as_cm(), 175.0);
+    assert_close((Length::m(1.8) - Length::cm(170.0)).as_cm(), 10.0);
+    assert_eq!(Length::cm(170.0).to_string(), "170.0 cm");
+}
+
+#[test]
+fn test_mass() {
+    assert_close(Mass::pounds(154.0).as_kg(), 69.853);
+    assert_close(Mass::kg(70.0).as_pounds(), 154.324);
+    assert_eq!(Mass::kg(70.04).to_string(), "70.0 kg");
+}
+
+#[test]
+fn test_pressure() {
+    assert_eq!(Pressure::kpa(16.0), Pressure::mmhg(120));
+    assert_close(Pressure::mmhg(120).as_kpa(), 15.999);
+    assert_eq!(Pressure::kpa(-1.0), Pressure::mmhg(0));
+    assert_eq!(Pressure::mmhg(80).to_string(), "80 mmHg");
+}
+
+#[test]
+fn test_temperature() {
+    assert_close(Temperature::fahrenheit(98.6).as_celsius(), 37.0);
+    assert_close(Temperature::celsius(-40.0).as_fahrenheit(), -40.0);
+    assert_eq!(Temperature::celsius(37.0).to_string(), "37.0 °C");
+}
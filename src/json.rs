@@ -0,0 +1,518 @@
+// Minimal JSON value used by the command-line tools and exporters.
+
+use std::fmt;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Build an object from key/value pairs, keeping their order.
+    pub fn object<'a, I>(fields: I) -> Value
+    where
+        I: IntoIterator<Item = (&'a str, Value)>,
+    {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+        )
+    }
+}
+
+impl Value {
+    /// Field `key` of an object, `None` for missing keys and non-objects.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The number as a `u64`, if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= u64::MAX as f64 => {
+                Some(*n as u64)
+            }
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)] // Only the tests read booleans so far.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)] // Only the tests check for null so far.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Number(value.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Value {
+    /// Compact JSON, without any whitespace between tokens.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) if !n.is_finite() => f.write_str("null"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write_escaped(f, s),
+            Value::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_escaped(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Error returned by [`parse`], with the character offset it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// How deeply arrays and objects may nest. The parser recurses once per
+/// level, so input from outside must not choose the depth.
+pub const MAX_DEPTH: usize = 128;
+
+/// Parse a complete JSON document. Trailing non-whitespace is an error, as
+/// is nesting deeper than [`MAX_DEPTH`].
+pub fn parse(input: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars(),
+        offset: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error("trailing characters")),
+    }
+}
+
+struct Parser<'a> {
+    chars: Chars<'a>,
+    offset: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            offset: self.offset,
+            message: message.to_owned(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += 1;
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(_) => Err(self.error(&format!("expected {expected:?}"))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+        {
+            self.next();
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Value::Null),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => self.nested(Self::array),
+            Some('{') => self.nested(Self::object),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, ParseError>,
+    ) -> Result<Value, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.offset;
+        let mut text = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            text.push(c);
+            self.next();
+        }
+        text.parse().map(Value::Number).map_err(|_| ParseError {
+            offset: start,
+            message: format!("invalid number {text:?}"),
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid \\u escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP come as surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(c);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"))
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_display_scalars() {
+    assert_eq!(Value::Null.to_string(), "null");
+    assert_eq!(Value::from(true).to_string(), "true");
+    assert_eq!(Value::from(42u32).to_string(), "42");
+    assert_eq!(Value::from(1.5).to_string(), "1.5");
+    assert_eq!(Value::from(f64::NAN).to_string(), "null");
+}
+
+#[test]
+fn test_display_escapes_strings() {
+    assert_eq!(
+        Value::from("a \"b\"\\\n\u{1}").to_string(),
+        r#""a \"b\"\\\n\u0001""#
+    );
+}
+
+#[test]
+fn test_display_nested() {
+    let value = Value::object([
+        ("number", "4263".into()),
+        ("valid", true.into()),
+        ("tags", vec![Value::from("a"), Value::Null].into()),
+    ]);
+    assert_eq!(
+        value.to_string(),
+        r#"{"number":"4263","valid":true,"tags":["a",null]}"#
+    );
+}
+
+#[test]
+fn test_parse_round_trip() {
+    let text =
+        r#"{"a":[1,-2.5,1e3,true,false,null],"b":{"c":"d\n\"e\" \u00e9 \ud83e\udd80"},"e":[]}"#;
+    let value = parse(text).unwrap();
+    assert_eq!(value.get("a").unwrap().as_array().unwrap().len(), 6);
+    assert_eq!(
+        value
+            .get("b")
+            .and_then(|b| b.get("c"))
+            .and_then(Value::as_str),
+        Some("d\n\"e\" é 🦀")
+    );
+    assert_eq!(parse(&value.to_string()), Ok(value));
+}
+
+#[test]
+fn test_parse_whitespace() {
+    assert_eq!(
+        parse(" { \"a\" : [ 1 , 2 ] }\n"),
+        Ok(Value::object([(
+            "a",
+            vec![Value::from(1u32), Value::from(2u32)].into()
+        )]))
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    for bad in [
+        "",
+        "{",
+        "[1,]",
+        "{\"a\" 1}",
+        "tru",
+        "\"abc",
+        "1 2",
+        "\"\\x\"",
+        "-",
+    ] {
+        assert!(parse(bad).is_err(), "{bad:?}");
+    }
+    assert_eq!(parse("[1,}").unwrap_err().offset, 3);
+}
+
+#[test]
+fn test_parse_depth_limit() {
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(parse(&nested(MAX_DEPTH)).is_ok());
+    let err = parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+    assert_eq!(
+        (err.offset, err.message.as_str()),
+        (MAX_DEPTH, "nesting too deep")
+    );
+    assert!(parse(&"[".repeat(200_000)).is_err());
+    assert!(parse(&"{\"a\":".repeat(200_000)).is_err());
+}
+
+#[test]
+fn test_accessors() {
+    let value = parse(r#"{"n":3,"f":1.5,"s":"x","b":true,"z":null}"#).unwrap();
+    assert_eq!(value.get("n").and_then(Value::as_u64), Some(3));
+    assert_eq!(value.get("f").and_then(Value::as_u64), None);
+    assert_eq!(value.get("f").and_then(Value::as_f64), Some(1.5));
+    assert_eq!(value.get("s").and_then(Value::as_str), Some("x"));
+    assert_eq!(value.get("b").and_then(Value::as_bool), Some(true));
+    assert!(value.get("z").is_some_and(Value::is_null));
+    assert!(value.get("missing").is_none());
+}
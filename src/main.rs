@@ -1,10 +1,18 @@
 mod day1;
 mod day2;
 mod day3;
+mod json;
 
 use day1::luhn;
+use day2::bookstore::server;
 
 #[allow(dead_code)]
 fn main() {
-    luhn::main();
+    // `serve-books` starts the bookstore API; anything else is for the
+    // Luhn tool.
+    if std::env::args().nth(1).as_deref() == Some("serve-books") {
+        server::main(std::env::args().skip(2));
+    } else {
+        luhn::main();
+    }
 }